@@ -1,9 +1,125 @@
 use criterion::{criterion_group, BatchSize, Criterion};
 use rand::prelude::*;
 
+use ray_tracing::bvh::{Bvh, FlatBvh, Split};
+use ray_tracing::material::Material;
+use ray_tracing::object::{self, Object};
 use ray_tracing::vec3::Vec3;
 use ray_tracing::*;
 
+/// Traces paths as the renderer did before Russian roulette, so results stay comparable with
+/// earlier runs.
+const BASELINE: RenderSettings = RenderSettings {
+    max_depth: 50,
+    min_depth: 0,
+    russian_roulette: false,
+};
+
+/// The Cornell box plus a thousand small spheres and a giant fog sphere, after the final scene in
+/// `main.rs`. The wide range of object sizes makes this a good test of BVH construction.
+fn uneven_scene() -> Vec<Box<dyn Object>> {
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(0xDEADBEEF);
+    let mut world = cornell_box_with_boxes();
+
+    let white = Material::Lambertian {
+        albedo: texture::constant(Vec3::from(0.73)),
+    };
+    for _ in 0..1000 {
+        world.push(Box::new(object::Translate {
+            offset: 555. * rng.gen::<Vec3>(),
+            object: object::Sphere {
+                radius: 10.,
+                material: white.clone(),
+            },
+        }));
+    }
+
+    world.push(Box::new(object::ConstantMedium {
+        boundary: object::Sphere {
+            radius: 5000.,
+            material: white.clone(),
+        },
+        density: 0.0001,
+        material: Material::Isotropic {
+            albedo: texture::constant(Vec3::from(1.)),
+        },
+    }));
+
+    world
+}
+
+fn bvh_benchmark(c: &mut Criterion) {
+    let strategies = [("median", Split::Median), ("sah", Split::Sah { bins: 16 })];
+
+    for &(name, split) in &strategies {
+        eprintln!(
+            "bvh/{}: expected traversal cost {:.2}",
+            name,
+            Bvh::with_split(uneven_scene(), 0. ..1., split).sah_cost()
+        );
+
+        c.bench_function(&format!("bvh/build/{}", name), |b| {
+            b.iter_batched(
+                uneven_scene,
+                |world| Bvh::with_split(world, 0. ..1., split),
+                BatchSize::SmallInput,
+            );
+        });
+
+        c.bench_function(&format!("bvh/seq/{}/10x10x4", name), |b| {
+            const NX: usize = 10;
+            const NY: usize = 10;
+            const NS: usize = 4;
+
+            let world = Bvh::with_split(uneven_scene(), 0. ..1., split);
+
+            let camera = camera::Camera::look(
+                Vec3(278., 278., -800.),
+                Vec3(278., 278., 0.),
+                Vec3(0., 1., 0.),
+                40.,
+                NX as f64 / NY as f64,
+                0.,
+                10.,
+                0. ..1.,
+            );
+
+            let mut rng = rand::rngs::SmallRng::seed_from_u64(0xDEADBEEF);
+            b.iter_batched(
+                || (),
+                |_| cast(NX, NY, NS, &BASELINE, &camera, &world, &mut rng),
+                BatchSize::SmallInput,
+            );
+        });
+
+        c.bench_function(&format!("bvh/seq/{}-flat/10x10x4", name), |b| {
+            const NX: usize = 10;
+            const NY: usize = 10;
+            const NS: usize = 4;
+
+            let world = FlatBvh::new(uneven_scene(), 0. ..1., split);
+
+            let camera = camera::Camera::look(
+                Vec3(278., 278., -800.),
+                Vec3(278., 278., 0.),
+                Vec3(0., 1., 0.),
+                40.,
+                NX as f64 / NY as f64,
+                0.,
+                10.,
+                0. ..1.,
+            );
+
+            let mut rng = rand::rngs::SmallRng::seed_from_u64(0xDEADBEEF);
+            b.iter_batched(
+                || (),
+                |_| cast(NX, NY, NS, &BASELINE, &camera, &world, &mut rng),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("scene/seq/10x10x4", |b| {
         const NX: usize = 10;
@@ -32,7 +148,7 @@ fn criterion_benchmark(c: &mut Criterion) {
         let mut rng = rand::rngs::SmallRng::seed_from_u64(0xDEADBEEF);
         b.iter_batched(
             || (),
-            |_| cast(NX, NY, NS, &camera, &world, &mut rng),
+            |_| cast(NX, NY, NS, &BASELINE, &camera, &world, &mut rng),
             BatchSize::SmallInput,
         );
     });
@@ -62,11 +178,11 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         b.iter_batched(
             || (),
-            |_| par_cast(NX, NY, NS, &camera, &world),
+            |_| par_cast(NX, NY, NS, &BASELINE, &camera, &world),
             BatchSize::SmallInput,
         );
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+criterion_group!(benches, criterion_benchmark, bvh_benchmark);
 criterion::criterion_main!(benches);
@@ -15,10 +15,58 @@ impl Aabb {
         }
     }
 
+    /// Whether the box is finite along every axis. Unbounded objects, such as infinite planes,
+    /// have boxes that aren't.
+    pub fn is_finite(&self) -> bool {
+        self.min.reduce(f64::min).is_finite() && self.max.reduce(f64::max).is_finite()
+    }
+
+    /// Pads the box out along any axis where it has (nearly) zero thickness, such as for a flat
+    /// object lying in an axis-aligned plane. `hit` never reports a hit on such boxes.
+    pub fn pad_flat(self) -> Self {
+        // The same fudge factor as `Rect`.
+        const PAD: f64 = 0.0001;
+
+        let (min, max) = (self.min, self.max);
+        Aabb {
+            min: min.zip_with(max, |lo, hi| if hi - lo < PAD { lo - PAD } else { lo }),
+            max: max.zip_with(min, |hi, lo| if hi - lo < PAD { hi + PAD } else { hi }),
+        }
+    }
+
+    /// Total area of the box's six faces.
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2. * (d.0 * d.1 + d.1 * d.2 + d.2 * d.0)
+    }
+
     pub fn hit(&self, ray: &Ray, t_range: std::ops::Range<f64>) -> bool {
-        let inv_d = ray.direction.map(|x| 1. / x);
-        let t0 = (self.min - ray.origin) * inv_d;
-        let t1 = (self.max - ray.origin) * inv_d;
+        self.hit_inv(ray.origin, ray.direction.map(|x| 1. / x), t_range)
+    }
+
+    /// Equivalent to `hit`, for a ray from `origin` whose direction has the elementwise reciprocal
+    /// `inv_d`. This saves recomputing the reciprocal when testing one ray against many boxes.
+    #[inline]
+    pub fn hit_inv(&self, origin: Vec3, inv_d: Vec3, t_range: std::ops::Range<f64>) -> bool {
+        let clipped = self.clip_inv(origin, inv_d, t_range);
+        clipped.end > clipped.start
+    }
+
+    /// Narrows `t_range` to the part where `ray` is inside the box. The result is empty if the ray
+    /// misses the box.
+    pub fn clip(&self, ray: &Ray, t_range: std::ops::Range<f64>) -> std::ops::Range<f64> {
+        self.clip_inv(ray.origin, ray.direction.map(|x| 1. / x), t_range)
+    }
+
+    #[inline]
+    fn clip_inv(
+        &self,
+        origin: Vec3,
+        inv_d: Vec3,
+        t_range: std::ops::Range<f64>,
+    ) -> std::ops::Range<f64> {
+        let t0 = (self.min - origin) * inv_d;
+        let t1 = (self.max - origin) * inv_d;
 
         let (t0, t1) = (
             inv_d.zip_with3(t0, t1, |i, a, b| if i < 0. { b } else { a }),
@@ -28,7 +76,7 @@ impl Aabb {
         let start = t_range.start.max(t0.reduce(f64::max));
         let end = t_range.end.min(t1.reduce(f64::min));
 
-        end > start
+        start..end
     }
 
     pub fn corners<'s>(&'s self) -> impl Iterator<Item = Vec3> + 's {
@@ -1,9 +1,14 @@
 use std::ops::Range;
 
+use rayon::prelude::*;
+
 use crate::aabb::Aabb;
 use crate::object::{HitRecord, Object};
 use crate::ray::Ray;
-use crate::vec3::Axis::{self, *};
+use crate::vec3::{
+    Axis::{self, *},
+    Vec3,
+};
 
 #[derive(Debug)]
 pub struct Bvh {
@@ -14,73 +19,292 @@ pub struct Bvh {
 
 #[derive(Debug)]
 pub enum BvhContents {
-    Node { left: Box<Bvh>, right: Box<Bvh> },
+    /// An interior node, whose children were divided along `axis`, with `left` the lower.
+    /// (The nodes that hang unbounded objects off the top aren't divided along any axis, and
+    /// record X.)
+    Node {
+        left: Box<Bvh>,
+        right: Box<Bvh>,
+        axis: Axis,
+    },
     Leaf(Box<dyn Object>),
 }
 
+/// Strategy for dividing a set of objects between the two children of a `Bvh` node.
+#[derive(Debug, Clone, Copy)]
+pub enum Split {
+    /// Divide at the median centroid along the axis where the objects are most spread out. This
+    /// is cheap, but produces poor trees when object sizes vary widely.
+    Median,
+    /// Divide wherever minimizes the expected traversal cost predicted by the [surface area
+    /// heuristic][sah], considering `bins` evenly spaced candidate positions along each axis.
+    ///
+    /// [sah]: https://pbr-book.org/3ed-2018/Primitives_and_Intersection_Acceleration/Bounding_Volume_Hierarchies#TheSurfaceAreaHeuristic
+    Sah { bins: usize },
+}
+
+/// Relative cost of testing a ray against a node's bounding box, for the surface area heuristic.
+const TRAVERSAL_COST: f64 = 0.125;
+/// Relative cost of testing a ray against an object, for the surface area heuristic.
+const INTERSECTION_COST: f64 = 1.;
+
+/// Subtrees with fewer than this many objects are built on the current thread; larger ones build
+/// their two children in parallel.
+const PARALLEL_THRESHOLD: usize = 256;
+
+/// An object awaiting placement in a `Bvh`, with its bounding box computed up front.
+struct BuildItem {
+    bounding_box: Aabb,
+    object: Box<dyn Object>,
+}
+
+impl BuildItem {
+    /// Twice the center of the item's bounding box along `axis`. (Doubling is equivalent for
+    /// comparisons and cheaper than halving.)
+    #[inline]
+    fn centroid2(&self, axis: Axis) -> f64 {
+        self.bounding_box.min[axis] + self.bounding_box.max[axis]
+    }
+}
+
 impl Bvh {
-    pub fn new(mut objs: Vec<Box<dyn Object>>, exposure: Range<f64>) -> Self {
-        // Note: though this BVH implementation is largely derived from Peter Shirley's, it does
-        // *not* use the random axis selection and sort routine, because it tended to fall into
-        // pathological cases.
-
-        fn axis_range(objs: &[Box<dyn Object>], exposure: Range<f64>, axis: Axis) -> f64 {
-            let range = objs.iter().fold(f64::MAX..f64::MIN, |range, o| {
-                let bb = o.bounding_box(exposure.clone());
-                let min = bb.min[axis].min(bb.max[axis]);
-                let max = bb.min[axis].max(bb.max[axis]);
-                range.start.min(min)..range.end.max(max)
-            });
-            range.end - range.start
-        }
-
-        // Find the axis that has the greatest range for this set of objects.
-        let axis = {
-            let mut ranges = [
-                (X, axis_range(&objs, exposure.clone(), X)),
-                (Y, axis_range(&objs, exposure.clone(), Y)),
-                (Z, axis_range(&objs, exposure.clone(), Z)),
-            ];
-            // Note reversed comparison function, to sort descending:
-            ranges.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
-            ranges[0].0
+    /// Builds a hierarchy over `objs` by splitting at median centroids. See `Split::Median`.
+    pub fn new(objs: Vec<Box<dyn Object>>, exposure: Range<f64>) -> Self {
+        Bvh::with_split(objs, exposure, Split::Median)
+    }
+
+    /// Builds a hierarchy over `objs`, dividing them at each level according to `split`.
+    ///
+    /// Each object's bounding box is computed exactly once, and large subtrees are built in
+    /// parallel.
+    pub fn with_split(objs: Vec<Box<dyn Object>>, exposure: Range<f64>, split: Split) -> Self {
+        if objs.is_empty() {
+            panic!("Can't create a BVH from zero objects.");
+        }
+        if let Split::Sah { bins } = split {
+            assert!(bins >= 2, "SAH splitting needs at least 2 bins.");
+        }
+
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = objs
+            .into_par_iter()
+            .map(|object| BuildItem {
+                bounding_box: object.bounding_box(exposure.clone()),
+                object,
+            })
+            .partition(|item| item.bounding_box.is_finite());
+
+        // Unbounded objects, such as infinite planes, would make a mess of the splitting
+        // heuristics, and every ray has to be tested against them anyway. So build the hierarchy
+        // without them, and then hang them off the top.
+        let mut unbounded = unbounded
+            .into_iter()
+            .map(|item| Bvh::build(vec![item], split));
+        let mut bvh = if bounded.is_empty() {
+            unbounded.next().unwrap()
+        } else {
+            Bvh::build(bounded, split)
         };
+        for leaf in unbounded {
+            bvh = Bvh {
+                bounding_box: bvh.bounding_box.merge(leaf.bounding_box),
+                size: bvh.size + leaf.size,
+                contents: BvhContents::Node {
+                    left: Box::new(bvh),
+                    right: Box::new(leaf),
+                    axis: X,
+                },
+            };
+        }
+        bvh
+    }
 
-        // Sort objects along it by centroid. (Actually, by centroid*2. This is equivalent and
-        // cheaper.)
-        objs.sort_unstable_by(|a, b| {
-            let abb = a.bounding_box(exposure.clone());
-            let bbb = b.bounding_box(exposure.clone());
-            let av = abb.min[axis] + abb.max[axis];
-            let bv = bbb.min[axis] + bbb.max[axis];
-            av.partial_cmp(&bv).unwrap()
-        });
-
-        match objs.len() {
-            0 => panic!("Can't create a BVH from zero objects."),
-            1 => Bvh {
-                bounding_box: objs[0].bounding_box(exposure.clone()),
+    fn build(mut items: Vec<BuildItem>, split: Split) -> Self {
+        if items.len() == 1 {
+            let item = items.pop().unwrap();
+            return Bvh {
+                bounding_box: item.bounding_box,
                 size: 1,
-                contents: BvhContents::Leaf(objs.pop().unwrap()),
+                contents: BvhContents::Leaf(item.object),
+            };
+        }
+
+        let (axis, right) = match split {
+            Split::Median => split_median(&mut items),
+            Split::Sah { bins } => split_sah(&mut items, bins),
+        };
+        let (left, right) = if items.len() + right.len() >= PARALLEL_THRESHOLD {
+            rayon::join(|| Bvh::build(items, split), || Bvh::build(right, split))
+        } else {
+            (Bvh::build(items, split), Bvh::build(right, split))
+        };
+
+        Bvh {
+            bounding_box: left.bounding_box.merge(right.bounding_box),
+            size: left.size + right.size,
+            contents: BvhContents::Node {
+                left: Box::new(left),
+                right: Box::new(right),
+                axis,
             },
-            _ => {
-                // Divide space at the median point of the selected axis.
-                let right = Box::new(Bvh::new(
-                    objs.drain(objs.len() / 2..).collect(),
-                    exposure.clone(),
-                ));
-                let left = Box::new(Bvh::new(objs, exposure.clone()));
-
-                Bvh {
-                    bounding_box: left.bounding_box.merge(right.bounding_box),
-                    size: left.size + right.size,
-                    contents: BvhContents::Node { left, right },
-                }
+        }
+    }
+
+    /// The objects in the hierarchy that give off light (see `Object::is_light`).
+    pub fn lights(&self) -> Vec<&dyn Object> {
+        match &self.contents {
+            BvhContents::Node { left, right, .. } => {
+                let mut lights = left.lights();
+                lights.extend(right.lights());
+                lights
+            }
+            BvhContents::Leaf(obj) if obj.is_light() => vec![obj.as_ref()],
+            BvhContents::Leaf(_) => vec![],
+        }
+    }
+
+    /// Estimates the cost of finding the nearest hit of a ray with this hierarchy, using the
+    /// surface area heuristic. Lower is better.
+    ///
+    /// The cost is in units of ray-object intersection tests, assuming the ray is random and hits
+    /// the hierarchy's bounding box.
+    pub fn sah_cost(&self) -> f64 {
+        match &self.contents {
+            BvhContents::Leaf(_) => INTERSECTION_COST,
+            BvhContents::Node { left, right, .. } if !self.bounding_box.is_finite() => {
+                // A node holding unbounded objects is hit by every ray, and so are its children.
+                TRAVERSAL_COST + left.sah_cost() + right.sah_cost()
+            }
+            BvhContents::Node { left, right, .. } => {
+                // The chance of a ray hitting a child, given that it hits the parent, is
+                // proportional to the child's surface area.
+                let area = self.bounding_box.surface_area();
+                TRAVERSAL_COST
+                    + (left.bounding_box.surface_area() * left.sah_cost()
+                        + right.bounding_box.surface_area() * right.sah_cost())
+                        / area
             }
         }
     }
 }
 
+/// Divides `items` at the median centroid of its widest axis. The upper half is removed from
+/// `items` and returned, along with the axis.
+fn split_median(items: &mut Vec<BuildItem>) -> (Axis, Vec<BuildItem>) {
+    // Note: though this BVH implementation is largely derived from Peter Shirley's, it does
+    // *not* use the random axis selection and sort routine, because it tended to fall into
+    // pathological cases.
+
+    // Find the axis that has the greatest range for this set of objects.
+    let bounds = items[1..].iter().fold(items[0].bounding_box, |bb, item| {
+        bb.merge(item.bounding_box)
+    });
+    let extent = bounds.max - bounds.min;
+    let axis = if extent[X] >= extent[Y] && extent[X] >= extent[Z] {
+        X
+    } else if extent[Y] >= extent[Z] {
+        Y
+    } else {
+        Z
+    };
+
+    // Partition objects along it by centroid, so that the lower half comes first. This doesn't
+    // need a full sort.
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |a, b| {
+        a.centroid2(axis).partial_cmp(&b.centroid2(axis)).unwrap()
+    });
+
+    // Divide space at the median point of the selected axis.
+    (axis, items.split_off(mid))
+}
+
+/// Divides `items` by binning their centroids along each axis and choosing the boundary between
+/// bins with the lowest surface area heuristic cost. Items above the boundary are removed from
+/// `items` and returned, along with the axis they were divided along.
+///
+/// Falls back to `split_median` if the centroids can't be separated.
+fn split_sah(items: &mut Vec<BuildItem>, bins: usize) -> (Axis, Vec<BuildItem>) {
+    fn merge(a: Option<Aabb>, b: Aabb) -> Option<Aabb> {
+        Some(a.map_or(b, |a| a.merge(b)))
+    }
+    fn area(bb: Option<Aabb>) -> f64 {
+        bb.map_or(0., |bb| bb.surface_area())
+    }
+
+    let bounds = items[1..].iter().fold(items[0].bounding_box, |bb, item| {
+        bb.merge(item.bounding_box)
+    });
+    let (cmin, cmax) = items.iter().fold(
+        (Vec3::from(f64::MAX), Vec3::from(f64::MIN)),
+        |(min, max), item| {
+            let c = Vec3(item.centroid2(X), item.centroid2(Y), item.centroid2(Z));
+            (min.zip_with(c, f64::min), max.zip_with(c, f64::max))
+        },
+    );
+    let bin_of = |item: &BuildItem, axis: Axis| {
+        let offset = (item.centroid2(axis) - cmin[axis]) / (cmax[axis] - cmin[axis]);
+        ((offset * bins as f64) as usize).min(bins - 1)
+    };
+
+    // Lowest cost found so far, along with its axis and the last bin on the left side.
+    let mut best: Option<(f64, Axis, usize)> = None;
+
+    for axis in [X, Y, Z] {
+        if cmax[axis] - cmin[axis] <= 0. {
+            continue;
+        }
+
+        let mut bin_bounds = vec![None; bins];
+        let mut bin_counts = vec![0usize; bins];
+        for item in items.iter() {
+            let b = bin_of(item, axis);
+            bin_bounds[b] = merge(bin_bounds[b], item.bounding_box);
+            bin_counts[b] += 1;
+        }
+
+        // Sweep from the right, recording the cost term for everything right of each boundary...
+        let mut right_terms = vec![0.; bins];
+        let (mut right_bounds, mut right_count) = (None, 0);
+        for b in (1..bins).rev() {
+            if let Some(bb) = bin_bounds[b] {
+                right_bounds = merge(right_bounds, bb);
+            }
+            right_count += bin_counts[b];
+            right_terms[b] = area(right_bounds) * right_count as f64;
+        }
+
+        // ...then sweep from the left, combining it with the cost term for the left side.
+        let (mut left_bounds, mut left_count) = (None, 0);
+        for b in 0..bins - 1 {
+            if let Some(bb) = bin_bounds[b] {
+                left_bounds = merge(left_bounds, bb);
+            }
+            left_count += bin_counts[b];
+            if left_count == 0 || left_count == items.len() {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST * (area(left_bounds) * left_count as f64 + right_terms[b + 1])
+                    / bounds.surface_area();
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, b));
+            }
+        }
+    }
+
+    let (axis, last_left_bin) = match best {
+        Some((_, axis, b)) => (axis, b),
+        None => return split_median(items),
+    };
+
+    let (left, right) = items
+        .drain(..)
+        .partition(|item| bin_of(item, axis) <= last_left_bin);
+    *items = left;
+    (axis, right)
+}
+
 impl Object for Bvh {
     fn hit<'o>(
         &'o self,
@@ -90,7 +314,7 @@ impl Object for Bvh {
     ) -> Option<HitRecord<'o>> {
         if self.bounding_box.hit(ray, t_range.clone()) {
             match &self.contents {
-                BvhContents::Node { left, right } => {
+                BvhContents::Node { left, right, .. } => {
                     let hit_left = left.hit(ray, t_range.clone(), rng);
 
                     // Don't bother searching past the left hit in the right space.
@@ -122,6 +346,174 @@ impl Object for Bvh {
     }
 }
 
+/// A `Bvh` flattened into an array, for faster traversal.
+///
+/// Nodes are stored in depth-first order, so each interior node is followed immediately by its
+/// first child, and records the index of its second child and the axis along which the children
+/// were divided. The first child is always the one lower along that axis, which lets traversal
+/// visit whichever child is nearer to the ray's origin first, and so shrink the search range
+/// sooner.
+#[derive(Debug)]
+pub struct FlatBvh {
+    nodes: Vec<FlatNode>,
+    objects: Vec<Box<dyn Object>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FlatNode {
+    bounding_box: Aabb,
+    contents: FlatContents,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FlatContents {
+    Node { second: u32, axis: Axis },
+    Leaf(u32),
+}
+
+impl FlatBvh {
+    /// Builds a hierarchy over `objs`, dividing them according to `split`, and flattens it.
+    pub fn new(objs: Vec<Box<dyn Object>>, exposure: Range<f64>, split: Split) -> Self {
+        FlatBvh::from(Bvh::with_split(objs, exposure, split))
+    }
+
+    /// The objects in the hierarchy that give off light (see `Object::is_light`).
+    pub fn lights(&self) -> Vec<&dyn Object> {
+        self.objects
+            .iter()
+            .filter(|obj| obj.is_light())
+            .map(|obj| obj.as_ref())
+            .collect()
+    }
+}
+
+impl From<Bvh> for FlatBvh {
+    fn from(bvh: Bvh) -> Self {
+        fn flatten(bvh: Bvh, nodes: &mut Vec<FlatNode>, objects: &mut Vec<Box<dyn Object>>) {
+            let index = nodes.len();
+            match bvh.contents {
+                BvhContents::Leaf(obj) => {
+                    nodes.push(FlatNode {
+                        bounding_box: bvh.bounding_box,
+                        contents: FlatContents::Leaf(objects.len() as u32),
+                    });
+                    objects.push(obj);
+                }
+                BvhContents::Node { left, right, axis } => {
+                    // The left child is always the lower one along the axis.
+                    nodes.push(FlatNode {
+                        bounding_box: bvh.bounding_box,
+                        contents: FlatContents::Node { second: 0, axis },
+                    });
+                    flatten(*left, nodes, objects);
+                    let second_index = nodes.len() as u32;
+                    flatten(*right, nodes, objects);
+                    nodes[index].contents = FlatContents::Node {
+                        second: second_index,
+                        axis,
+                    };
+                }
+            }
+        }
+
+        let mut nodes = Vec::with_capacity(2 * bvh.size - 1);
+        let mut objects = Vec::with_capacity(bvh.size);
+        flatten(bvh, &mut nodes, &mut objects);
+        FlatBvh { nodes, objects }
+    }
+}
+
+impl Object for FlatBvh {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let inv_d = ray.direction.map(|x| 1. / x);
+        let mut stack = TraversalStack::new();
+        stack.push(0);
+
+        let mut nearest = None;
+        let mut t_end = t_range.end;
+
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index as usize];
+            if !node
+                .bounding_box
+                .hit_inv(ray.origin, inv_d, t_range.start..t_end)
+            {
+                continue;
+            }
+
+            match node.contents {
+                FlatContents::Node { second, axis } => {
+                    // Push the far child first, so that the near child is visited first.
+                    if ray.direction[axis] < 0. {
+                        stack.push(index + 1);
+                        stack.push(second);
+                    } else {
+                        stack.push(second);
+                        stack.push(index + 1);
+                    }
+                }
+                FlatContents::Leaf(i) => {
+                    if let Some(hit) = self.objects[i as usize].hit(ray, t_range.start..t_end, rng)
+                    {
+                        t_end = hit.t;
+                        nearest = Some(hit);
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        self.nodes[0].bounding_box
+    }
+}
+
+/// Stack of node indices for traversing a `FlatBvh`.
+///
+/// Balanced hierarchies never need more than a few dozen entries, which are kept on the program
+/// stack. Badly unbalanced ones (which the SAH builder can produce) spill onto the heap.
+struct TraversalStack {
+    inline: [u32; 64],
+    len: usize,
+    spill: Vec<u32>,
+}
+
+impl TraversalStack {
+    #[inline]
+    fn new() -> Self {
+        TraversalStack {
+            inline: [0; 64],
+            len: 0,
+            spill: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, index: u32) {
+        if self.len < self.inline.len() {
+            self.inline[self.len] = index;
+            self.len += 1;
+        } else {
+            self.spill.push(index);
+        }
+    }
+
+    #[inline]
+    fn pop(&mut self) -> Option<u32> {
+        self.spill.pop().or_else(|| {
+            self.len = self.len.checked_sub(1)?;
+            Some(self.inline[self.len])
+        })
+    }
+}
+
 // TODO: this no longer has much value
 pub fn from_scene(scene: Vec<Box<dyn Object>>, exposure: Range<f64>) -> Bvh {
     Bvh::new(scene, exposure)
@@ -1,6 +1,6 @@
 use rand::prelude::*;
 
-use crate::ray::Ray;
+use crate::ray::{Differentials, Ray};
 use crate::vec3::{Axis::*, Vec3};
 
 #[derive(Debug)]
@@ -16,6 +16,7 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn look(
         look_from: Vec3,
         look_at: Vec3,
@@ -51,6 +52,11 @@ impl Camera {
         }
     }
 
+    /// Generates a ray through the point `(s, t)` of the image, each running from 0 to 1 across
+    /// it, from a random point on the lens at a random time during the exposure.
+    ///
+    /// The ray's differentials are with respect to `s` and `t`, and so span the whole image; use
+    /// `Ray::scale_differentials` to narrow them down to a pixel.
     pub fn get_ray(&self, s: f64, t: f64, rng: &mut impl Rng) -> Ray {
         let rd = self.lens_radius * Vec3::in_unit_disc(rng);
         let offset = rd[X] * self.u + rd[Y] * self.v;
@@ -61,6 +67,12 @@ impl Camera {
                 - self.origin
                 - offset,
             time,
+            differentials: Some(Differentials {
+                dx_origin: Vec3::default(),
+                dx_direction: self.horizontal,
+                dy_origin: Vec3::default(),
+                dy_direction: self.vertical,
+            }),
         }
     }
 }
@@ -0,0 +1,257 @@
+//! Constructive solid geometry: shapes built by combining the volumes of other shapes.
+//!
+//! Where `object::And` simply reports whichever of two objects a ray hits first, the operators
+//! here treat their operands as solids and combine the space they enclose. A lens is the
+//! intersection of two spheres; a die is a cube with spherical pips taken out of it.
+//!
+//! The operands must be closed, with normals pointing outward, so that each surface crossing can
+//! be classified as entering or leaving the solid. The combined shape keeps that property, so
+//! materials such as `Material::Dielectric` see consistent normals, `ConstantMedium` can use it as
+//! a boundary, and CSG objects can themselves be combined further.
+
+use std::ops::Range;
+
+use crate::aabb::Aabb;
+use crate::object::{HitRecord, Object};
+use crate::ray::Ray;
+
+/// Distance stepped past a surface before searching for the next one along the same ray, so that
+/// the surface just found isn't found again.
+const EPSILON: f64 = 0.0001;
+
+/// The ways of combining two solids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Space inside either solid.
+    Union,
+    /// Space inside both solids.
+    Intersection,
+    /// Space inside the first solid but not the second.
+    Difference,
+}
+
+impl Op {
+    /// Whether a point is inside the combined solid, given whether it's inside each operand.
+    fn contains(self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Op::Union => in_a || in_b,
+            Op::Intersection => in_a && in_b,
+            Op::Difference => in_a && !in_b,
+        }
+    }
+}
+
+/// The solid formed by combining the solids `A` and `B` with an `Op`.
+///
+/// Use the `union`, `intersection` and `difference` functions to obtain one of these.
+#[derive(Debug, Clone)]
+pub struct Csg<A, B> {
+    pub op: Op,
+    pub a: A,
+    pub b: B,
+}
+
+/// Returns the solid occupying the space inside either `a` or `b`.
+pub fn union<A: Object, B: Object>(a: A, b: B) -> Csg<A, B> {
+    Csg {
+        op: Op::Union,
+        a,
+        b,
+    }
+}
+
+/// Returns the solid occupying the space inside both `a` and `b`.
+pub fn intersection<A: Object, B: Object>(a: A, b: B) -> Csg<A, B> {
+    Csg {
+        op: Op::Intersection,
+        a,
+        b,
+    }
+}
+
+/// Returns the solid occupying the space inside `a` that is not inside `b`. Surfaces of `b` that
+/// bound the result are rendered in `b`'s materials.
+pub fn difference<A: Object, B: Object>(a: A, b: B) -> Csg<A, B> {
+    Csg {
+        op: Op::Difference,
+        a,
+        b,
+    }
+}
+
+/// Whether `hit` is a surface crossing on the way out of a solid, rather than into it.
+fn leaving(ray: &Ray, hit: &HitRecord) -> bool {
+    ray.direction.dot(hit.normal) > 0.
+}
+
+impl<A: Object, B: Object> Object for Csg<A, B> {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        // Walk along the ray through the surfaces of both operands, in order, keeping track of
+        // whether we're inside each. The first surface at which that changes whether we're inside
+        // the result is the one we hit.
+        let mut next_a = self.a.hit(ray, t_range.clone(), rng);
+        let mut next_b = self.b.hit(ray, t_range.clone(), rng);
+        // Before the first surface of an operand, we're inside it iff that surface leads out.
+        let mut in_a = next_a.as_ref().is_some_and(|h| leaving(ray, h));
+        let mut in_b = next_b.as_ref().is_some_and(|h| leaving(ray, h));
+
+        loop {
+            let inside = self.op.contains(in_a, in_b);
+
+            let from_a = match (&next_a, &next_b) {
+                (None, None) => return None,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(a), Some(b)) => a.t <= b.t,
+            };
+
+            if from_a {
+                let hit = next_a.take().unwrap();
+                in_a = !leaving(ray, &hit);
+                if self.op.contains(in_a, in_b) != inside {
+                    return Some(hit);
+                }
+                next_a = self.a.hit(ray, hit.t + EPSILON..t_range.end, rng);
+            } else {
+                let hit = next_b.take().unwrap();
+                in_b = !leaving(ray, &hit);
+                if self.op.contains(in_a, in_b) != inside {
+                    return Some(if self.op == Op::Difference {
+                        // The result is outside `b`, so its surface faces into `b`.
+                        HitRecord {
+                            normal: -hit.normal,
+                            ..hit
+                        }
+                    } else {
+                        hit
+                    });
+                }
+                next_b = self.b.hit(ray, hit.t + EPSILON..t_range.end, rng);
+            }
+        }
+    }
+
+    fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
+        let a = self.a.bounding_box(exposure.clone());
+        match self.op {
+            Op::Union => a.merge(self.b.bounding_box(exposure)),
+            Op::Intersection => {
+                let b = self.b.bounding_box(exposure);
+                let min = a.min.zip_with(b.min, f64::max);
+                // If the boxes don't overlap, the result is empty; collapse it to a point.
+                let max = a.max.zip_with(b.max, f64::min).zip_with(min, f64::max);
+                Aabb { min, max }
+            }
+            Op::Difference => a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+    use crate::object::{Sphere, Translate};
+    use crate::texture;
+    use crate::vec3::Vec3;
+
+    fn ball(x: f64, radius: f64) -> Translate<Sphere> {
+        Translate {
+            offset: Vec3(x, 0., 0.),
+            object: Sphere {
+                radius,
+                material: Material::Lambertian {
+                    albedo: texture::constant(Vec3::from(0.5)),
+                },
+            },
+        }
+    }
+
+    /// Where a ray from (`from`, 0, 0), along the X axis in the direction of `sign`, first meets
+    /// `object`: the X coordinate of the hit and of its normal.
+    fn along_x(object: &impl Object, from: f64, sign: f64) -> Option<(f64, f64)> {
+        let ray = Ray {
+            origin: Vec3(from, 0., 0.),
+            direction: Vec3(sign, 0., 0.),
+            time: 0.,
+            differentials: None,
+        };
+        object
+            .hit(&ray, 0.001..f64::MAX, &mut || 0.5)
+            .map(|hit| (hit.p.0, hit.normal.0))
+    }
+
+    fn assert_hit(hit: Option<(f64, f64)>, x: f64, normal: f64) {
+        match hit {
+            Some((hx, hn)) => assert!(
+                (hx - x).abs() < 1e-9 && (hn - normal).abs() < 1e-9,
+                "hit at {} with normal {}, expected {} and {}",
+                hx,
+                hn,
+                x,
+                normal
+            ),
+            None => panic!("missed, expected a hit at {}", x),
+        }
+    }
+
+    #[test]
+    fn hollow_shell() {
+        // A hollow shell, from radius 1 to 2.
+        let shell = difference(ball(0., 2.), ball(0., 1.));
+        // From outside, the outer surface.
+        assert_hit(along_x(&shell, -5., 1.), -2., -1.);
+        // From inside the shell's wall, the inner surface, facing out of the wall into the hole.
+        assert_hit(along_x(&shell, -1.5, 1.), -1., 1.);
+        // From inside the hole, the far side of the hole.
+        assert_hit(along_x(&shell, 0., 1.), 1., -1.);
+        // From inside the far wall, its outer surface.
+        assert_hit(along_x(&shell, 1.5, 1.), 2., 1.);
+    }
+
+    #[test]
+    fn ray_starting_inside_both() {
+        // A sphere with a bite taken out of its right side.
+        let bitten = difference(ball(0., 1.), ball(1., 1.));
+        // Starting in the bite, which is inside both spheres, the first surface is where the bite
+        // ends, facing into it.
+        assert_hit(along_x(&bitten, 0.5, -1.), 0., 1.);
+        assert_eq!(along_x(&bitten, 0.5, 1.), None);
+    }
+
+    #[test]
+    fn lens() {
+        let lens = intersection(ball(-0.5, 1.), ball(0.5, 1.));
+        assert_hit(along_x(&lens, -5., 1.), -0.5, -1.);
+        assert_hit(along_x(&lens, 5., -1.), 0.5, 1.);
+        assert_hit(along_x(&lens, 0., 1.), 0.5, 1.);
+
+        let nothing = intersection(ball(-2., 1.), ball(2., 1.));
+        assert_eq!(along_x(&nothing, -5., 1.), None);
+        let b = nothing.bounding_box(0. ..1.);
+        assert!(b.min.0 >= b.max.0);
+    }
+
+    #[test]
+    fn overlapping_union() {
+        let pair = union(ball(-0.5, 1.), ball(0.5, 1.));
+        assert_hit(along_x(&pair, -5., 1.), -1.5, -1.);
+        // The surfaces inside the other ball don't count.
+        assert_hit(along_x(&pair, 0., 1.), 1.5, 1.);
+        assert_hit(along_x(&pair, 0., -1.), -1.5, -1.);
+    }
+
+    #[test]
+    fn nested() {
+        // A shell with a slot cut through it, combined again.
+        let slotted = difference(difference(ball(0., 2.), ball(0., 1.)), ball(1.5, 0.75));
+        // The slot removes the near wall, so the first surface is the inside of the far one.
+        assert_hit(along_x(&slotted, 5., -1.), -1., 1.);
+        assert_hit(along_x(&slotted, -5., 1.), -2., -1.);
+    }
+}
@@ -0,0 +1,440 @@
+//! Light arriving from infinitely far away, such as from the sky, seen wherever rays escape the
+//! scene.
+//!
+//! An environment lights a scene without any objects having to surround it. Environment maps,
+//! which light the scene with a photograph of the surroundings, are sampled in proportion to
+//! their brightness, so that the sun or a bright window in the photograph is found quickly.
+//!
+//! ```no_run
+//! use ray_tracing::environment::{Environment, EnvironmentMap};
+//!
+//! // A map turned by 90 degrees, at half its recorded brightness.
+//! let map = EnvironmentMap::load("studio.hdr", 90., 0.5).unwrap();
+//! let environment = Environment::Map(map);
+//! ```
+
+use std::f64::consts::PI;
+use std::path::Path;
+
+use crate::object::{orthonormal_basis, sphere_uv};
+use crate::raster::{self, Raster, RasterError};
+use crate::vec3::{Axis::*, Vec3};
+
+/// The light arriving from far away in each direction.
+#[derive(Debug, Clone)]
+pub enum Environment {
+    /// The same light from every direction.
+    Constant(Vec3),
+    /// A sky that blends evenly from `bottom`, straight down, to `top`, straight up.
+    Gradient { bottom: Vec3, top: Vec3 },
+    /// An image covering every direction.
+    Map(EnvironmentMap),
+    /// A clear daytime sky and the sun.
+    Sky(Sky),
+}
+
+impl Environment {
+    /// The light arriving from `direction`, which need not be a unit vector.
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        match self {
+            Environment::Constant(color) => *color,
+            Environment::Gradient { bottom, top } => {
+                let t = 0.5 * (direction.into_unit()[Y] + 1.);
+                (1. - t) * *bottom + t * *top
+            }
+            Environment::Map(map) => map.radiance(direction),
+            Environment::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    /// Chooses a random direction for light to arrive from, favoring the brighter ones.
+    pub fn random_direction(&self, rng: &mut dyn FnMut() -> f64) -> Vec3 {
+        match self {
+            Environment::Map(map) => map.random_direction(rng),
+            Environment::Sky(sky) => sky.random_direction(rng),
+            // Other environments vary too little to be worth favoring any direction.
+            _ => uniform_direction(rng),
+        }
+    }
+
+    /// The probability density, per unit solid angle, of `random_direction` choosing `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match self {
+            Environment::Map(map) => map.pdf(direction),
+            Environment::Sky(sky) => sky.pdf(direction),
+            _ => 1. / (4. * PI),
+        }
+    }
+}
+
+/// A direction chosen evenly from all directions.
+fn uniform_direction(rng: &mut dyn FnMut() -> f64) -> Vec3 {
+    let z = 1. - 2. * rng();
+    let phi = 2. * PI * rng();
+    let rho = (1. - z * z).sqrt();
+    Vec3(rho * phi.cos(), rho * phi.sin(), z)
+}
+
+/// An image stretched over every direction, in the [equirectangular][equi] layout: the image's
+/// columns run once around the horizon and its rows from straight up, at the top, to straight
+/// down. This is the layout of most HDR environment maps.
+///
+/// [equi]: https://en.wikipedia.org/wiki/Equirectangular_projection
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    image: Raster,
+    /// Sine and cosine of the rotation of the map around the Y axis.
+    rotation: (f64, f64),
+    intensity: f64,
+    /// For choosing pixels in proportion to the light they contribute: the cumulative
+    /// distribution of rows, and then for each row, of the pixels within it. Each has one more
+    /// entry than there are rows or pixels, starting at 0 and ending at 1.
+    row_cdf: Vec<f64>,
+    column_cdfs: Vec<Vec<f64>>,
+    /// Probability of choosing each pixel, row by row.
+    pixel_probability: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    /// Uses `image` as an environment, turned by `rotation` degrees around the Y axis (counter
+    /// clockwise when seen from above), and with its colors multiplied by `intensity`.
+    ///
+    /// The image should hold linear intensities, as HDR images do; decode sRGB images with
+    /// `Raster::decode_srgb` first.
+    pub fn new(image: Raster, rotation: f64, intensity: f64) -> Self {
+        let (width, height) = (image.width(), image.height());
+
+        // Each pixel's share of the light is its brightness times the solid angle it covers,
+        // which shrinks towards the poles.
+        let mut weights: Vec<f64> = (0..height)
+            .flat_map(|y| {
+                let theta = PI * (y as f64 + 0.5) / height as f64;
+                let image = &image;
+                (0..width).map(move |x| image.luminance(x, y).max(0.) * theta.sin())
+            })
+            .collect();
+        let mut total: f64 = weights.iter().sum();
+        if total <= 0. || !total.is_finite() {
+            // A black (or broken) image; sample it evenly.
+            weights.iter_mut().for_each(|w| *w = 1.);
+            total = weights.len() as f64;
+        }
+
+        let cumulative = |values: &[f64]| {
+            let sum: f64 = values.iter().sum();
+            let mut running = 0.;
+            let mut cdf = vec![0.];
+            for v in values {
+                running += v;
+                cdf.push(if sum > 0. { running / sum } else { 0. });
+            }
+            *cdf.last_mut().unwrap() = 1.;
+            cdf
+        };
+        let rows: Vec<&[f64]> = weights.chunks(width).collect();
+        let row_sums: Vec<f64> = rows.iter().map(|row| row.iter().sum()).collect();
+        let row_cdf = cumulative(&row_sums);
+        let column_cdfs = rows.iter().map(|row| cumulative(row)).collect();
+        let pixel_probability = weights.iter().map(|w| w / total).collect();
+
+        let radians = rotation.to_radians();
+        EnvironmentMap {
+            image,
+            rotation: (radians.sin(), radians.cos()),
+            intensity,
+            row_cdf,
+            column_cdfs,
+            pixel_probability,
+        }
+    }
+
+    /// Loads the image file at `path` (see `raster::load`) as an environment, as for `new`.
+    pub fn load(
+        path: impl AsRef<Path>,
+        rotation: f64,
+        intensity: f64,
+    ) -> Result<Self, RasterError> {
+        Ok(EnvironmentMap::new(
+            raster::load(path)?,
+            rotation,
+            intensity,
+        ))
+    }
+
+    /// Turns `direction` from the scene's coordinates into the map's, or back if `sign` is -1.
+    fn rotate(&self, direction: Vec3, sign: f64) -> Vec3 {
+        let (sin, cos) = (sign * self.rotation.0, self.rotation.1);
+        Vec3(
+            cos * direction[X] + sin * direction[Z],
+            direction[Y],
+            -sin * direction[X] + cos * direction[Z],
+        )
+    }
+
+    /// The position in the image, in pixels from its top left, seen in `direction`, along with
+    /// the sine of the direction's angle from straight up.
+    fn position(&self, direction: Vec3) -> (f64, f64, f64) {
+        let n = self.rotate(direction, -1.).into_unit();
+        let (u, v) = sphere_uv(n);
+        let sin_theta = (1. - n[Y] * n[Y]).max(0.).sqrt();
+        (
+            u * self.image.width() as f64,
+            (1. - v) * self.image.height() as f64,
+            sin_theta,
+        )
+    }
+
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let (width, height) = (self.image.width(), self.image.height());
+        let (x, y, _) = self.position(direction);
+        // Interpolate between the four nearest pixel centers, wrapping around the horizon.
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let column = |i: f64| (i as i64).rem_euclid(width as i64) as usize;
+        let row = |i: f64| (i.max(0.) as usize).min(height - 1);
+        let texel = |i, j| self.image.get(column(i), row(j));
+        let top = (1. - fx) * texel(x0, y0) + fx * texel(x0 + 1., y0);
+        let bottom = (1. - fx) * texel(x0, y0 + 1.) + fx * texel(x0 + 1., y0 + 1.);
+        self.intensity * ((1. - fy) * top + fy * bottom)
+    }
+
+    fn random_direction(&self, rng: &mut dyn FnMut() -> f64) -> Vec3 {
+        // Choose a pixel, and then a point evenly within it.
+        let pick = |cdf: &[f64], r: f64| {
+            let i = cdf.partition_point(|&c| c <= r);
+            i.clamp(1, cdf.len() - 1) - 1
+        };
+        let y = pick(&self.row_cdf, rng());
+        let x = pick(&self.column_cdfs[y], rng());
+        let u = (x as f64 + rng()) / self.image.width() as f64;
+        let v = 1. - (y as f64 + rng()) / self.image.height() as f64;
+
+        // Invert `sphere_uv`.
+        let phi = 2. * PI * u - PI;
+        let theta = PI * v;
+        let direction = Vec3(
+            theta.sin() * phi.cos(),
+            -theta.cos(),
+            -theta.sin() * phi.sin(),
+        );
+        self.rotate(direction, 1.)
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+        let (width, height) = (self.image.width(), self.image.height());
+        let (x, y, sin_theta) = self.position(direction);
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        let (x, y) = ((x as usize).min(width - 1), (y as usize).min(height - 1));
+        // Points are spread evenly over the pixel's area in the image, which covers a solid
+        // angle of 2π² sin θ / (width * height).
+        let probability = self.pixel_probability[y * width + x];
+        probability * (width * height) as f64 / (2. * PI * PI * sin_theta)
+    }
+}
+
+/// The sky on a clear day, following the analytic model of [Preetham, Shirley and Smits][pss],
+/// with the sun as a small, very bright disk.
+///
+/// The sky's brightness and color depend on the sun's position and on the turbidity of the air:
+/// 2 is a very clear sky, 3 a typical clear day, and 6 or more a hazy one. The model only covers
+/// the sky above the horizon, and a sun at or above it; below the horizon is the ground, a
+/// diffuse surface with color `ground_albedo` lit by the sun and sky.
+///
+/// Before being scaled by `intensity`, radiance is in thousands of candelas per square meter, so
+/// the sky is a few units bright and the sun about a million. An intensity of around 0.02 gives
+/// scenes similar in brightness to the others.
+///
+/// [pss]: https://doi.org/10.1145/311535.311545
+#[derive(Debug, Clone)]
+pub struct Sky {
+    /// Unit vector towards the sun.
+    sun: Vec3,
+    /// Coefficients of the Perez distribution, and its value at the zenith, for luminance and
+    /// the two chromaticity coordinates.
+    perez: [[f64; 5]; 3],
+    zenith: [f64; 3],
+    intensity: f64,
+    /// Radiance of the sun's disk, after passing through the atmosphere.
+    sun_radiance: Vec3,
+    ground_radiance: Vec3,
+    /// Probability of `random_direction` choosing a direction within the sun.
+    sun_probability: f64,
+}
+
+/// The sun's angular radius, in radians.
+const SUN_RADIUS: f64 = 0.004_65;
+
+impl Sky {
+    /// A sky with the sun towards `sun_direction`, which need not be a unit vector, and with
+    /// radiance multiplied by `intensity`. See the type's documentation for the other parameters.
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Vec3, intensity: f64) -> Self {
+        let sun = sun_direction.into_unit();
+        // The model goes wrong with the sun below the horizon; keep the sky as at sunset.
+        let theta_sun = sun[Y].clamp(0., 1.).acos();
+        let t = turbidity;
+
+        // The Perez coefficients and zenith values, fitted to simulations by Preetham et al.
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_sun);
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.);
+        let cubic = |c: [f64; 4]| {
+            let s = theta_sun;
+            c[0] * s * s * s + c[1] * s * s + c[2] * s + c[3]
+        };
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+        // Divide out the distribution's value at the zenith, so it can be scaled by the zenith
+        // values directly.
+        let zenith = [zenith_luminance, zenith_x, zenith_y];
+        let zenith = [0, 1, 2].map(|i| zenith[i] / perez_distribution(&perez[i], 1., theta_sun));
+
+        // The sun's light is scattered out of its path by air molecules (Rayleigh scattering)
+        // and by haze (aerosols), more so at shorter wavelengths and lower in the sky.
+        let sun_radiance = if sun[Y] > 0. {
+            let zenith_angle = theta_sun.to_degrees();
+            let air_mass = 1. / (sun[Y] + 0.15 * (93.885 - zenith_angle).powf(-1.253));
+            let beta = 0.046_083_658_220_5 * t - 0.045_860_259_285_22;
+            // Wavelengths, in micrometers, standing in for red, green and blue.
+            let transmittance = Vec3(0.68, 0.55, 0.44).map(|wavelength| {
+                let rayleigh = 0.008_735 * wavelength.powf(-4.08);
+                let aerosol = beta * wavelength.powf(-1.3);
+                (-(rayleigh + aerosol) * air_mass).exp()
+            });
+            // The sun's luminance above the atmosphere: 128,000 lux from its tiny disk.
+            1.88e6 * transmittance
+        } else {
+            Vec3::default()
+        };
+
+        let mut sky = Sky {
+            sun,
+            perez,
+            zenith,
+            intensity,
+            sun_radiance: intensity * sun_radiance,
+            ground_radiance: Vec3::default(),
+            sun_probability: 0.,
+        };
+
+        // Add up the light from the sky, in total and falling on the ground, by the midpoint
+        // rule over the upper hemisphere in equal areas.
+        const STEPS: usize = 32;
+        let (mut sky_power, mut sky_irradiance) = (0., Vec3::default());
+        for i in 0..STEPS {
+            let cos_theta = (i as f64 + 0.5) / STEPS as f64;
+            let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+            for j in 0..2 * STEPS {
+                let phi = PI * (j as f64 + 0.5) / STEPS as f64;
+                let direction = Vec3(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                let radiance = sky.sky_radiance(direction);
+                let solid_angle = 2. * PI / (2 * STEPS * STEPS) as f64;
+                sky_power += luminance(radiance) * solid_angle;
+                sky_irradiance += cos_theta * solid_angle * radiance;
+            }
+        }
+        let sun_solid_angle = 2. * PI * (1. - SUN_RADIUS.cos());
+        let sun_power = luminance(sky.sun_radiance) * sun_solid_angle;
+        let sun_irradiance = sun[Y].max(0.) * sun_solid_angle * sky.sun_radiance;
+
+        sky.ground_radiance = ground_albedo * (sky_irradiance + sun_irradiance) / PI;
+        // Aim for the sun in proportion to its share of the light, but always leave some samples
+        // for the rest of the sky.
+        if sun_power > 0. {
+            sky.sun_probability = (sun_power / (sun_power + sky_power)).min(0.9);
+        }
+        sky
+    }
+
+    /// The light of the sky alone, without the sun's disk, from `direction`, a unit vector above
+    /// the horizon.
+    fn sky_radiance(&self, direction: Vec3) -> Vec3 {
+        let cos_gamma = direction.dot(self.sun).clamp(-1., 1.);
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * perez_distribution(&self.perez[i], direction[Y], cos_gamma.acos())
+        });
+        if y <= 0. {
+            return Vec3::default();
+        }
+        // From xyY to CIE XYZ, and then to linear sRGB.
+        let xyz = Vec3(x / y * luminance, luminance, (1. - x - y) / y * luminance);
+        let rgb = Vec3(
+            3.2406 * xyz[X] - 1.5372 * xyz[Y] - 0.4986 * xyz[Z],
+            -0.9689 * xyz[X] + 1.8758 * xyz[Y] + 0.0415 * xyz[Z],
+            0.0557 * xyz[X] - 0.2040 * xyz[Y] + 1.0570 * xyz[Z],
+        );
+        self.intensity * rgb.map(|c| c.max(0.))
+    }
+
+    fn radiance(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.into_unit();
+        if direction[Y] < 0. {
+            return self.ground_radiance;
+        }
+        let mut radiance = self.sky_radiance(direction);
+        if direction.dot(self.sun) >= SUN_RADIUS.cos() {
+            radiance += self.sun_radiance;
+        }
+        radiance
+    }
+
+    fn random_direction(&self, rng: &mut dyn FnMut() -> f64) -> Vec3 {
+        if rng() >= self.sun_probability {
+            return uniform_direction(rng);
+        }
+        // Choose a point evenly within the sun's disk.
+        let cos_theta = 1. - rng() * (1. - SUN_RADIUS.cos());
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let phi = 2. * PI * rng();
+        let (u, v) = orthonormal_basis(self.sun);
+        sin_theta * (phi.cos() * u + phi.sin() * v) + cos_theta * self.sun
+    }
+
+    fn pdf(&self, direction: Vec3) -> f64 {
+        let mut pdf = (1. - self.sun_probability) / (4. * PI);
+        if direction.into_unit().dot(self.sun) >= SUN_RADIUS.cos() {
+            pdf += self.sun_probability / (2. * PI * (1. - SUN_RADIUS.cos()));
+        }
+        pdf
+    }
+}
+
+/// The Perez sky distribution, with coefficients `c`, for a direction at angle `theta` (given by
+/// its cosine) from the zenith and `gamma` from the sun.
+fn perez_distribution(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1. + c[0] * (c[1] / cos_theta.max(1e-3)).exp())
+        * (1. + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+/// Luminance of a linear sRGB color.
+fn luminance(color: Vec3) -> f64 {
+    color.dot(Vec3(0.2126, 0.7152, 0.0722))
+}
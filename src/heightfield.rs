@@ -0,0 +1,272 @@
+//! Terrain described by a grid of heights.
+//!
+//! ```
+//! use ray_tracing::heightfield::Heightfield;
+//! use ray_tracing::material::Material;
+//! use ray_tracing::texture;
+//! use ray_tracing::vec3::Vec3;
+//!
+//! // Rolling hills, 1000 units on a side and up to 100 high.
+//! let hills = Heightfield::from_fn(
+//!     128,
+//!     128,
+//!     |u, v| 0.5 + 0.25 * ((10. * u).sin() + (7. * v).cos()),
+//!     Vec3(1000., 100., 1000.),
+//!     Material::Lambertian {
+//!         albedo: texture::constant(Vec3(0.48, 0.83, 0.53)),
+//!     },
+//! );
+//! ```
+
+use std::ops::Range;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::mesh;
+use crate::object::{HitRecord, Object};
+use crate::raster::Raster;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// A surface whose height above the XZ plane is given at regularly spaced points, and interpolated
+/// linearly between them.
+///
+/// The grid covers the rectangle from the origin to `size.0` along X and `size.2` along Z, and
+/// heights, given from 0 to 1, are scaled by `size.1`. Each grid cell is drawn as two triangles,
+/// with normals interpolated smoothly across them. The surface coordinates run from 0 to 1 across
+/// the grid, along X and Z respectively.
+///
+/// Rays are intersected by walking through the grid cells they pass over, in order, so the cost of
+/// a hit grows with the width of the grid rather than its area.
+#[derive(Clone)]
+pub struct Heightfield {
+    /// Number of grid points along X.
+    nx: usize,
+    /// Number of grid points along Z.
+    nz: usize,
+    /// Heights of grid points, in scene units, row by row along X.
+    heights: Vec<f64>,
+    /// Surface normals at grid points, in the same order as `heights`.
+    normals: Vec<Vec3>,
+    size: Vec3,
+    bounds: Aabb,
+    material: Material,
+}
+
+impl Heightfield {
+    /// Creates a heightfield from an `nx` by `nz` grid of `heights`, given row by row, starting at
+    /// the origin and running along X.
+    ///
+    /// # Panics
+    ///
+    /// If there are fewer than two grid points in either direction, or not exactly `nx * nz`
+    /// heights.
+    pub fn new(nx: usize, nz: usize, heights: Vec<f64>, size: Vec3, material: Material) -> Self {
+        assert!(nx >= 2 && nz >= 2, "Heightfield needs at least a 2x2 grid.");
+        assert_eq!(
+            heights.len(),
+            nx * nz,
+            "Heightfield must have nx * nz heights."
+        );
+
+        let heights: Vec<f64> = heights.into_iter().map(|h| h * size.1).collect();
+        let (dx, dz) = (size.0 / (nx - 1) as f64, size.2 / (nz - 1) as f64);
+
+        // Estimate the slope at each point by central differences, or one-sided differences at the
+        // edges of the grid.
+        let height = |i: usize, j: usize| heights[j * nx + i];
+        let normals = (0..nz)
+            .flat_map(|j| (0..nx).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(nx - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(nz - 1));
+                let slope_x = (height(i1, j) - height(i0, j)) / ((i1 - i0) as f64 * dx);
+                let slope_z = (height(i, j1) - height(i, j0)) / ((j1 - j0) as f64 * dz);
+                Vec3(-slope_x, 1., -slope_z).into_unit()
+            })
+            .collect();
+
+        let (low, high) = heights
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &h| {
+                (lo.min(h), hi.max(h))
+            });
+        let bounds = Aabb {
+            min: Vec3(0., low, 0.),
+            max: Vec3(size.0, high, size.2),
+        }
+        .pad_flat();
+
+        Heightfield {
+            nx,
+            nz,
+            heights,
+            normals,
+            size,
+            bounds,
+            material,
+        }
+    }
+
+    /// Creates a heightfield by sampling `height` on an `nx` by `nz` grid. The function receives
+    /// the surface coordinates of each grid point, from 0 to 1 across the grid.
+    pub fn from_fn(
+        nx: usize,
+        nz: usize,
+        height: impl Fn(f64, f64) -> f64,
+        size: Vec3,
+        material: Material,
+    ) -> Self {
+        let heights = (0..nz)
+            .flat_map(|j| (0..nx).map(move |i| (i, j)))
+            .map(|(i, j)| height(i as f64 / (nx - 1) as f64, j as f64 / (nz - 1) as f64))
+            .collect();
+        Heightfield::new(nx, nz, heights, size, material)
+    }
+
+    /// Creates a heightfield from the brightness of each pixel of `image`, with one grid point per
+    /// pixel. Columns of the image run along X and rows along Z.
+    pub fn from_raster(image: &Raster, size: Vec3, material: Material) -> Self {
+        let heights = (0..image.height())
+            .flat_map(|y| (0..image.width()).map(move |x| (x, y)))
+            .map(|(x, y)| image.luminance(x, y))
+            .collect();
+        Heightfield::new(image.width(), image.height(), heights, size, material)
+    }
+
+    /// The position of grid point `(i, j)`.
+    fn vertex(&self, i: usize, j: usize) -> Vec3 {
+        Vec3(
+            i as f64 * self.size.0 / (self.nx - 1) as f64,
+            self.heights[j * self.nx + i],
+            j as f64 * self.size.2 / (self.nz - 1) as f64,
+        )
+    }
+
+    /// Intersects `ray` with the two triangles of the cell with lowest corner `(i, j)`, returning
+    /// the hit's `t` and interpolated normal.
+    fn hit_cell(&self, ray: &Ray, i: usize, j: usize, t_range: Range<f64>) -> Option<(f64, Vec3)> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut nearest: Option<(f64, Vec3)> = None;
+        for [a, b, c] in [[0, 1, 2], [0, 2, 3]] {
+            let [a, b, c] = [corners[a], corners[b], corners[c]];
+            let end = nearest.map_or(t_range.end, |(t, _)| t);
+            if let Some((t, b1, b2)) = mesh::intersect(
+                ray,
+                self.vertex(a.0, a.1),
+                self.vertex(b.0, b.1),
+                self.vertex(c.0, c.1),
+                t_range.start..end,
+            ) {
+                let normal = |(i, j): (usize, usize)| self.normals[j * self.nx + i];
+                let n = (1. - b1 - b2) * normal(a) + b1 * normal(b) + b2 * normal(c);
+                nearest = Some((t, n.into_unit()));
+            }
+        }
+        nearest
+    }
+}
+
+impl std::fmt::Debug for Heightfield {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Heightfield")
+            .field("nx", &self.nx)
+            .field("nz", &self.nz)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Object for Heightfield {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let range = self.bounds.clip(ray, t_range);
+        if range.is_empty() {
+            return None;
+        }
+
+        // Walk the cells under the ray using a 2D digital differential analyzer: at each step,
+        // move into whichever neighboring cell the ray reaches first, along X or Z.
+        let (o, d) = (ray.origin, ray.direction);
+        let cell_size = [
+            self.size.0 / (self.nx - 1) as f64,
+            self.size.2 / (self.nz - 1) as f64,
+        ];
+        let (origin, direction) = ([o.0, o.2], [d.0, d.2]);
+        let last = [self.nx as isize - 2, self.nz as isize - 2];
+
+        let entry = ray.point_at_parameter(range.start);
+        let mut cell = [0, 1].map(|k| {
+            let p = [entry.0, entry.2][k];
+            ((p / cell_size[k]).floor() as isize).clamp(0, last[k])
+        });
+        let step = direction.map(|d| if d < 0. { -1 } else { 1 });
+        // The `t` at which the ray crosses the next cell boundary along each axis, and the change
+        // in `t` from one boundary to the next.
+        let mut t_next = [0, 1].map(|k| {
+            if direction[k] == 0. {
+                f64::INFINITY
+            } else {
+                let boundary = (cell[k] + if step[k] > 0 { 1 } else { 0 }) as f64 * cell_size[k];
+                (boundary - origin[k]) / direction[k]
+            }
+        });
+        let t_delta = [0, 1].map(|k| (cell_size[k] / direction[k]).abs());
+
+        let mut t_in = range.start;
+        loop {
+            let t_out = t_next[0].min(t_next[1]).min(range.end);
+
+            // Skip cells where the ray passes entirely above or below the terrain.
+            let (i, j) = (cell[0] as usize, cell[1] as usize);
+            let ys = [
+                self.heights[j * self.nx + i],
+                self.heights[j * self.nx + i + 1],
+                self.heights[(j + 1) * self.nx + i],
+                self.heights[(j + 1) * self.nx + i + 1],
+            ];
+            let (low, high) = (
+                ys.iter().copied().fold(f64::INFINITY, f64::min),
+                ys.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            );
+            let (y0, y1) = (o.1 + t_in * d.1, o.1 + t_out * d.1);
+            if y0.min(y1) <= high && y0.max(y1) >= low {
+                if let Some((t, normal)) = self.hit_cell(ray, i, j, range.clone()) {
+                    let p = ray.point_at_parameter(t);
+                    // Follow the smoothed surface, whose slope is given by the normal.
+                    return Some(HitRecord {
+                        t,
+                        p,
+                        normal,
+                        uv: (p.0 / self.size.0, p.2 / self.size.2),
+                        tangent: self.size.0 * Vec3(1., -normal.0 / normal.1, 0.),
+                        bitangent: self.size.2 * Vec3(0., -normal.2 / normal.1, 1.),
+                        local_p: p,
+                        footprint: None,
+                        face: None,
+                        material: &self.material,
+                    });
+                }
+            }
+
+            if t_out >= range.end {
+                return None;
+            }
+            let k = if t_next[0] < t_next[1] { 0 } else { 1 };
+            cell[k] += step[k];
+            if cell[k] < 0 || cell[k] > last[k] {
+                return None;
+            }
+            t_in = t_next[k];
+            t_next[k] += t_delta[k];
+        }
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        self.bounds
+    }
+}
@@ -0,0 +1,127 @@
+//! Instancing: placing many copies of shared geometry in a scene.
+//!
+//! A scene with many copies of a model is built in two levels. The bottom level is the model
+//! itself, built once (usually as a `FlatBvh` over its parts) and shared by `Arc`. The top level is
+//! a hierarchy over `Instance`s, each of which refers to the shared model and places it in the
+//! scene with its own transform. Memory use is then proportional to the amount of unique geometry,
+//! plus a small, fixed amount per instance.
+//!
+//! ```
+//! use std::sync::Arc;
+//!
+//! use ray_tracing::bvh::{FlatBvh, Split};
+//! use ray_tracing::instance::{self, Instance};
+//! use ray_tracing::matrix::Mat4;
+//! use ray_tracing::object::Object;
+//! use ray_tracing::vec3::Vec3;
+//!
+//! let model: Arc<dyn Object> = Arc::new(FlatBvh::new(
+//!     ray_tracing::cornell_box(),
+//!     0. ..1.,
+//!     Split::Sah { bins: 16 },
+//! ));
+//! let instances = (0..100)
+//!     .map(|i| {
+//!         let offset = Vec3(600. * (i % 10) as f64, 0., 600. * (i / 10) as f64);
+//!         Instance::new(model.clone(), Mat4::translation(offset))
+//!     })
+//!     .collect();
+//! let world = instance::top_level(instances, 0. ..1., Split::Sah { bins: 16 });
+//! ```
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::bvh::{FlatBvh, Split};
+use crate::material::Material;
+use crate::matrix::Mat4;
+use crate::object::{self, HitRecord, Object, Transform};
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// A copy of shared geometry, placed in the scene by an affine transform and optionally given a
+/// different material.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    placed: Transform<Arc<dyn Object>>,
+    /// Replacement for the prototype's materials, if any.
+    material: Option<Material>,
+}
+
+impl Instance {
+    /// Places `prototype` in the scene by applying `transform` to it.
+    ///
+    /// # Panics
+    ///
+    /// If `transform` can't be inverted.
+    pub fn new(prototype: Arc<dyn Object>, transform: Mat4) -> Self {
+        Instance {
+            placed: object::transform(transform, prototype),
+            material: None,
+        }
+    }
+
+    /// Renders this instance in `material`, instead of whatever materials the prototype uses.
+    pub fn with_material(self, material: Material) -> Self {
+        Instance {
+            material: Some(material),
+            ..self
+        }
+    }
+}
+
+impl Object for Instance {
+    #[inline]
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        self.placed.hit(ray, t_range, rng).map(|hit| HitRecord {
+            material: self.material.as_ref().unwrap_or(hit.material),
+            ..hit
+        })
+    }
+
+    fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
+        self.placed.bounding_box(exposure)
+    }
+
+    /// With a replacement material, the instance is a light if that material is. It can only be
+    /// aimed at if the prototype supports `random_toward` whatever its own material, as the
+    /// basic shapes do.
+    fn is_light(&self) -> bool {
+        match &self.material {
+            Some(material) => matches!(material, Material::DiffuseLight { .. }),
+            None => self.placed.is_light(),
+        }
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        self.placed.random_toward(origin, time, rng)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        self.placed.pdf_toward(origin, direction, time, rng)
+    }
+}
+
+/// Builds the top level of a two-level scene: a hierarchy over `instances`.
+pub fn top_level(instances: Vec<Instance>, exposure: Range<f64>, split: Split) -> FlatBvh {
+    FlatBvh::new(
+        instances
+            .into_iter()
+            .map(|i| Box::new(i) as Box<dyn Object>)
+            .collect(),
+        exposure,
+        split,
+    )
+}
@@ -4,17 +4,34 @@
 mod aabb;
 pub mod bvh;
 pub mod camera;
+pub mod csg;
+pub mod environment;
+pub mod heightfield;
+pub mod instance;
+pub mod light;
 pub mod material;
+pub mod matrix;
+pub mod mesh;
+pub mod noise;
+pub mod obj;
 pub mod object;
-mod perlin;
+pub mod perlin;
+pub mod ply;
+pub mod raster;
 pub mod ray;
+pub mod sdf;
+pub mod shape;
+pub mod stl;
 pub mod texture;
+pub mod texture_graph;
 pub mod vec3;
 
 use rand::prelude::*;
 use rayon::prelude::*;
 
 use crate::camera::Camera;
+use crate::environment::Environment;
+use crate::light::Light;
 use crate::material::Material;
 use crate::object::Object;
 use crate::ray::Ray;
@@ -22,12 +39,92 @@ use crate::vec3::{Channel::*, *};
 
 pub trait World: Send + Sync {
     fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<object::HitRecord<'a>>;
+
+    /// The objects in the world that give off light and can be aimed at directly (see
+    /// `Object::is_light`). The renderer collects these once, before casting any rays.
+    fn lights(&self) -> Vec<&dyn Object> {
+        vec![]
+    }
+
+    /// The light arriving from far away along rays that escape the world, if any. Without one,
+    /// escaping rays find only darkness.
+    fn environment(&self) -> Option<&Environment> {
+        None
+    }
+
+    /// The lights in the world that are not objects, and can only be found by shadow rays.
+    fn delta_lights(&self) -> Vec<&Light> {
+        vec![]
+    }
 }
 
-impl<'r, T: World + ?Sized> World for &'r T {
+impl<T: World + ?Sized> World for &T {
     fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<object::HitRecord<'a>> {
         (*self).hit_top(ray, rng)
     }
+
+    fn lights(&self) -> Vec<&dyn Object> {
+        (*self).lights()
+    }
+
+    fn environment(&self) -> Option<&Environment> {
+        (*self).environment()
+    }
+
+    fn delta_lights(&self) -> Vec<&Light> {
+        (*self).delta_lights()
+    }
+}
+
+/// A world lit by an environment, such as the sky, from beyond its objects.
+pub struct WithEnvironment<W> {
+    pub world: W,
+    /// The environment, or `None` for darkness.
+    pub environment: Option<Environment>,
+}
+
+impl<W: World> World for WithEnvironment<W> {
+    fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<object::HitRecord<'a>> {
+        self.world.hit_top(ray, rng)
+    }
+
+    fn lights(&self) -> Vec<&dyn Object> {
+        self.world.lights()
+    }
+
+    fn environment(&self) -> Option<&Environment> {
+        self.environment.as_ref()
+    }
+
+    fn delta_lights(&self) -> Vec<&Light> {
+        self.world.delta_lights()
+    }
+}
+
+/// A world lit by point, spot or directional lights, in addition to any lights of its own.
+pub struct WithLights<W> {
+    pub world: W,
+    pub lights: Vec<Light>,
+}
+
+impl<W: World> World for WithLights<W> {
+    fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<object::HitRecord<'a>> {
+        self.world.hit_top(ray, rng)
+    }
+
+    fn lights(&self) -> Vec<&dyn Object> {
+        self.world.lights()
+    }
+
+    fn environment(&self) -> Option<&Environment> {
+        self.world.environment()
+    }
+
+    fn delta_lights(&self) -> Vec<&Light> {
+        let mut lights = self.world.delta_lights();
+        lights.extend(&self.lights);
+        lights
+    }
 }
 
 impl World for [Box<dyn Object>] {
@@ -46,54 +143,267 @@ impl World for [Box<dyn Object>] {
 
         hit
     }
+
+    fn lights(&self) -> Vec<&dyn Object> {
+        self.iter()
+            .filter(|obj| obj.is_light())
+            .map(|obj| obj.as_ref())
+            .collect()
+    }
 }
 
 impl World for bvh::Bvh {
     fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<object::HitRecord<'a>> {
         self.hit(ray, 0.001..f64::MAX, &mut || rng.gen())
     }
+
+    fn lights(&self) -> Vec<&dyn Object> {
+        bvh::Bvh::lights(self)
+    }
 }
 
-/// Computes the pixel color along `ray` for the scene of objects `world`.
+impl World for bvh::FlatBvh {
+    fn hit_top<'a>(&'a self, ray: &Ray, rng: &mut impl Rng) -> Option<object::HitRecord<'a>> {
+        self.hit(ray, 0.001..f64::MAX, &mut || rng.gen())
+    }
+
+    fn lights(&self) -> Vec<&dyn Object> {
+        bvh::FlatBvh::lights(self)
+    }
+}
+
+/// How far the renderer follows each path of light through the scene.
+#[derive(Debug, Clone, Copy)]
+pub struct RenderSettings {
+    /// The most surfaces a path may reach. Paths are cut off there, losing any light they would
+    /// have found further on, so this should be large enough that they rarely get that far.
+    pub max_depth: usize,
+    /// The number of surfaces a path reaches before Russian roulette may end it.
+    pub min_depth: usize,
+    /// Whether to end paths at random, with a chance that grows as they grow dimmer, once they
+    /// have reached `min_depth` surfaces. The paths that carry on are brightened to make up for
+    /// those that stop, so this adds noise but no bias, and saves tracing dim paths to full
+    /// depth.
+    pub russian_roulette: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            max_depth: 50,
+            min_depth: 3,
+            russian_roulette: true,
+        }
+    }
+}
+
+/// Computes the pixel color along `ray` for the scene of objects `world`, which gives off light
+/// from `lights` (as returned by `World::lights`), its environment, and perhaps elsewhere.
 ///
 /// This is the actual ray-tracing routine.
-pub fn color(world: &impl World, mut ray: Ray, rng: &mut impl Rng) -> Vec3 {
+pub fn color(
+    world: &impl World,
+    lights: &[&dyn Object],
+    settings: &RenderSettings,
+    mut ray: Ray,
+    rng: &mut impl Rng,
+) -> Vec3 {
     // Accumulates contribution of each surface we reach
     let mut accum = Vec3::default();
     // Records the cumulative (product) attenuation fo each surface we've visited so far
     let mut strength = Vec3::from(1.);
+    // The density with which the last surface scattered `ray` in its direction, if it also
+    // sampled the lights, and so shares the credit for any light found along `ray`.
+    let mut scatter_pdf = None;
+    let delta_lights = world.delta_lights();
 
     let mut bounces = 0;
 
     // Iterate until one of the following conditions is reached:
     // 1. The ray escapes into space (i.e. no objects are hit).
     // 2. The ray reaches a surface that does not scatter.
-    // 3. The ray bounces more than 50 times.
+    // 3. The ray reaches `settings.max_depth` surfaces, or is ended by Russian roulette.
     while let Some(hit) = world.hit_top(&ray, rng) {
-        if bounces == 50 {
+        if bounces == settings.max_depth {
             return accum;
         }
         bounces += 1;
+        let hit = hit.with_footprint(&ray);
+
+        // Record this hit's contribution, attenuated by the total attenuation so far, and
+        // weighted against the chance that sampling the lights would have found it instead.
+        let emitted = hit.material.emitted(&hit);
+        let weight = match scatter_pdf {
+            Some(pdf) if emitted.reduce(f64::max) > 0. => {
+                power_heuristic(pdf, lights_pdf(world, lights, &ray, rng))
+            }
+            _ => 1.,
+        };
+        accum += weight * strength * emitted;
+
+        // Unless the surface is a mirror or the like, add the light arriving directly from a
+        // light chosen at random. This finds small lights far more often than scattered rays do
+        // by chance.
+        let has_lights = !lights.is_empty() || world.environment().is_some();
+        let samples_lights = has_lights && !hit.material.is_specular();
+        if samples_lights {
+            accum += strength * sample_lights(world, lights, &ray, &hit, rng);
+        }
+        if !hit.material.is_specular() {
+            accum += strength * sample_delta_lights(world, &delta_lights, &ray, &hit, rng);
+        }
 
-        // Record this hit's contribution, attenuated by the total attenuation so far.
-        accum += strength * hit.material.emitted(hit.p);
-
-        // Check whether the material scatters light, generating a new ray. In practive this is
+        // Check whether the material scatters light, generating a new ray. In practice this is
         // true for everything but the emission-only `DiffuseLight` type.
-        //
-        // TODO: and also for frosted metal, which effectively makes frosted metal an emitter. That
-        // can't be right.
         if let Some((new_ray, attenuation)) = hit.material.scatter(&ray, &hit, rng) {
+            scatter_pdf = if samples_lights {
+                Some(hit.material.pdf(&ray, &hit, new_ray.direction))
+            } else {
+                None
+            };
             // Redirect flight, accumulate the new attenuation value
             ray = new_ray;
             strength *= attenuation;
+
+            // Play Russian roulette with dim paths, keeping each with a chance in proportion to
+            // its strength (but never quite certainly), and making up for the lost ones in the
+            // strength of those kept.
+            if settings.russian_roulette && bounces >= settings.min_depth {
+                let survival = strength.reduce(f64::max).min(0.95);
+                if rng.gen::<f64>() >= survival {
+                    return accum;
+                }
+                strength = strength / survival;
+            }
         } else {
             // Locally absorbed; we're done
             return accum;
         }
     }
 
-    Vec3::default()
+    // Escaped into space, to be lit by the environment, if any.
+    if let Some(environment) = world.environment() {
+        let weight = match scatter_pdf {
+            Some(pdf) => power_heuristic(pdf, lights_pdf(world, lights, &ray, rng)),
+            None => 1.,
+        };
+        accum += weight * strength * environment.radiance(ray.direction);
+    }
+    accum
+}
+
+/// Veach's power heuristic: the weight given to a sample taken with density `pdf`, when the
+/// other way of sampling it has density `other_pdf`. Each way of sampling gets most of the credit
+/// for the directions it's best at finding.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0. {
+        0.
+    } else {
+        a / (a + b)
+    }
+}
+
+/// The probability density, per unit solid angle, of `sample_lights` choosing the direction of
+/// `ray` from its origin.
+fn lights_pdf(world: &impl World, lights: &[&dyn Object], ray: &Ray, rng: &mut impl Rng) -> f64 {
+    let environment = world.environment();
+    let count = lights.len() + environment.iter().count();
+    if count == 0 {
+        return 0.;
+    }
+    let total: f64 = lights
+        .iter()
+        .map(|light| light.pdf_toward(ray.origin, ray.direction, ray.time, &mut || rng.gen()))
+        .sum::<f64>()
+        + environment.map_or(0., |e| e.pdf(ray.direction));
+    total / count as f64
+}
+
+/// Estimates the light reaching the surface at `hit` directly from `lights` or the world's
+/// environment, and scattered back along `ray`, by tracing a shadow ray toward a random point on a
+/// random light. The estimate is weighted against the chance that the surface's own scattering
+/// would have found the light.
+fn sample_lights(
+    world: &impl World,
+    lights: &[&dyn Object],
+    ray: &Ray,
+    hit: &object::HitRecord,
+    rng: &mut impl Rng,
+) -> Vec3 {
+    let environment = world.environment();
+    let choice = rng.gen_range(0, lights.len() + environment.iter().count());
+    let direction = match (lights.get(choice), environment) {
+        (Some(light), _) => light.random_toward(hit.p, ray.time, &mut || rng.gen()),
+        (None, Some(environment)) => Some(environment.random_direction(&mut || rng.gen())),
+        (None, None) => None,
+    };
+    let direction = match direction {
+        Some(direction) => direction,
+        None => return Vec3::default(),
+    };
+    let response = hit.material.eval(ray, hit, direction);
+    if response.reduce(f64::max) <= 0. {
+        return Vec3::default();
+    }
+
+    let shadow_ray = Ray {
+        origin: hit.p,
+        direction,
+        time: ray.time,
+        differentials: None,
+    };
+    // Any light along the way counts, not just the chosen one, so the density of the direction is
+    // that of choosing it through any of the lights.
+    let pdf = lights_pdf(world, lights, &shadow_ray, rng);
+    if pdf <= 0. {
+        return Vec3::default();
+    }
+    let arriving = match world.hit_top(&shadow_ray, rng) {
+        Some(light_hit) => light_hit.material.emitted(&light_hit),
+        None => environment.map_or(Vec3::default(), |e| e.radiance(direction)),
+    };
+    let weight = power_heuristic(pdf, hit.material.pdf(ray, hit, direction));
+    weight / pdf * response * arriving
+}
+
+/// The light reaching the surface at `hit` directly from `delta_lights` (as returned by
+/// `World::delta_lights`), and scattered back along `ray`. Every light is checked, each with one
+/// shadow ray.
+fn sample_delta_lights(
+    world: &impl World,
+    delta_lights: &[&Light],
+    ray: &Ray,
+    hit: &object::HitRecord,
+    rng: &mut impl Rng,
+) -> Vec3 {
+    let mut total = Vec3::default();
+    for light in delta_lights {
+        let (to_light, reach, irradiance) = match light.illuminate(hit.p) {
+            Some(illumination) => illumination,
+            None => continue,
+        };
+        // `eval` includes the cosine factor, which turns the irradiance facing the light into
+        // that on the surface.
+        let response = hit.material.eval(ray, hit, to_light);
+        if response.reduce(f64::max) <= 0. {
+            continue;
+        }
+        let shadow_ray = Ray {
+            origin: hit.p,
+            direction: to_light,
+            time: ray.time,
+            differentials: None,
+        };
+        // Anything in the way before the light casts a shadow.
+        let shadowed = world
+            .hit_top(&shadow_ray, rng)
+            .is_some_and(|blocker| blocker.t < reach * (1. - 1e-6));
+        if !shadowed {
+            total += response * irradiance;
+        }
+    }
+    total
 }
 
 pub fn cornell_box() -> Vec<Box<dyn Object>> {
@@ -350,7 +660,7 @@ pub fn print_ppm(image: Image) {
             let col = Vec3(col.0.sqrt(), col.1.sqrt(), col.2.sqrt());
 
             fn to_u8(x: f64) -> i32 {
-                ((255.99 * x) as i32).max(0).min(255)
+                ((255.99 * x) as i32).clamp(0, 255)
             }
 
             let ir = to_u8(col[R]);
@@ -362,15 +672,33 @@ pub fn print_ppm(image: Image) {
     }
 }
 
-pub fn par_cast(nx: usize, ny: usize, ns: usize, camera: &Camera, world: impl World) -> Image {
+/// The fraction of a pixel that each of `ns` samples of it should be taken to cover, when
+/// filtering textures. Samples cover less than a pixel when there are several of them, though
+/// they're kept from getting too small, since texture filtering is cheaper than more samples.
+fn sample_footprint(ns: usize) -> f64 {
+    (1. / (ns as f64).sqrt()).max(0.125)
+}
+
+pub fn par_cast(
+    nx: usize,
+    ny: usize,
+    ns: usize,
+    settings: &RenderSettings,
+    camera: &Camera,
+    world: impl World,
+) -> Image {
+    let footprint = sample_footprint(ns);
+    let lights = world.lights();
     Image::par_compute(nx, ny, |x, y| {
         let col: Vec3 = (0..ns)
             .map(|_| {
                 let mut rng = thread_rng();
                 let u = (x as f64 + rng.gen::<f64>()) / nx as f64;
                 let v = (y as f64 + rng.gen::<f64>()) / ny as f64;
-                let r = camera.get_ray(u, v, &mut rng);
-                color(&world, r, &mut rng)
+                let r = camera
+                    .get_ray(u, v, &mut rng)
+                    .scale_differentials(footprint / nx as f64, footprint / ny as f64);
+                color(&world, &lights, settings, r, &mut rng)
             })
             .sum();
         col / ns as f64
@@ -381,17 +709,22 @@ pub fn cast(
     nx: usize,
     ny: usize,
     ns: usize,
+    settings: &RenderSettings,
     camera: &Camera,
     world: impl World,
     rng: &mut impl Rng,
 ) -> Image {
+    let footprint = sample_footprint(ns);
+    let lights = world.lights();
     Image::compute(nx, ny, |x, y| {
         let col: Vec3 = (0..ns)
             .map(|_| {
                 let u = (x as f64 + rng.gen::<f64>()) / nx as f64;
                 let v = (y as f64 + rng.gen::<f64>()) / ny as f64;
-                let r = camera.get_ray(u, v, rng);
-                color(&world, r, rng)
+                let r = camera
+                    .get_ray(u, v, rng)
+                    .scale_differentials(footprint / nx as f64, footprint / ny as f64);
+                color(&world, &lights, settings, r, rng)
             })
             .sum();
         col / ns as f64
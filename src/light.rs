@@ -0,0 +1,97 @@
+//! Lights that are not objects: points and directions that light comes from, which rays bouncing
+//! around the scene can never hit by chance. Instead, the renderer checks at each surface whether
+//! each of them is in view, by tracing a shadow ray.
+//!
+//! ```
+//! use ray_tracing::light::Light;
+//! use ray_tracing::vec3::Vec3;
+//!
+//! // A lamp hanging over a table, lighting a circle about two units across.
+//! let lamp = Light::Spot {
+//!     position: Vec3(0., 3., 0.),
+//!     direction: Vec3(0., -1., 0.),
+//!     intensity: Vec3::from(20.),
+//!     inner_angle: 15.,
+//!     outer_angle: 25.,
+//! };
+//! let (_, _, irradiance) = lamp.illuminate(Vec3(0., 1., 0.)).unwrap();
+//! assert_eq!(irradiance.1, 5.);
+//! assert!(lamp.illuminate(Vec3(2., 1., 0.)).is_none());
+//! ```
+
+use crate::vec3::Vec3;
+
+/// A source of light with no size, which only shadow rays can find.
+#[derive(Debug, Clone)]
+pub enum Light {
+    /// Light shining equally in every direction from `position`, and growing dimmer with the
+    /// square of the distance from it.
+    Point {
+        position: Vec3,
+        /// Light given off per unit solid angle: the irradiance on a surface facing the light,
+        /// one unit away.
+        intensity: Vec3,
+    },
+    /// A point light shining in a cone around `direction`. Within `inner_angle` degrees of
+    /// `direction` the light has its full `intensity`, and between there and `outer_angle` it
+    /// fades smoothly away to nothing.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        inner_angle: f64,
+        outer_angle: f64,
+    },
+    /// Parallel light from infinitely far away, such as sunlight, traveling along `direction`.
+    Directional {
+        direction: Vec3,
+        /// Irradiance on a surface facing the light.
+        irradiance: Vec3,
+    },
+}
+
+impl Light {
+    /// The light reaching point `p`, if any: the vector from `p` to the light, the parameter
+    /// along that vector at which it reaches the light (which may be infinite), and the
+    /// irradiance on a surface at `p` facing the light.
+    pub fn illuminate(&self, p: Vec3) -> Option<(Vec3, f64, Vec3)> {
+        match self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let to_light = *position - p;
+                let distance_squared = to_light.dot(to_light);
+                Some((to_light, 1., *intensity / distance_squared))
+            }
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                inner_angle,
+                outer_angle,
+            } => {
+                let to_light = *position - p;
+                let distance_squared = to_light.dot(to_light);
+                let cos = -to_light.into_unit().dot(direction.into_unit());
+                let (cos_inner, cos_outer) = (
+                    inner_angle.to_radians().cos(),
+                    outer_angle.to_radians().cos(),
+                );
+                let falloff = if cos >= cos_inner {
+                    1.
+                } else if cos <= cos_outer {
+                    return None;
+                } else {
+                    let t = (cos - cos_outer) / (cos_inner - cos_outer);
+                    t * t * (3. - 2. * t)
+                };
+                Some((to_light, 1., falloff * *intensity / distance_squared))
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => Some((-direction.into_unit(), f64::INFINITY, *irradiance)),
+        }
+    }
+}
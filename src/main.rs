@@ -4,12 +4,24 @@ use std::time::Instant;
 use rand::prelude::*;
 
 use ray_tracing::camera::Camera;
+use ray_tracing::environment::Environment;
+use ray_tracing::light::Light;
 use ray_tracing::object::{self, Object};
 use ray_tracing::vec3::Vec3;
 use ray_tracing::*;
 
+/// The objects in a scene, the camera looking at them, the camera's exposure time, the
+/// environment lighting them, if any, and any point, spot or directional lights.
+type Scene = (
+    Vec<Box<dyn Object>>,
+    Camera,
+    Range<f64>,
+    Option<Environment>,
+    Vec<Light>,
+);
+
 #[allow(unused)]
-fn cornell_box_scene(nx: usize, ny: usize) -> (Vec<Box<dyn Object>>, Camera, Range<f64>) {
+fn cornell_box_scene(nx: usize, ny: usize) -> Scene {
     let look_from = Vec3(278., 278., -800.);
     let look_at = Vec3(278., 278., 0.);
     let dist_to_focus = 10.;
@@ -27,11 +39,11 @@ fn cornell_box_scene(nx: usize, ny: usize) -> (Vec<Box<dyn Object>>, Camera, Ran
         exposure.clone(),
     );
 
-    (cornell_box_with_boxes(), camera, exposure)
+    (cornell_box_with_boxes(), camera, exposure, None, vec![])
 }
 
 #[allow(unused)]
-fn motion_test(nx: usize, ny: usize) -> (Vec<Box<dyn Object>>, Camera, Range<f64>) {
+fn motion_test(nx: usize, ny: usize) -> Scene {
     let look_from = Vec3(278., 278., -800.);
     let look_at = Vec3(278., 278., 0.);
     let dist_to_focus = 10.;
@@ -64,11 +76,11 @@ fn motion_test(nx: usize, ny: usize) -> (Vec<Box<dyn Object>>, Camera, Range<f64
         },
     }));
 
-    (scene, camera, exposure)
+    (scene, camera, exposure, None, vec![])
 }
 
 #[allow(unused)]
-fn volume_test(nx: usize, ny: usize) -> (Vec<Box<dyn Object>>, Camera, Range<f64>) {
+fn volume_test(nx: usize, ny: usize) -> Scene {
     let look_from = Vec3(278., 278., -800.);
     let look_at = Vec3(278., 278., 0.);
     let dist_to_focus = 10.;
@@ -105,15 +117,11 @@ fn volume_test(nx: usize, ny: usize) -> (Vec<Box<dyn Object>>, Camera, Range<f64
         },
     }));
 
-    (scene, camera, exposure)
+    (scene, camera, exposure, None, vec![])
 }
 
 #[allow(unused)]
-fn simple_light_scene(
-    nx: usize,
-    ny: usize,
-    rng: &mut impl Rng,
-) -> (Vec<Box<dyn Object>>, Camera, Range<f64>) {
+fn simple_light_scene(nx: usize, ny: usize, rng: &mut impl Rng) -> Scene {
     let look_from = Vec3(278., 278., -800.);
     let look_at = Vec3(278., 278., 0.);
     let dist_to_focus = 10.;
@@ -148,23 +156,17 @@ fn simple_light_scene(
         }));
     }
 
-    world.push(Box::new(object::FlipNormals(object::Sphere {
-        radius: 1000.,
-        material: Material::DiffuseLight {
-            emission: ray_tracing::texture::constant(Vec3::from(0.1)),
-            brightness: 1.,
-        },
-    })));
-
-    (world, camera, exposure)
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(Vec3::from(0.1))),
+        vec![],
+    )
 }
 
 #[allow(unused)]
-fn book_final_scene(
-    nx: usize,
-    ny: usize,
-    rng: &mut impl Rng,
-) -> (Vec<Box<dyn Object>>, Camera, Range<f64>) {
+fn book_final_scene(nx: usize, ny: usize, rng: &mut impl Rng) -> Scene {
     let look_from = Vec3(478., 278., -600.);
     let look_at = Vec3(278., 278., 0.);
     let dist_to_focus = 10.;
@@ -183,6 +185,7 @@ fn book_final_scene(
     );
 
     use ray_tracing::material::Material;
+    use ray_tracing::perlin::Perlin;
     use ray_tracing::texture;
 
     let ground = Material::Lambertian {
@@ -285,7 +288,7 @@ fn book_final_scene(
         object: object::Sphere {
             radius: 80.,
             material: Material::Lambertian {
-                albedo: texture::perlin(0.05),
+                albedo: texture::perlin(0.05, Perlin::new(0)),
             },
         },
     }));
@@ -314,15 +317,860 @@ fn book_final_scene(
         }
     }));
 
-    (world, camera, exposure)
+    (world, camera, exposure, None, vec![])
+}
+
+#[allow(unused)]
+fn instancing_test(nx: usize, ny: usize, rng: &mut impl Rng) -> Scene {
+    let look_from = Vec3(0., 300., -1500.);
+    let look_at = Vec3(0., 0., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::bvh::{FlatBvh, Split};
+    use ray_tracing::instance::Instance;
+    use ray_tracing::material::Material;
+    use ray_tracing::matrix::Mat4;
+    use ray_tracing::texture;
+
+    // A simple tree: a trunk with a round canopy, built once and shared by every instance.
+    let bark = Material::Lambertian {
+        albedo: texture::constant(Vec3(0.4, 0.25, 0.1)),
+    };
+    let leaves = Material::Lambertian {
+        albedo: texture::constant(Vec3(0.1, 0.5, 0.15)),
+    };
+    let tree: Vec<Box<dyn Object>> = vec![
+        Box::new(object::rect_prism(
+            Vec3(-1., 0., -1.),
+            Vec3(1., 10., 1.),
+            bark,
+        )),
+        Box::new(object::Translate {
+            offset: Vec3(0., 12., 0.),
+            object: object::Sphere {
+                radius: 5.,
+                material: leaves,
+            },
+        }),
+    ];
+    let tree: std::sync::Arc<dyn Object> =
+        std::sync::Arc::new(FlatBvh::new(tree, exposure.clone(), Split::Median));
+
+    // A forest of ten thousand trees, each with its own position, heading and size.
+    let mut world: Vec<Box<dyn Object>> = (0..10_000)
+        .map(|i| {
+            let position = Vec3(
+                -2000. + 40. * (i % 100) as f64 + 20. * rng.gen::<f64>(),
+                0.,
+                -500. + 40. * (i / 100) as f64 + 20. * rng.gen::<f64>(),
+            );
+            let transform = Mat4::translation(position)
+                * Mat4::rotation_y(360. * rng.gen::<f64>())
+                * Mat4::scaling(Vec3::from(rng.gen_range(0.7, 1.3)));
+            Box::new(Instance::new(tree.clone(), transform)) as Box<dyn Object>
+        })
+        .collect();
+
+    world.push(Box::new(object::Rect {
+        orthogonal_to: object::StaticY,
+        range0: -5000. ..5000.,
+        range1: -5000. ..5000.,
+        k: 0.,
+        material: Material::Lambertian {
+            albedo: texture::constant(Vec3(0.48, 0.83, 0.53)),
+        },
+    }));
+
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(Vec3(0.7, 0.8, 1.))),
+        vec![],
+    )
+}
+
+#[allow(unused)]
+fn csg_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(278., 278., -800.);
+    let look_at = Vec3(278., 278., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::csg;
+    use ray_tracing::material::Material;
+    use ray_tracing::texture;
+
+    fn ball(center: Vec3, radius: f64, material: Material) -> impl Object {
+        object::Translate {
+            offset: center,
+            object: object::Sphere { radius, material },
+        }
+    }
+
+    let glass = Material::Dielectric { ref_idx: 1.5 };
+    let ivory = Material::Lambertian {
+        albedo: texture::constant(Vec3(0.9, 0.88, 0.8)),
+    };
+    let black = Material::Lambertian {
+        albedo: texture::constant(Vec3::from(0.05)),
+    };
+
+    let mut scene = cornell_box();
+
+    // A biconvex lens: the overlap of two large spheres.
+    scene.push(Box::new(csg::intersection(
+        ball(Vec3(160., 300., 200.), 150., glass.clone()),
+        ball(Vec3(160., 300., 440.), 150., glass),
+    )));
+
+    // A die: a cube with rounded corners, and pips drilled out of three faces.
+    let body = csg::intersection(
+        object::rect_prism(Vec3(0., 0., 0.), Vec3(150., 150., 150.), ivory.clone()),
+        ball(Vec3::from(75.), 110., ivory),
+    );
+    let pip = |p: Vec3| ball(p, 14., black.clone());
+    let pips = object::And(
+        object::And(pip(Vec3(75., 150., 75.)), pip(Vec3(40., 40., 0.))),
+        object::And(pip(Vec3(110., 110., 0.)), pip(Vec3(0., 75., 75.))),
+    );
+    scene.push(Box::new(object::Translate {
+        offset: Vec3(330., 0., 150.),
+        object: object::rotate_y(-25., csg::difference(body, pips)),
+    }));
+
+    // Fog filling a hollow shell, so the medium has to cope with leaving and re-entering its
+    // boundary.
+    scene.push(Box::new(object::ConstantMedium {
+        boundary: csg::difference(
+            ball(Vec3(400., 420., 350.), 90., black.clone()),
+            ball(Vec3(400., 420., 350.), 60., black),
+        ),
+        density: 0.02,
+        material: Material::Isotropic {
+            albedo: texture::constant(Vec3(0.2, 0.4, 0.9)),
+        },
+    }));
+
+    (scene, camera, exposure, None, vec![])
+}
+
+#[allow(unused)]
+fn shapes_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(0., 3., -9.);
+    let look_at = Vec3(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::material::Material;
+    use ray_tracing::shape;
+    use ray_tracing::texture;
+
+    fn diffuse_color(c: Vec3) -> Material {
+        Material::Lambertian {
+            albedo: texture::constant(c),
+        }
+    }
+
+    let world: Vec<Box<dyn Object>> = vec![
+        // An endless floor.
+        Box::new(shape::Plane {
+            point: Vec3(0., 0., 0.),
+            normal: Vec3(0., 1., 0.),
+            material: diffuse_color(Vec3::from(0.5)),
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(-3., 0., 0.),
+            object: shape::Cylinder {
+                radius: 0.7,
+                height: 2.,
+                material: diffuse_color(Vec3(0.65, 0.05, 0.05)),
+            },
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(-1., 0., 1.),
+            object: shape::Cone {
+                radius: 0.8,
+                height: 2.2,
+                material: diffuse_color(Vec3(0.12, 0.45, 0.15)),
+            },
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(1.2, 1., -0.5),
+            object: object::transform(
+                ray_tracing::matrix::Mat4::rotation_x(60.),
+                shape::Torus {
+                    major_radius: 0.8,
+                    minor_radius: 0.25,
+                    material: Material::Dielectric { ref_idx: 1.5 },
+                },
+            ),
+        }),
+        Box::new(shape::Quad {
+            corner: Vec3(2.2, 0., 1.5),
+            u: Vec3(0.4, 2.5, 0.),
+            v: Vec3(1.6, 0., -0.6),
+            material: Material::Metal {
+                albedo: Vec3(0.8, 0.85, 0.9),
+                fuzz: 0.,
+            },
+        }),
+        // A round light overhead, facing down.
+        Box::new(object::Translate {
+            offset: Vec3(0., 6., 0.),
+            object: object::FlipNormals(shape::Disk {
+                radius: 2.,
+                material: Material::DiffuseLight {
+                    emission: texture::constant(Vec3::from(1.)),
+                    brightness: 6.,
+                },
+            }),
+        }),
+    ];
+
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(0.3 * Vec3(0.5, 0.7, 1.0))),
+        vec![],
+    )
+}
+
+#[allow(unused)]
+fn sdf_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(0., 3., -9.);
+    let look_at = Vec3(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::material::Material;
+    use ray_tracing::sdf::{self, SdfObject};
+    use ray_tracing::shape;
+    use ray_tracing::texture;
+
+    fn diffuse_color(c: Vec3) -> Material {
+        Material::Lambertian {
+            albedo: texture::constant(c),
+        }
+    }
+
+    let world: Vec<Box<dyn Object>> = vec![
+        Box::new(shape::Plane {
+            point: Vec3(0., 0., 0.),
+            normal: Vec3(0., 1., 0.),
+            material: diffuse_color(Vec3::from(0.5)),
+        }),
+        // A fractal.
+        Box::new(object::Translate {
+            offset: Vec3(0., 1.2, 0.),
+            object: SdfObject::new(
+                sdf::mandelbulb(8., 10),
+                Vec3::from(-1.2),
+                Vec3::from(1.2),
+                diffuse_color(Vec3(0.8, 0.6, 0.3)),
+            )
+            .with_step_scale(0.9),
+        }),
+        // A rounded box with a sphere melted into its top.
+        Box::new(object::Translate {
+            offset: Vec3(-2.8, 0.5, 0.),
+            object: SdfObject::new(
+                sdf::smooth_union(
+                    sdf::rounded_box(Vec3(0.8, 0.5, 0.8), 0.1),
+                    sdf::translate(sdf::sphere(0.5), Vec3(0., 0.6, 0.)),
+                    0.3,
+                ),
+                Vec3(-0.8, -0.5, -0.8),
+                Vec3(0.8, 1.1, 0.8),
+                diffuse_color(Vec3(0.65, 0.05, 0.05)),
+            ),
+        }),
+        // A twisted column of glass.
+        Box::new(object::Translate {
+            offset: Vec3(2.8, 0., 0.),
+            object: SdfObject::new(
+                sdf::twist(
+                    sdf::translate(
+                        sdf::rounded_box(Vec3(0.5, 1.2, 0.5), 0.05),
+                        Vec3(0., 1.2, 0.),
+                    ),
+                    1.,
+                ),
+                Vec3(-0.75, 0., -0.75),
+                Vec3(0.75, 2.4, 0.75),
+                Material::Dielectric { ref_idx: 1.5 },
+            )
+            .with_step_scale(0.5),
+        }),
+        // A row of rings receding into the distance.
+        Box::new(object::Translate {
+            offset: Vec3(0., 0.25, 3.),
+            object: SdfObject::new(
+                sdf::repeat(sdf::torus(0.4, 0.1), Vec3(1.2, 0., 0.)),
+                Vec3(-6., -0.1, -0.5),
+                Vec3(6., 0.1, 0.5),
+                Material::Metal {
+                    albedo: Vec3(0.8, 0.85, 0.9),
+                    fuzz: 0.1,
+                },
+            ),
+        }),
+    ];
+
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(Vec3(0.5, 0.7, 1.0))),
+        vec![],
+    )
+}
+
+#[allow(unused)]
+fn terrain_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(-300., 400., -900.);
+    let look_at = Vec3(500., 50., 500.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::heightfield::Heightfield;
+    use ray_tracing::material::Material;
+    use ray_tracing::perlin::Perlin;
+    use ray_tracing::texture;
+
+    // Hills from turbulent noise, sampled on a 512x512 grid. (A grayscale image could be used
+    // instead, via `raster::load` and `Heightfield::from_raster`.)
+    let noise = Perlin::new(1);
+    let terrain = Heightfield::from_fn(
+        512,
+        512,
+        |u, v| noise.turb(Vec3(4. * u, 0.5, 4. * v), 7),
+        Vec3(2000., 400., 2000.),
+        Material::Lambertian {
+            albedo: texture::constant(Vec3(0.48, 0.83, 0.53)),
+        },
+    );
+
+    let world: Vec<Box<dyn Object>> = vec![Box::new(object::Translate {
+        offset: Vec3(-500., 0., -500.),
+        object: terrain,
+    })];
+
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(Vec3(0.5, 0.7, 1.0))),
+        vec![],
+    )
 }
 
 const USE_BVH: bool = true;
+const BVH_SPLIT: ray_tracing::bvh::Split = ray_tracing::bvh::Split::Sah { bins: 16 };
+
+#[allow(unused)]
+fn image_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(0., 1.5, 6.);
+    let look_at = Vec3(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::material::Material;
+    use ray_tracing::raster;
+    use ray_tracing::shape::Quad;
+    use ray_tracing::texture::{self, Filter, Wrap};
+
+    let picture = raster::load("img/final-scene.png")
+        .expect("couldn't load image")
+        .decode_srgb();
+
+    let world: Vec<Box<dyn Object>> = vec![
+        // The picture wrapped around a globe, which stays stuck to it as it turns.
+        Box::new(object::Translate {
+            offset: Vec3(-1.2, 1., 0.),
+            object: object::rotate_y(
+                30.,
+                object::Sphere {
+                    radius: 1.,
+                    material: Material::Lambertian {
+                        albedo: texture::image(picture.clone(), Filter::Bilinear, Wrap::Repeat),
+                    },
+                },
+            ),
+        }),
+        // The picture as a glowing screen, magnified enough to show the filtering.
+        Box::new(Quad {
+            corner: Vec3(0.4, 0.2, -0.5),
+            u: Vec3(1.8, 0., 0.3),
+            v: Vec3(0., 1.6, 0.),
+            material: Material::DiffuseLight {
+                emission: texture::image(picture, Filter::Bicubic, Wrap::Clamp),
+                brightness: 1.,
+            },
+        }),
+        Box::new(Quad {
+            corner: Vec3(-10., 0., 10.),
+            u: Vec3(20., 0., 0.),
+            v: Vec3(0., 0., -20.),
+            material: Material::Lambertian {
+                albedo: texture::uv_checker(
+                    texture::constant(Vec3::from(0.8)),
+                    texture::constant(Vec3::from(0.2)),
+                    (20., 20.),
+                ),
+            },
+        }),
+    ];
+
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(Vec3(0.5, 0.7, 1.0))),
+        vec![],
+    )
+}
+
+#[allow(unused)]
+fn noise_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(0., 2., 9.);
+    let look_at = Vec3(0., 0.8, 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::material::Material;
+    use ray_tracing::shape::Plane;
+    use ray_tracing::texture;
+
+    let diffuse = |albedo| Material::Lambertian { albedo };
+    let world: Vec<Box<dyn Object>> = vec![
+        // A block cut from off the middle of a log, to show the rings on its faces.
+        Box::new(object::Translate {
+            offset: Vec3(-3.9, 0., 0.),
+            object: object::rect_prism(
+                Vec3(0.4, 0., -0.6),
+                Vec3(1.6, 1.2, 0.6),
+                diffuse(texture::wood(3., 1)),
+            ),
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(-0.9, 0.8, 0.),
+            object: object::Sphere {
+                radius: 0.8,
+                material: diffuse(texture::marble(3., 2)),
+            },
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(0.9, 0.8, 0.),
+            object: object::Sphere {
+                radius: 0.8,
+                material: diffuse(texture::granite(12., 3)),
+            },
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(2.7, 0.8, 0.),
+            object: object::Sphere {
+                radius: 0.8,
+                material: diffuse(texture::clouds(1.5, 0.5, 4)),
+            },
+        }),
+        Box::new(Plane {
+            point: Vec3::default(),
+            normal: Vec3(0., 1., 0.),
+            material: diffuse(texture::constant(Vec3::from(0.5))),
+        }),
+    ];
+
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(Vec3(0.9, 0.9, 1.0))),
+        vec![],
+    )
+}
+
+#[allow(unused)]
+fn texture_graph_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(0., 2., 9.);
+    let look_at = Vec3(0., 0.8, 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::material::Material;
+    use ray_tracing::shape::Plane;
+    use ray_tracing::texture;
+    use ray_tracing::texture_graph::Node;
+
+    let diffuse = |expression: &str| Material::Lambertian {
+        albedo: expression.parse::<Node>().unwrap().compile(),
+    };
+    let sphere = |x: f64, expression| -> Box<dyn Object> {
+        Box::new(object::Translate {
+            offset: Vec3(x, 0.8, 0.),
+            object: object::Sphere {
+                radius: 0.8,
+                material: diffuse(expression),
+            },
+        })
+    };
+    let world: Vec<Box<dyn Object>> = vec![
+        // Blue-veined marble.
+        sphere(
+            -2.7,
+            "ramp(warp(scale(4, 4, 4, fbm(simplex(7), 5)), fbm(perlin(3), 3), 0.3),
+                0.45, rgb(0.95, 0.95, 0.93), 0.5, rgb(0.2, 0.25, 0.5), 0.55, rgb(0.95, 0.95, 0.93))",
+        ),
+        // Cobblestones, with dark mortar between them.
+        sphere(
+            -0.9,
+            "mix(0.05, mul(rgb(0.6, 0.55, 0.5), fbm(perlin(2), 3)),
+                ramp(scale(3, 3, 3, worley(5, f2_minus_f1)), 0, 0, 0.08, 1))",
+        ),
+        // The test image, projected onto the sphere along each axis.
+        sphere(
+            0.9,
+            "triplanar(uv_transform(0.6, 0.6, 0.5, 0.5,
+                image(\"img/final-scene.png\", bilinear, mirror, srgb)), 4)",
+        ),
+        // Rocky stripes.
+        sphere(
+            2.7,
+            "ramp(add(mul(0.1, position), scale(2, 2, 2, ridged(simplex(9), 5))),
+                0.2, rgb(0.5, 0.3, 0.2), 0.4, rgb(0.9, 0.8, 0.6), 0.6, rgb(0.4, 0.4, 0.4))",
+        ),
+        Box::new(Plane {
+            point: Vec3::default(),
+            normal: Vec3(0., 1., 0.),
+            material: diffuse("mix(0.3, 0.6, scale(2, 2, 2, worley(1, f1)))"),
+        }),
+    ];
+
+    (
+        world,
+        camera,
+        exposure,
+        Some(Environment::Constant(Vec3(0.9, 0.9, 1.0))),
+        vec![],
+    )
+}
+
+#[allow(unused)]
+fn environment_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(0., 1.5, 7.);
+    let look_at = Vec3(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        50.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::environment::EnvironmentMap;
+    use ray_tracing::material::Material;
+    use ray_tracing::raster;
+    use ray_tracing::texture;
+
+    // Any image will do to try out environment maps, though only HDR images capture the full
+    // brightness of the sun or sky.
+    let picture = raster::load("img/final-scene.png")
+        .expect("Failed to load img/final-scene.png")
+        .decode_srgb();
+    let environment = Environment::Map(EnvironmentMap::new(picture, 0., 2.));
+
+    let world: Vec<Box<dyn Object>> = vec![
+        Box::new(object::Translate {
+            offset: Vec3(-1.1, 1., 0.),
+            object: object::Sphere {
+                radius: 1.,
+                material: Material::Lambertian {
+                    albedo: texture::constant(Vec3::from(0.8)),
+                },
+            },
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(1.1, 1., 0.),
+            object: object::Sphere {
+                radius: 1.,
+                material: Material::Metal {
+                    albedo: Vec3(0.9, 0.9, 0.9),
+                    fuzz: 0.05,
+                },
+            },
+        }),
+        Box::new(object::Rect {
+            orthogonal_to: object::StaticY,
+            range0: -4. ..4.,
+            range1: -4. ..4.,
+            k: 0.,
+            material: Material::Lambertian {
+                albedo: texture::constant(Vec3::from(0.5)),
+            },
+        }),
+    ];
+
+    (world, camera, exposure, Some(environment), vec![])
+}
+
+#[allow(unused)]
+fn sky_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(-14., 3., -16.);
+    let look_at = Vec3(0., 3., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        50.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::environment::Sky;
+    use ray_tracing::material::Material;
+    use ray_tracing::shape;
+    use ray_tracing::texture;
+
+    // A late afternoon sun on a clear day.
+    let sky = Sky::new(Vec3(-1., 0.5, 0.3), 3., Vec3::from(0.3), 0.03);
+
+    let concrete = Material::Lambertian {
+        albedo: texture::constant(Vec3(0.6, 0.58, 0.55)),
+    };
+    let mut world: Vec<Box<dyn Object>> = vec![Box::new(shape::Plane {
+        point: Vec3(0., 0., 0.),
+        normal: Vec3(0., 1., 0.),
+        material: Material::Lambertian {
+            albedo: texture::constant(Vec3::from(0.3)),
+        },
+    })];
+    // A few blocks of buildings, and a glass tower.
+    for &(x, z, width, depth, height) in &[
+        (-6., 2., 4., 6., 5.),
+        (0., 6., 5., 4., 9.),
+        (-1., -4., 3., 3., 3.),
+        (7., 0., 4., 8., 6.),
+    ] {
+        world.push(Box::new(object::rect_prism(
+            Vec3(x, 0., z),
+            Vec3(x + width, height, z + depth),
+            concrete.clone(),
+        )));
+    }
+    world.push(Box::new(object::rect_prism(
+        Vec3(3., 0., -2.),
+        Vec3(5.5, 14., 0.5),
+        Material::Metal {
+            albedo: Vec3(0.6, 0.7, 0.75),
+            fuzz: 0.02,
+        },
+    )));
+
+    (world, camera, exposure, Some(Environment::Sky(sky)), vec![])
+}
+
+#[allow(unused)]
+fn delta_lights_test(nx: usize, ny: usize) -> Scene {
+    let look_from = Vec3(0., 3., -9.);
+    let look_at = Vec3(0., 1., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.0;
+    let exposure = 0. ..1.;
+
+    let camera = Camera::look(
+        look_from,
+        look_at,
+        Vec3(0., 1., 0.),
+        40.,
+        nx as f64 / ny as f64,
+        aperture,
+        dist_to_focus,
+        exposure.clone(),
+    );
+
+    use ray_tracing::material::Material;
+    use ray_tracing::shape;
+    use ray_tracing::texture;
+
+    fn diffuse_color(c: Vec3) -> Material {
+        Material::Lambertian {
+            albedo: texture::constant(c),
+        }
+    }
+
+    let world: Vec<Box<dyn Object>> = vec![
+        Box::new(shape::Plane {
+            point: Vec3(0., 0., 0.),
+            normal: Vec3(0., 1., 0.),
+            material: diffuse_color(Vec3::from(0.5)),
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(-2., 1., 0.),
+            object: object::Sphere {
+                radius: 1.,
+                material: diffuse_color(Vec3(0.65, 0.05, 0.05)),
+            },
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(0.5, 0., 1.),
+            object: shape::Cylinder {
+                radius: 0.6,
+                height: 2.,
+                material: diffuse_color(Vec3::from(0.73)),
+            },
+        }),
+        Box::new(object::Translate {
+            offset: Vec3(2.5, 0.8, -0.5),
+            object: object::Sphere {
+                radius: 0.8,
+                material: Material::Metal {
+                    albedo: Vec3(0.8, 0.85, 0.9),
+                    fuzz: 0.2,
+                },
+            },
+        }),
+    ];
+
+    let lights = vec![
+        // A spotlight picking out the red ball.
+        Light::Spot {
+            position: Vec3(-3., 5., -3.),
+            direction: Vec3(1., -4., 3.),
+            intensity: Vec3(30., 28., 24.),
+            inner_angle: 12.,
+            outer_angle: 18.,
+        },
+        // A warm lamp off to the right.
+        Light::Point {
+            position: Vec3(4., 3., -2.),
+            intensity: Vec3(6., 4., 2.),
+        },
+        // Faint, bluish moonlight.
+        Light::Directional {
+            direction: Vec3(-1., -2., 1.),
+            irradiance: Vec3(0.05, 0.06, 0.1),
+        },
+    ];
+
+    (world, camera, exposure, None, lights)
+}
 
 fn main() {
     const NX: usize = 800;
     const NY: usize = 800;
     const NS: usize = 10_000;
+    let settings = RenderSettings::default();
 
     eprintln!(
         "Parallel casting {} x {} image using {}x oversampling.",
@@ -332,22 +1180,49 @@ fn main() {
     let mut rng = rand::rngs::SmallRng::seed_from_u64(0xDEADBEEF);
 
     // World
-    //let (world, camera, exposure) = cornell_box_scene(NX, NY);
-    //let (world, camera, exposure) = simple_light_scene(NX, NY, &mut rng);
-    //let (world, camera, exposure) = volume_test(NX, NY);
-    let (world, camera, exposure) = book_final_scene(NX, NY, &mut rng);
+    //let (world, camera, exposure, environment, lights) = cornell_box_scene(NX, NY);
+    //let (world, camera, exposure, environment, lights) = simple_light_scene(NX, NY, &mut rng);
+    //let (world, camera, exposure, environment, lights) = volume_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = instancing_test(NX, NY, &mut rng);
+    //let (world, camera, exposure, environment, lights) = csg_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = shapes_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = sdf_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = terrain_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = image_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = noise_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = texture_graph_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = environment_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = sky_test(NX, NY);
+    //let (world, camera, exposure, environment, lights) = delta_lights_test(NX, NY);
+    let (world, camera, exposure, environment, lights) = book_final_scene(NX, NY, &mut rng);
 
     let (image, time) = if USE_BVH {
         eprintln!("Generating bounding volume hierarchy.");
-        let world = ray_tracing::bvh::from_scene(world, exposure);
-        eprintln!("Done.");
         let start = Instant::now();
-        (par_cast(NX, NY, NS, &camera, world), start.elapsed())
+        let world = ray_tracing::bvh::Bvh::with_split(world, exposure, BVH_SPLIT);
+        eprintln!(
+            "Done in {:?}. Expected traversal cost {:.2}.",
+            start.elapsed(),
+            world.sah_cost()
+        );
+        let world = ray_tracing::bvh::FlatBvh::from(world);
+        let world = WithEnvironment { world, environment };
+        let world = WithLights { world, lights };
+        let start = Instant::now();
+        (
+            par_cast(NX, NY, NS, &settings, &camera, world),
+            start.elapsed(),
+        )
     } else {
         eprintln!("Testing every ray against every object.");
         let world: &[Box<dyn Object>] = &world;
+        let world = WithEnvironment { world, environment };
+        let world = WithLights { world, lights };
         let start = Instant::now();
-        (par_cast(NX, NY, NS, &camera, world), start.elapsed())
+        (
+            par_cast(NX, NY, NS, &settings, &camera, world),
+            start.elapsed(),
+        )
     };
 
     eprintln!("Took {:?} wall time", time);
@@ -1,7 +1,7 @@
 use rand::prelude::*;
 
 use crate::object::HitRecord;
-use crate::ray::Ray;
+use crate::ray::{Differentials, Ray};
 use crate::texture::Texture;
 use crate::vec3::{reflect, refract, Vec3};
 
@@ -51,28 +51,49 @@ impl Material {
     pub fn scatter(&self, ray: &Ray, hit: &HitRecord, rng: &mut impl Rng) -> Option<(Ray, Vec3)> {
         match self {
             Material::Lambertian { albedo } => {
-                let target = hit.p + hit.normal + Vec3::in_unit_sphere(rng);
+                // Offsetting the normal by a random unit vector gives directions in proportion to
+                // the cosine of their angle from the normal, as Lambert's law calls for.
+                let target = hit.p + hit.normal + Vec3::on_unit_sphere(rng);
+                // Diffuse rays spread out too much for differentials to be meaningful.
                 let scattered = Ray {
                     origin: hit.p,
                     direction: target - hit.p,
                     time: ray.time,
+                    differentials: None,
                 };
-                Some((scattered, albedo(hit.p)))
+                Some((scattered, albedo(hit)))
             }
             Material::Metal { albedo, fuzz } => {
-                let scattered = Ray {
-                    origin: hit.p,
-                    direction: reflect(ray.direction.into_unit(), hit.normal)
-                        + *fuzz * Vec3::in_unit_sphere(rng),
-                    ..*ray
-                };
-                if scattered.direction.dot(hit.normal) > 0. {
-                    Some((scattered, *albedo))
+                let unit_direction = ray.direction.into_unit();
+                // Only a polished surface reflects a coherent image that differentials can follow.
+                let differentials = if *fuzz == 0. {
+                    follow_differentials(ray, hit, |d| {
+                        reflect(unit_derivative(ray.direction, d), hit.normal)
+                    })
                 } else {
-                    // TODO: this is in the original, but has the odd effect of making metal an
-                    // emitter
                     None
+                };
+                let reflected = reflect(unit_direction, hit.normal);
+                if reflected.dot(hit.normal) <= 0. {
+                    // Reached from behind; there's no way out.
+                    return None;
                 }
+                // Fuzzed directions that would go into the surface are drawn again, rather than
+                // absorbed, so that the surface reflects as much light as `albedo` says. At
+                // least half of them are above the surface, so this soon finds one.
+                let direction = loop {
+                    let direction = reflected + *fuzz * Vec3::in_unit_sphere(rng);
+                    if direction.dot(hit.normal) > 0. {
+                        break direction;
+                    }
+                };
+                let scattered = Ray {
+                    origin: hit.p,
+                    direction,
+                    time: ray.time,
+                    differentials,
+                };
+                Some((scattered, *albedo))
             }
             Material::Dielectric { ref_idx } => {
                 let (outward_normal, ni_over_nt, cosine) = if ray.direction.dot(hit.normal) > 0. {
@@ -89,15 +110,35 @@ impl Material {
                     )
                 };
 
-                let direction = refract(ray.direction, outward_normal, ni_over_nt)
-                    .filter(|_| rng.gen::<f64>() >= schlick(cosine, *ref_idx))
-                    .unwrap_or_else(|| reflect(ray.direction, hit.normal));
+                let refracted = refract(ray.direction, outward_normal, ni_over_nt)
+                    .filter(|_| rng.gen::<f64>() >= schlick(cosine, *ref_idx));
+                let (direction, differentials) = match refracted {
+                    Some(direction) => {
+                        // Differentiate the refracted direction, ni_over_nt * i + mu * n for the
+                        // unit incoming direction i, holding the normal n fixed.
+                        let i = ray.direction.into_unit();
+                        let c = -i.dot(outward_normal);
+                        let root = (1. - ni_over_nt * ni_over_nt * (1. - c * c)).sqrt();
+                        let differentials = follow_differentials(ray, hit, |d| {
+                            let di = unit_derivative(ray.direction, d);
+                            let dc = -di.dot(outward_normal);
+                            let dmu = ni_over_nt * (1. - ni_over_nt * c / root) * dc;
+                            ni_over_nt * di + dmu * outward_normal
+                        });
+                        (direction, differentials)
+                    }
+                    None => (
+                        reflect(ray.direction, hit.normal),
+                        follow_differentials(ray, hit, |d| reflect(d, hit.normal)),
+                    ),
+                };
 
                 let attenuation = Vec3::from(1.);
                 let ray = Ray {
                     origin: hit.p,
                     direction,
                     time: ray.time,
+                    differentials,
                 };
 
                 Some((ray, attenuation))
@@ -107,19 +148,96 @@ impl Material {
                 Ray {
                     origin: hit.p,
                     direction: Vec3::in_unit_sphere(rng),
-                    ..*ray
+                    time: ray.time,
+                    differentials: None,
                 },
-                albedo(hit.p),
+                albedo(hit),
             )),
         }
     }
 
-    pub fn emitted(&self, p: Vec3) -> Vec3 {
+    /// Whether `scatter` sends light in exactly one direction (or one of a few, for glass), as
+    /// for mirrors, glass and lights, which don't scatter at all. Light can't be aimed at such
+    /// surfaces, and they have no meaningful `eval` or `pdf`.
+    pub fn is_specular(&self) -> bool {
+        match self {
+            Material::Metal { fuzz, .. } => *fuzz == 0.,
+            Material::Lambertian { .. } | Material::Isotropic { .. } => false,
+            Material::Dielectric { .. } | Material::DiffuseLight { .. } => true,
+        }
+    }
+
+    /// The fraction of light arriving at `hit` from `direction` that's scattered back along `ray`,
+    /// per unit solid angle, including the cosine of the angle to the surface normal. (This is the
+    /// [BSDF][bsdf] times that cosine.) The renderer uses this to add light from lights it aims at
+    /// directly.
+    ///
+    /// This is zero for specular materials.
+    ///
+    /// [bsdf]: https://en.wikipedia.org/wiki/Bidirectional_scattering_distribution_function
+    pub fn eval(&self, ray: &Ray, hit: &HitRecord, direction: Vec3) -> Vec3 {
+        // Each of these materials chooses directions in proportion to how much light they
+        // scatter, and attenuates them all alike, so the scattered fraction is the attenuation
+        // times the density of the direction.
+        match self {
+            Material::Lambertian { albedo } | Material::Isotropic { albedo } => {
+                self.pdf(ray, hit, direction) * albedo(hit)
+            }
+            Material::Metal { albedo, .. } if !self.is_specular() => {
+                self.pdf(ray, hit, direction) * *albedo
+            }
+            _ => Vec3::default(),
+        }
+    }
+
+    /// The probability density, per unit solid angle, of `scatter` choosing `direction` for light
+    /// arriving along `ray`.
+    ///
+    /// This is zero for specular materials.
+    pub fn pdf(&self, ray: &Ray, hit: &HitRecord, direction: Vec3) -> f64 {
+        use std::f64::consts::PI;
+
+        let direction = direction.into_unit();
+        match self {
+            Material::Lambertian { .. } => hit.normal.dot(direction).max(0.) / PI,
+            Material::Isotropic { .. } => 1. / (4. * PI),
+            Material::Metal { fuzz, .. } if !self.is_specular() => {
+                let reflected = reflect(ray.direction.into_unit(), hit.normal);
+                let height = reflected.dot(hit.normal) / fuzz;
+                if direction.dot(hit.normal) <= 0. || height <= 0. {
+                    return 0.;
+                }
+                // The direction is that of a point chosen evenly from the part above the surface
+                // of a ball of radius `fuzz` around the unit reflected direction, so its density
+                // is the volume of the ball's cone along the direction, per unit solid angle,
+                // over the volume of that part. The surface cuts a cap off the ball (of unit
+                // radius, after scaling) whose depth is 1 - `height`.
+                let cap = (1. - height).max(0.);
+                let above = 1. - cap * cap * (3. - cap) / 4.;
+                let cosine = direction.dot(reflected);
+                let discriminant = fuzz * fuzz - (1. - cosine * cosine);
+                if discriminant < 0. {
+                    return 0.;
+                }
+                // Distances along the direction where it enters and leaves the ball.
+                let far = cosine + discriminant.sqrt();
+                let near = (cosine - discriminant.sqrt()).max(0.);
+                if far <= 0. {
+                    return 0.;
+                }
+                (far.powi(3) - near.powi(3)) / (4. * PI * fuzz.powi(3) * above)
+            }
+            _ => 0.,
+        }
+    }
+
+    /// The light given off by the surface at `hit`.
+    pub fn emitted(&self, hit: &HitRecord) -> Vec3 {
         match self {
             Material::DiffuseLight {
                 emission,
                 brightness,
-            } => *brightness * emission(p),
+            } => *brightness * emission(hit),
             _ => Vec3::default(),
         }
     }
@@ -131,6 +249,31 @@ impl std::fmt::Debug for Material {
     }
 }
 
+/// Computes the differentials of a ray leaving the surface at `hit`, given the incoming `ray`,
+/// and `direction`, which maps the change in the incoming direction to the change in the
+/// outgoing one.
+fn follow_differentials(
+    ray: &Ray,
+    hit: &HitRecord,
+    direction: impl Fn(Vec3) -> Vec3,
+) -> Option<Differentials> {
+    let footprint = hit.footprint?;
+    let d = ray.differentials?;
+    Some(Differentials {
+        dx_origin: footprint.dpdx,
+        dx_direction: direction(d.dx_direction),
+        dy_origin: footprint.dpdy,
+        dy_direction: direction(d.dy_direction),
+    })
+}
+
+/// The change in the unit vector along `v` when `v` changes by `dv`.
+fn unit_derivative(v: Vec3, dv: Vec3) -> Vec3 {
+    let length = v.length();
+    let unit = v / length;
+    (dv - dv.dot(unit) * unit) / length
+}
+
 /// [Schlick's approximation][schlick] for computing reflection vs. refraction at a material
 /// surface.
 ///
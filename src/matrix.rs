@@ -0,0 +1,453 @@
+//! Matrices for affine transformations of 3D space.
+
+use crate::aabb::Aabb;
+use crate::vec3::Vec3;
+
+/// A 4x4 matrix representing an affine transformation, acting on points and vectors written as
+/// columns. Composing with `*` applies the right-hand transform first, so `a * b` means "`b`, then
+/// `a`".
+///
+/// Points are transformed with an implicit fourth coordinate of 1, so they are affected by
+/// translation; vectors have an implicit 0, so they are not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4(pub [[f64; 4]; 4]);
+
+impl Mat4 {
+    /// The transform that leaves everything where it is.
+    pub const IDENTITY: Mat4 = Mat4([
+        [1., 0., 0., 0.],
+        [0., 1., 0., 0.],
+        [0., 0., 1., 0.],
+        [0., 0., 0., 1.],
+    ]);
+
+    /// Translation by `offset`.
+    pub fn translation(offset: Vec3) -> Self {
+        Mat4([
+            [1., 0., 0., offset.0],
+            [0., 1., 0., offset.1],
+            [0., 0., 1., offset.2],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Scaling by `factor` along each axis.
+    pub fn scaling(factor: Vec3) -> Self {
+        Mat4([
+            [factor.0, 0., 0., 0.],
+            [0., factor.1, 0., 0.],
+            [0., 0., factor.2, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Rotation by `degrees` around the X axis, counter-clockwise when looking toward the origin.
+    pub fn rotation_x(degrees: f64) -> Self {
+        let (s, c) = degrees.to_radians().sin_cos();
+        Mat4([
+            [1., 0., 0., 0.],
+            [0., c, -s, 0.],
+            [0., s, c, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Rotation by `degrees` around the Y axis, in the same sense as `object::rotate_y`.
+    pub fn rotation_y(degrees: f64) -> Self {
+        let (s, c) = degrees.to_radians().sin_cos();
+        Mat4([
+            [c, 0., s, 0.],
+            [0., 1., 0., 0.],
+            [-s, 0., c, 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Rotation by `degrees` around the Z axis, counter-clockwise when looking toward the origin.
+    pub fn rotation_z(degrees: f64) -> Self {
+        let (s, c) = degrees.to_radians().sin_cos();
+        Mat4([
+            [c, -s, 0., 0.],
+            [s, c, 0., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Rotation by `degrees` around `axis`, counter-clockwise when looking along `axis` toward the
+    /// origin. `axis` need not be a unit vector.
+    pub fn rotation(axis: Vec3, degrees: f64) -> Self {
+        Quaternion::from_axis_angle(axis, degrees).into()
+    }
+
+    /// Rotation by the Euler angles `degrees`, given in degrees around the X, Y and Z axes. The
+    /// rotations are applied in that order, so this is `rotation_z * rotation_y * rotation_x`.
+    pub fn rotation_euler(degrees: Vec3) -> Self {
+        Mat4::rotation_z(degrees.2) * Mat4::rotation_y(degrees.1) * Mat4::rotation_x(degrees.0)
+    }
+
+    /// Shear, adding multiples of each coordinate to the others. For example, `xy` is the amount
+    /// of `y` added to `x`, so that `x' = x + xy * y + xz * z`.
+    pub fn shearing(xy: f64, xz: f64, yx: f64, yz: f64, zx: f64, zy: f64) -> Self {
+        Mat4([
+            [1., xy, xz, 0.],
+            [yx, 1., yz, 0.],
+            [zx, zy, 1., 0.],
+            [0., 0., 0., 1.],
+        ])
+    }
+
+    /// Swaps rows and columns.
+    pub fn transpose(&self) -> Self {
+        let m = &self.0;
+        Mat4(std::array::from_fn(|i| std::array::from_fn(|j| m[j][i])))
+    }
+
+    /// Computes the transform that undoes this one, or `None` if it collapses space onto a plane,
+    /// line or point and so can't be undone.
+    pub fn inverse(&self) -> Option<Self> {
+        // Gauss-Jordan elimination with partial pivoting, applied to `self` and the identity side
+        // by side.
+        let mut a = self.0;
+        let mut inv = Mat4::IDENTITY.0;
+
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1. / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+
+            for row in 0..4 {
+                if row != col {
+                    let factor = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[col][k];
+                        inv[row][k] -= factor * inv[col][k];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4(inv))
+    }
+
+    /// The factor by which the transform scales volumes, negative if it also mirrors them. Only
+    /// the upper-left 3x3 part, which acts on vectors, matters for an affine transform.
+    pub fn determinant(&self) -> f64 {
+        let m = &self.0;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Applies the transform to point `p`.
+    #[inline]
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * p.0 + m[0][1] * p.1 + m[0][2] * p.2 + m[0][3],
+            m[1][0] * p.0 + m[1][1] * p.1 + m[1][2] * p.2 + m[1][3],
+            m[2][0] * p.0 + m[2][1] * p.1 + m[2][2] * p.2 + m[2][3],
+        )
+    }
+
+    /// Applies the transform to vector `v`, ignoring translation.
+    #[inline]
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * v.0 + m[0][1] * v.1 + m[0][2] * v.2,
+            m[1][0] * v.0 + m[1][1] * v.1 + m[1][2] * v.2,
+            m[2][0] * v.0 + m[2][1] * v.1 + m[2][2] * v.2,
+        )
+    }
+
+    /// Multiplies vector `n` by the transpose of this matrix, ignoring translation.
+    ///
+    /// Surface normals don't transform like ordinary vectors: to keep them perpendicular to the
+    /// surface, they must be multiplied by the inverse-transpose of the transform applied to the
+    /// surface. So call this on the *inverse* of that transform. The result is not normalized.
+    #[inline]
+    pub fn transform_normal(&self, n: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3(
+            m[0][0] * n.0 + m[1][0] * n.1 + m[2][0] * n.2,
+            m[0][1] * n.0 + m[1][1] * n.1 + m[2][1] * n.2,
+            m[0][2] * n.0 + m[1][2] * n.1 + m[2][2] * n.2,
+        )
+    }
+
+    /// Computes the smallest box containing every point of `b` after transformation.
+    ///
+    /// Each coordinate of the result is a sum of terms, one per input axis, and each term is
+    /// minimized (or maximized) independently by picking the right end of that axis. This is the
+    /// same as transforming all eight corners, at a fraction of the cost.
+    pub(crate) fn transform_box(&self, b: Aabb) -> Aabb {
+        let m = &self.0;
+        let mut min = [0.; 3];
+        let mut max = [0.; 3];
+        for i in 0..3 {
+            min[i] = m[i][3];
+            max[i] = m[i][3];
+            for (j, (lo, hi)) in [(b.min.0, b.max.0), (b.min.1, b.max.1), (b.min.2, b.max.2)]
+                .into_iter()
+                .enumerate()
+            {
+                if m[i][j] == 0. {
+                    // Skip, rather than multiply an unbounded box's infinities by zero.
+                    continue;
+                }
+                let (from_lo, from_hi) = (m[i][j] * lo, m[i][j] * hi);
+                min[i] += from_lo.min(from_hi);
+                max[i] += from_lo.max(from_hi);
+            }
+        }
+        Aabb {
+            min: Vec3(min[0], min[1], min[2]),
+            max: Vec3(max[0], max[1], max[2]),
+        }
+    }
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::IDENTITY
+    }
+}
+
+/// Composition: `a * b` applies `b`, then `a`.
+impl std::ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Mat4) -> Self::Output {
+        let (a, b) = (&self.0, &rhs.0);
+        Mat4(std::array::from_fn(|i| {
+            std::array::from_fn(|j| (0..4).map(|k| a[i][k] * b[k][j]).sum())
+        }))
+    }
+}
+
+impl From<Quaternion> for Mat4 {
+    /// The rotation represented by `q`, which is normalized first.
+    fn from(q: Quaternion) -> Self {
+        let Quaternion { w, x, y, z } = q.normalize();
+        Mat4([
+            [
+                1. - 2. * (y * y + z * z),
+                2. * (x * y - w * z),
+                2. * (x * z + w * y),
+                0.,
+            ],
+            [
+                2. * (x * y + w * z),
+                1. - 2. * (x * x + z * z),
+                2. * (y * z - w * x),
+                0.,
+            ],
+            [
+                2. * (x * z - w * y),
+                2. * (y * z + w * x),
+                1. - 2. * (x * x + y * y),
+                0.,
+            ],
+            [0., 0., 0., 1.],
+        ])
+    }
+}
+
+/// A quaternion `w + xi + yj + zk`. Unit quaternions represent rotations, and are convenient for
+/// composing them and interpolating between them; convert to a `Mat4` to apply one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    /// The quaternion representing no rotation.
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.,
+        x: 0.,
+        y: 0.,
+        z: 0.,
+    };
+
+    /// The rotation by `degrees` around `axis`, in the same sense as `Mat4::rotation`.
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Self {
+        let (s, c) = (degrees.to_radians() / 2.).sin_cos();
+        let axis = axis.into_unit();
+        Quaternion {
+            w: c,
+            x: s * axis.0,
+            y: s * axis.1,
+            z: s * axis.2,
+        }
+    }
+
+    /// Scales the quaternion to unit length, so that it represents a pure rotation.
+    pub fn normalize(self) -> Self {
+        let len = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        Quaternion {
+            w: self.w / len,
+            x: self.x / len,
+            y: self.y / len,
+            z: self.z / len,
+        }
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion::IDENTITY
+    }
+}
+
+/// The Hamilton product. As with `Mat4`, `a * b` is the rotation `b`, then `a`.
+impl std::ops::Mul for Quaternion {
+    type Output = Quaternion;
+
+    fn mul(self, rhs: Quaternion) -> Self::Output {
+        let (a, b) = (self, rhs);
+        Quaternion {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that `a` and `b` agree to within rounding error.
+    fn assert_close(a: Mat4, b: Mat4) {
+        for (row_a, row_b) in a.0.iter().zip(&b.0) {
+            for (x, y) in row_a.iter().zip(row_b) {
+                assert!((x - y).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    fn assert_close_vec(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    /// A transform using every kind of matrix.
+    fn everything() -> Mat4 {
+        Mat4::translation(Vec3(1., -2., 3.))
+            * Mat4::rotation(Vec3(1., 2., 3.), 40.)
+            * Mat4::shearing(0.5, 0., 0.2, 0., 0., -0.3)
+            * Mat4::scaling(Vec3(2., 0.5, -3.))
+            * Mat4::rotation_euler(Vec3(10., 20., 30.))
+    }
+
+    #[test]
+    fn inverse_round_trip() {
+        let m = everything();
+        let inverse = m.inverse().unwrap();
+        assert_close(m * inverse, Mat4::IDENTITY);
+        assert_close(inverse * m, Mat4::IDENTITY);
+        assert_close(inverse.inverse().unwrap(), m);
+
+        let p = Vec3(0.3, -4., 7.);
+        assert_close_vec(inverse.transform_point(m.transform_point(p)), p);
+        assert_close_vec(inverse.transform_vector(m.transform_vector(p)), p);
+    }
+
+    #[test]
+    fn inverse_needs_pivoting() {
+        // Zero on the diagonal, so elimination must swap rows.
+        let m = Mat4([
+            [0., 1., 0., 2.],
+            [1., 0., 0., 3.],
+            [0., 0., 2., 4.],
+            [0., 0., 0., 1.],
+        ]);
+        assert_close(m * m.inverse().unwrap(), Mat4::IDENTITY);
+    }
+
+    #[test]
+    fn singular_matrices() {
+        assert_eq!(Mat4::scaling(Vec3(1., 0., 1.)).inverse(), None);
+        let flattening = Mat4([
+            [1., 2., 3., 0.],
+            [2., 4., 6., 0.],
+            [0., 0., 1., 0.],
+            [0., 0., 0., 1.],
+        ]);
+        assert_eq!(flattening.inverse(), None);
+        assert_eq!(flattening.determinant(), 0.);
+    }
+
+    #[test]
+    fn determinant() {
+        assert_eq!(Mat4::scaling(Vec3(2., 3., -4.)).determinant(), -24.);
+        assert!((Mat4::rotation(Vec3(1., 1., 0.), 33.).determinant() - 1.).abs() < 1e-12);
+        assert_eq!(Mat4::shearing(1., 2., 3., 4., 5., 6.).determinant(), 20.);
+    }
+
+    #[test]
+    fn rotations_agree() {
+        assert_close(Mat4::rotation(Vec3(1., 0., 0.), 30.), Mat4::rotation_x(30.));
+        assert_close(
+            Mat4::rotation(Vec3(0., 2., 0.), -75.),
+            Mat4::rotation_y(-75.),
+        );
+        assert_close(
+            Mat4::rotation(Vec3(0., 0., 1.), 120.),
+            Mat4::rotation_z(120.),
+        );
+        // A quarter turn around Z takes X to Y.
+        assert_close_vec(
+            Mat4::rotation_z(90.).transform_vector(Vec3(1., 0., 0.)),
+            Vec3(0., 1., 0.),
+        );
+
+        let a = Quaternion::from_axis_angle(Vec3(1., 2., 3.), 50.);
+        let b = Quaternion::from_axis_angle(Vec3(-1., 0., 1.), 20.);
+        assert_close(Mat4::from(a * b), Mat4::from(a) * Mat4::from(b));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        let m = everything();
+        let inverse = m.inverse().unwrap();
+        let (tangent, bitangent) = (Vec3(1., 2., 0.), Vec3(0., -1., 3.));
+        let normal = tangent.cross(&bitangent);
+        let moved = inverse.transform_normal(normal);
+        assert!(moved.dot(m.transform_vector(tangent)).abs() < 1e-9);
+        assert!(moved.dot(m.transform_vector(bitangent)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn transformed_box() {
+        let m = everything();
+        let b = Aabb {
+            min: Vec3(-1., 0., 2.),
+            max: Vec3(3., 1., 5.),
+        };
+        let moved = m.transform_box(b);
+        let corners: Vec<Vec3> = b.corners().map(|c| m.transform_point(c)).collect();
+        for axis in 0..3 {
+            let ends = |v: Vec3| [v.0, v.1, v.2][axis];
+            let min = corners.iter().map(|&c| ends(c)).fold(f64::MAX, f64::min);
+            let max = corners.iter().map(|&c| ends(c)).fold(f64::MIN, f64::max);
+            assert!((ends(moved.min) - min).abs() < 1e-9);
+            assert!((ends(moved.max) - max).abs() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! Triangles and indexed triangle meshes.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::object::{HitRecord, Object};
+use crate::ray::Ray;
+use crate::vec3::{Axis::*, Vec3};
+
+/// A single triangle with a flat normal.
+///
+/// The normal follows the right-hand rule: it faces a viewer who sees the vertices in
+/// counter-clockwise order. The surface coordinates at a hit are the barycentric weights of the
+/// second and third vertices.
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    /// Corners of the triangle.
+    pub vertices: [Vec3; 3],
+    /// Triangle material.
+    pub material: Material,
+}
+
+impl Object for Triangle {
+    #[inline]
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let [v0, v1, v2] = self.vertices;
+        intersect(ray, v0, v1, v2, t_range).map(|(t, b1, b2)| {
+            let p = ray.point_at_parameter(t);
+            HitRecord {
+                t,
+                p,
+                normal: (v1 - v0).cross(&(v2 - v0)).into_unit(),
+                uv: (b1, b2),
+                tangent: v1 - v0,
+                bitangent: v2 - v0,
+                local_p: p,
+                footprint: None,
+                face: None,
+                material: &self.material,
+            }
+        })
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        triangle_bounds(self.vertices)
+    }
+}
+
+/// Intersects `ray` with the triangle `v0 v1 v2` using the [Möller–Trumbore algorithm][mt].
+///
+/// If the ray hits the triangle within `t_range`, returns the `t` of the hit and the barycentric
+/// weights of `v1` and `v2` at that point. (The weight of `v0` is one minus the other two.)
+///
+/// [mt]: https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+#[inline]
+pub(crate) fn intersect(
+    ray: &Ray,
+    v0: Vec3,
+    v1: Vec3,
+    v2: Vec3,
+    t_range: Range<f64>,
+) -> Option<(f64, f64, f64)> {
+    let e1 = v1 - v0;
+    let e2 = v2 - v0;
+    let pvec = ray.direction.cross(&e2);
+    let det = e1.dot(pvec);
+    if det.abs() < f64::MIN_POSITIVE {
+        // Ray is parallel to the triangle's plane.
+        return None;
+    }
+    let inv_det = 1. / det;
+
+    let tvec = ray.origin - v0;
+    let b1 = tvec.dot(pvec) * inv_det;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+
+    let qvec = tvec.cross(&e1);
+    let b2 = ray.direction.dot(qvec) * inv_det;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+
+    let t = e2.dot(qvec) * inv_det;
+    if t < t_range.start || t >= t_range.end {
+        return None;
+    }
+
+    Some((t, b1, b2))
+}
+
+/// Computes the bounding box of a triangle.
+fn triangle_bounds([a, b, c]: [Vec3; 3]) -> Aabb {
+    // Triangles lying in an axis-aligned plane would have a box of zero thickness, which
+    // `Aabb::hit` never reports as hit.
+    Aabb {
+        min: a.zip_with3(b, c, |a, b, c| a.min(b).min(c)),
+        max: a.zip_with3(b, c, |a, b, c| a.max(b).max(c)),
+    }
+    .pad_flat()
+}
+
+/// Maximum number of triangles stored in a leaf of a mesh's internal hierarchy.
+const LEAF_SIZE: usize = 4;
+
+/// Vertex and face data for a `TriangleMesh`, along with a bounding volume hierarchy over its
+/// faces.
+///
+/// The geometry is kept apart from the mesh's material and shared by `Arc`, so that several meshes
+/// can reuse the same (potentially very large) vertex data.
+pub struct MeshData {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    colors: Option<Vec<Vec3>>,
+    /// Vertex indices of each face. These are reordered during construction so that each leaf of
+    /// `nodes` refers to a contiguous run.
+    triangles: Vec<[u32; 3]>,
+    /// Hierarchy nodes in depth-first order, root first.
+    nodes: Vec<Node>,
+}
+
+/// A node in the hierarchy of a `MeshData`.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    /// For a leaf, the index of its first triangle. For an interior node, the index of its second
+    /// child; the first child always immediately follows its parent.
+    offset: u32,
+    /// Number of triangles in a leaf, or zero for an interior node.
+    count: u32,
+}
+
+/// A triangle being sorted into the hierarchy during construction.
+struct BuildItem {
+    bounds: Aabb,
+    centroid: Vec3,
+    triangle: [u32; 3],
+}
+
+impl MeshData {
+    /// Creates mesh geometry from vertex `positions` and `triangles` of indices into them, and
+    /// builds its internal hierarchy.
+    ///
+    /// Faces use the same winding convention as `Triangle`.
+    ///
+    /// # Panics
+    ///
+    /// If `triangles` is empty, or refers to a vertex outside `positions`.
+    pub fn new(positions: Vec<Vec3>, triangles: Vec<[u32; 3]>) -> Self {
+        assert!(
+            !triangles.is_empty(),
+            "Can't create a mesh from zero triangles."
+        );
+        assert!(
+            triangles
+                .iter()
+                .flatten()
+                .all(|&i| (i as usize) < positions.len()),
+            "Mesh triangle refers to a nonexistent vertex."
+        );
+
+        let mut items: Vec<BuildItem> = triangles
+            .into_iter()
+            .map(|triangle| {
+                let corners = triangle.map(|i| positions[i as usize]);
+                let bounds = triangle_bounds(corners);
+                BuildItem {
+                    bounds,
+                    centroid: (bounds.min + bounds.max) / 2.,
+                    triangle,
+                }
+            })
+            .collect();
+
+        let mut nodes = Vec::with_capacity(2 * items.len() / LEAF_SIZE + 1);
+        build_node(&mut items, 0, &mut nodes);
+
+        MeshData {
+            positions,
+            normals: None,
+            uvs: None,
+            colors: None,
+            triangles: items.into_iter().map(|item| item.triangle).collect(),
+            nodes,
+        }
+    }
+
+    /// Attaches per-vertex `normals`, which are interpolated across each face for smooth shading.
+    ///
+    /// # Panics
+    ///
+    /// If there is not exactly one normal per vertex.
+    pub fn with_normals(self, normals: Vec<Vec3>) -> Self {
+        assert_eq!(normals.len(), self.positions.len());
+        MeshData {
+            normals: Some(normals),
+            ..self
+        }
+    }
+
+    /// Attaches per-vertex surface coordinates, which are interpolated across each face.
+    ///
+    /// # Panics
+    ///
+    /// If there is not exactly one coordinate pair per vertex.
+    pub fn with_uvs(self, uvs: Vec<(f64, f64)>) -> Self {
+        assert_eq!(uvs.len(), self.positions.len());
+        MeshData {
+            uvs: Some(uvs),
+            ..self
+        }
+    }
+
+    /// Attaches per-vertex (linear) colors. These can be applied to the mesh's material using
+    /// `texture::vertex_colors`.
+    ///
+    /// # Panics
+    ///
+    /// If there is not exactly one color per vertex.
+    pub fn with_colors(self, colors: Vec<Vec3>) -> Self {
+        assert_eq!(colors.len(), self.positions.len());
+        MeshData {
+            colors: Some(colors),
+            ..self
+        }
+    }
+
+    /// Vertex positions.
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// Per-vertex normals, if any.
+    pub fn normals(&self) -> Option<&[Vec3]> {
+        self.normals.as_deref()
+    }
+
+    /// Per-vertex surface coordinates, if any.
+    pub fn uvs(&self) -> Option<&[(f64, f64)]> {
+        self.uvs.as_deref()
+    }
+
+    /// Per-vertex colors, if any.
+    pub fn colors(&self) -> Option<&[Vec3]> {
+        self.colors.as_deref()
+    }
+
+    /// Vertex indices of each face. Note that these are not necessarily in the order given to
+    /// `new`.
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    /// Bounding box of the whole mesh.
+    pub(crate) fn bounds(&self) -> Aabb {
+        self.nodes[0].bounds
+    }
+
+    /// Finds the nearest face hit by `ray` within `t_range`. Returns the face's index, the `t` of
+    /// the hit, and the barycentric weights of its second and third vertices.
+    fn hit_face(&self, ray: &Ray, t_range: Range<f64>) -> Option<(usize, f64, f64, f64)> {
+        // Median splits halve the triangle count at each level, so this is deep enough for any
+        // mesh indexable by `u32`.
+        let mut stack = [0u32; 64];
+        let mut len = 1;
+
+        let inv_d = ray.direction.map(|x| 1. / x);
+        let mut nearest = None;
+        let mut t_end = t_range.end;
+
+        while len > 0 {
+            len -= 1;
+            let index = stack[len];
+            let node = &self.nodes[index as usize];
+            if !node.bounds.hit_inv(ray.origin, inv_d, t_range.start..t_end) {
+                continue;
+            }
+
+            if node.count == 0 {
+                // Push the second child first, so the first child is visited first.
+                stack[len] = node.offset;
+                stack[len + 1] = index + 1;
+                len += 2;
+            } else {
+                let first = node.offset as usize;
+                for i in first..first + node.count as usize {
+                    let [a, b, c] = self.triangles[i];
+                    let hit = intersect(
+                        ray,
+                        self.positions[a as usize],
+                        self.positions[b as usize],
+                        self.positions[c as usize],
+                        t_range.start..t_end,
+                    );
+                    if let Some((t, b1, b2)) = hit {
+                        t_end = t;
+                        nearest = Some((i, t, b1, b2));
+                    }
+                }
+            }
+        }
+
+        nearest
+    }
+
+    /// Interpolates a per-vertex attribute across `face` using barycentric weights `b1` and `b2`.
+    pub(crate) fn interpolate(&self, attribute: &[Vec3], face: usize, b1: f64, b2: f64) -> Vec3 {
+        let [i0, i1, i2] = self.triangles[face].map(|i| i as usize);
+        (1. - b1 - b2) * attribute[i0] + b1 * attribute[i1] + b2 * attribute[i2]
+    }
+}
+
+impl std::fmt::Debug for MeshData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MeshData")
+            .field("vertices", &self.positions.len())
+            .field("triangles", &self.triangles.len())
+            .field("normals", &self.normals.is_some())
+            .field("uvs", &self.uvs.is_some())
+            .field("colors", &self.colors.is_some())
+            .finish()
+    }
+}
+
+/// Recursively builds the hierarchy over `items`, appending nodes to `nodes` in depth-first
+/// order. `first` is the index of `items[0]` in the final triangle list.
+fn build_node(items: &mut [BuildItem], first: usize, nodes: &mut Vec<Node>) {
+    let bounds = items[1..]
+        .iter()
+        .fold(items[0].bounds, |bb, item| bb.merge(item.bounds));
+
+    let index = nodes.len();
+    nodes.push(Node {
+        bounds,
+        offset: first as u32,
+        count: items.len() as u32,
+    });
+
+    if items.len() <= LEAF_SIZE {
+        return;
+    }
+
+    // Split at the median centroid along the axis where the centroids are most spread out.
+    let (cmin, cmax) = items[1..].iter().fold(
+        (items[0].centroid, items[0].centroid),
+        |(min, max), item| {
+            (
+                min.zip_with(item.centroid, f64::min),
+                max.zip_with(item.centroid, f64::max),
+            )
+        },
+    );
+    let extent = cmax - cmin;
+    let axis = if extent[X] >= extent[Y] && extent[X] >= extent[Z] {
+        X
+    } else if extent[Y] >= extent[Z] {
+        Y
+    } else {
+        Z
+    };
+
+    let mid = items.len() / 2;
+    items.select_nth_unstable_by(mid, |a, b| {
+        a.centroid[axis].partial_cmp(&b.centroid[axis]).unwrap()
+    });
+
+    let (left, right) = items.split_at_mut(mid);
+    build_node(left, first, nodes);
+    let second = nodes.len() as u32;
+    build_node(right, first + mid, nodes);
+
+    nodes[index].offset = second;
+    nodes[index].count = 0;
+}
+
+/// Finds the derivatives of position with respect to surface coordinates across a triangle with
+/// edges `e1` and `e2` from its first vertex, given the surface coordinates at each vertex. If the
+/// surface coordinates don't span an area, falls back to the edges themselves.
+fn uv_tangents(
+    e1: Vec3,
+    e2: Vec3,
+    uv0: (f64, f64),
+    uv1: (f64, f64),
+    uv2: (f64, f64),
+) -> (Vec3, Vec3) {
+    let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+    let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+    let det = du1 * dv2 - du2 * dv1;
+    if det.abs() < 1e-12 {
+        return (e1, e2);
+    }
+    ((dv2 * e1 - dv1 * e2) / det, (du1 * e2 - du2 * e1) / det)
+}
+
+/// A mesh of triangles sharing vertices, with optional per-vertex normals and surface
+/// coordinates.
+///
+/// The mesh carries its own bounding volume hierarchy, so even a very large mesh can be placed in
+/// a scene as a single object.
+///
+/// ```
+/// use std::sync::Arc;
+///
+/// use ray_tracing::material::Material;
+/// use ray_tracing::mesh::{MeshData, TriangleMesh};
+/// use ray_tracing::texture;
+/// use ray_tracing::vec3::Vec3;
+///
+/// // A unit square in the XY plane, facing +Z.
+/// let square = MeshData::new(
+///     vec![Vec3(0., 0., 0.), Vec3(1., 0., 0.), Vec3(1., 1., 0.), Vec3(0., 1., 0.)],
+///     vec![[0, 1, 2], [0, 2, 3]],
+/// );
+/// let mesh = TriangleMesh {
+///     geometry: Arc::new(square),
+///     material: Material::Lambertian {
+///         albedo: texture::constant(Vec3::from(0.5)),
+///     },
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct TriangleMesh {
+    /// Shape of the mesh.
+    pub geometry: Arc<MeshData>,
+    /// Material of every face in the mesh.
+    pub material: Material,
+}
+
+impl Object for TriangleMesh {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let mesh = &*self.geometry;
+        let (face, t, b1, b2) = mesh.hit_face(ray, t_range)?;
+        let b0 = 1. - b1 - b2;
+        let [i0, i1, i2] = mesh.triangles[face].map(|i| i as usize);
+
+        let normal = match &mesh.normals {
+            Some(n) => mesh.interpolate(n, face, b1, b2).into_unit(),
+            None => {
+                let p = &mesh.positions;
+                (p[i1] - p[i0]).cross(&(p[i2] - p[i0])).into_unit()
+            }
+        };
+        let p = &mesh.positions;
+        let (e1, e2) = (p[i1] - p[i0], p[i2] - p[i0]);
+        let (uv, (tangent, bitangent)) = match &mesh.uvs {
+            Some(uv) => (
+                (
+                    b0 * uv[i0].0 + b1 * uv[i1].0 + b2 * uv[i2].0,
+                    b0 * uv[i0].1 + b1 * uv[i1].1 + b2 * uv[i2].1,
+                ),
+                uv_tangents(e1, e2, uv[i0], uv[i1], uv[i2]),
+            ),
+            None => ((b1, b2), (e1, e2)),
+        };
+
+        let p = ray.point_at_parameter(t);
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            uv,
+            tangent,
+            bitangent,
+            local_p: p,
+            footprint: None,
+            face: Some((face, b1, b2)),
+            material: &self.material,
+        })
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        self.geometry.bounds()
+    }
+}
@@ -0,0 +1,328 @@
+//! Procedural noise, for building textures.
+//!
+//! The basic noise functions (`Perlin`, `Simplex` and `Worley`) are smooth or cellular
+//! pseudo-random patterns with features about one unit across. The combinators (`Fbm`, `Ridged`
+//! and `Warp`) build more natural-looking patterns out of them, by adding together copies at
+//! different scales or distorting one with another. Everything is generated from explicit seeds,
+//! so the same seed always gives the same pattern.
+//!
+//! ```
+//! use ray_tracing::noise::{Fbm, Noise, Simplex, Warp};
+//! use ray_tracing::vec3::Vec3;
+//!
+//! // Billowing, swirled noise, as for smoke.
+//! let smoke = Warp {
+//!     noise: Fbm::new(Simplex::new(1), 5),
+//!     warp: Fbm::new(Simplex::new(2), 3),
+//!     strength: 0.8,
+//! };
+//! let value = smoke.sample(Vec3(0.5, 1.5, 2.5));
+//! assert!(value.abs() <= 1.);
+//! ```
+
+use std::sync::Arc;
+
+use rand::prelude::*;
+use rand::rngs::SmallRng;
+
+use crate::perlin::Perlin;
+use crate::vec3::Vec3;
+
+/// A pattern of values varying over space.
+pub trait Noise: Send + Sync {
+    /// The value of the pattern at `p`. Unless noted otherwise, this is between -1 and 1.
+    fn sample(&self, p: Vec3) -> f64;
+}
+
+impl<N: Noise + ?Sized> Noise for Arc<N> {
+    fn sample(&self, p: Vec3) -> f64 {
+        (**self).sample(p)
+    }
+}
+
+impl Noise for Perlin {
+    fn sample(&self, p: Vec3) -> f64 {
+        self.noise(p)
+    }
+}
+
+/// Ken Perlin's [simplex noise][simplex]: smooth noise like `Perlin`, but with fewer directional
+/// artifacts, computed on a grid of tetrahedra rather than cubes.
+///
+/// [simplex]: https://en.wikipedia.org/wiki/Simplex_noise
+#[derive(Debug, Clone)]
+pub struct Simplex {
+    /// A random permutation of 0 to 255, repeated twice so lookups needn't wrap.
+    perm: Vec<u8>,
+}
+
+impl Simplex {
+    /// Generates the noise field for `seed`.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut perm: Vec<u8> = (0..=255).collect();
+        perm.shuffle(&mut rng);
+        perm.extend_from_within(..);
+        Simplex { perm }
+    }
+
+    fn hash(&self, i: i64, j: i64, k: i64) -> usize {
+        let p = |n: i64| self.perm[(n & 255) as usize] as i64;
+        p(i + p(j + p(k))) as usize
+    }
+}
+
+/// Gradient directions for simplex noise: the midpoints of the edges of a cube.
+const SIMPLEX_GRADIENTS: [Vec3; 12] = [
+    Vec3(1., 1., 0.),
+    Vec3(-1., 1., 0.),
+    Vec3(1., -1., 0.),
+    Vec3(-1., -1., 0.),
+    Vec3(1., 0., 1.),
+    Vec3(-1., 0., 1.),
+    Vec3(1., 0., -1.),
+    Vec3(-1., 0., -1.),
+    Vec3(0., 1., 1.),
+    Vec3(0., -1., 1.),
+    Vec3(0., 1., -1.),
+    Vec3(0., -1., -1.),
+];
+
+impl Noise for Simplex {
+    fn sample(&self, p: Vec3) -> f64 {
+        // Skew space so the tetrahedral grid becomes a grid of cubes, each split into six
+        // tetrahedra, and find the cube and tetrahedron containing `p`.
+        const SKEW: f64 = 1. / 3.;
+        const UNSKEW: f64 = 1. / 6.;
+        let s = (p.0 + p.1 + p.2) * SKEW;
+        let cell = (p + Vec3::from(s)).map(f64::floor);
+        let t = (cell.0 + cell.1 + cell.2) * UNSKEW;
+        let d0 = p - (cell - Vec3::from(t));
+
+        // Walk from the cube's lowest corner to its highest, along whichever axes `p` is furthest.
+        let mut axes = [(d0.0, 0), (d0.1, 1), (d0.2, 2)];
+        axes.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let mut offsets = [[0i64; 3]; 4];
+        for step in 1..4 {
+            offsets[step] = offsets[step - 1];
+            offsets[step][axes[step - 1].1] += 1;
+        }
+
+        let (i, j, k) = (cell.0 as i64, cell.1 as i64, cell.2 as i64);
+        let sum: f64 = offsets
+            .iter()
+            .enumerate()
+            .map(|(n, o)| {
+                let d = d0 - Vec3(o[0] as f64, o[1] as f64, o[2] as f64)
+                    + Vec3::from(n as f64 * UNSKEW);
+                let falloff = 0.6 - d.dot(d);
+                if falloff <= 0. {
+                    return 0.;
+                }
+                let gradient = SIMPLEX_GRADIENTS[self.hash(i + o[0], j + o[1], k + o[2]) % 12];
+                falloff.powi(4) * gradient.dot(d)
+            })
+            .sum();
+        // Scale the result to fill the range -1 to 1.
+        32. * sum
+    }
+}
+
+/// Which distance `Worley` noise reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Distance to the nearest feature point, which gives rounded cells.
+    F1,
+    /// Distance to the second-nearest feature point.
+    F2,
+    /// The difference between the two, which is zero along the borders between cells, giving a
+    /// pattern like cracked mud or cobblestones.
+    F2MinusF1,
+}
+
+/// [Worley noise][worley], also known as cellular noise: the distance from each point to random
+/// feature points scattered through space, one in each unit cube.
+///
+/// Unlike the other noise functions, values are between 0 and a little over 1.
+///
+/// [worley]: https://en.wikipedia.org/wiki/Worley_noise
+#[derive(Debug, Clone, Copy)]
+pub struct Worley {
+    seed: u64,
+    feature: Feature,
+}
+
+impl Worley {
+    /// Generates the noise field for `seed`, reporting `feature`.
+    pub fn new(seed: u64, feature: Feature) -> Self {
+        Worley { seed, feature }
+    }
+
+    /// The feature point in the unit cube with lowest corner `(i, j, k)`.
+    fn point(&self, i: i64, j: i64, k: i64) -> Vec3 {
+        // Hash the cell and seed together (using the SplitMix64 finalizer), and take three
+        // fractions from the bits of the result.
+        let mut h = self.seed
+            ^ (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (j as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+            ^ (k as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+        h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        h ^= h >> 31;
+        let fraction = |bits: u64| (bits & 0x1F_FFFF) as f64 / 0x20_0000 as f64;
+        Vec3(i as f64, j as f64, k as f64) + Vec3(fraction(h), fraction(h >> 21), fraction(h >> 42))
+    }
+}
+
+impl Noise for Worley {
+    fn sample(&self, p: Vec3) -> f64 {
+        let cell = p.map(f64::floor);
+        let (i, j, k) = (cell.0 as i64, cell.1 as i64, cell.2 as i64);
+        // Only the neighboring cells are searched. That almost always finds the nearest point, but
+        // the second-nearest (and, rarely, the nearest) can lie further out; this approximation
+        // is the usual one, and its errors are too small to see.
+        let (mut f1, mut f2) = (f64::INFINITY, f64::INFINITY);
+        for di in -1..=1 {
+            for dj in -1..=1 {
+                for dk in -1..=1 {
+                    let d = (self.point(i + di, j + dj, k + dk) - p).length();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        match self.feature {
+            Feature::F1 => f1,
+            Feature::F2 => f2,
+            Feature::F2MinusF1 => f2 - f1,
+        }
+    }
+}
+
+/// Fractional Brownian motion: the sum of several copies, or octaves, of `noise`, each at a
+/// higher frequency and lower amplitude than the one before. This gives a pattern with detail at
+/// every scale, like terrain or clouds.
+///
+/// The result is divided by the total amplitude of the octaves, so it stays in the same range as
+/// `noise`.
+#[derive(Debug, Clone)]
+pub struct Fbm<N> {
+    pub noise: N,
+    /// Number of copies of `noise` added together.
+    pub octaves: usize,
+    /// Factor by which the frequency of each octave exceeds the last, usually about 2.
+    pub lacunarity: f64,
+    /// Factor by which the amplitude of each octave is smaller than the last, usually about 0.5.
+    /// Higher values give rougher patterns.
+    pub gain: f64,
+}
+
+impl<N: Noise> Fbm<N> {
+    /// Returns `octaves` octaves of `noise`, each with twice the frequency and half the amplitude
+    /// of the last.
+    pub fn new(noise: N, octaves: usize) -> Self {
+        Fbm {
+            noise,
+            octaves,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+}
+
+impl<N: Noise> Noise for Fbm<N> {
+    fn sample(&self, mut p: Vec3) -> f64 {
+        let (mut sum, mut total, mut amplitude) = (0., 0., 1.);
+        for _ in 0..self.octaves {
+            sum += amplitude * self.noise.sample(p);
+            total += amplitude;
+            amplitude *= self.gain;
+            p = self.lacunarity * p;
+        }
+        if total == 0. {
+            0.
+        } else {
+            sum / total
+        }
+    }
+}
+
+/// F. Kenton Musgrave's ridged multifractal: like `Fbm`, but built from inverted absolute values
+/// of `noise`, which form sharp ridges where `noise` crosses zero. Each octave is weighted by the
+/// one before, so detail piles up along the ridges and valleys are left smooth, as in mountain
+/// ranges.
+///
+/// The result is between 0 and 1.
+#[derive(Debug, Clone)]
+pub struct Ridged<N> {
+    pub noise: N,
+    /// Number of copies of `noise` added together.
+    pub octaves: usize,
+    /// Factor by which the frequency of each octave exceeds the last, usually about 2.
+    pub lacunarity: f64,
+    /// Factor by which the amplitude of each octave is smaller than the last, usually about 0.5.
+    pub gain: f64,
+}
+
+impl<N: Noise> Ridged<N> {
+    /// Returns `octaves` octaves of `noise`, each with twice the frequency and half the amplitude
+    /// of the last.
+    pub fn new(noise: N, octaves: usize) -> Self {
+        Ridged {
+            noise,
+            octaves,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+}
+
+impl<N: Noise> Noise for Ridged<N> {
+    fn sample(&self, mut p: Vec3) -> f64 {
+        let (mut sum, mut total, mut amplitude) = (0., 0., 1.);
+        let mut weight = 1.;
+        for _ in 0..self.octaves {
+            let ridge = 1. - self.noise.sample(p).abs().min(1.);
+            let signal = ridge * ridge * weight;
+            // Octaves are only strong where the previous ones were high, on the ridges.
+            weight = (2. * signal).clamp(0., 1.);
+            sum += amplitude * signal;
+            total += amplitude;
+            amplitude *= self.gain;
+            p = self.lacunarity * p;
+        }
+        if total == 0. {
+            0.
+        } else {
+            sum / total
+        }
+    }
+}
+
+/// Domain warping: `noise`, with its input pushed around by `warp`, which swirls and stretches
+/// the pattern.
+#[derive(Debug, Clone)]
+pub struct Warp<N, W> {
+    pub noise: N,
+    /// The noise giving the displacement, sampled three times at widely spaced points to get a
+    /// vector.
+    pub warp: W,
+    /// Distance by which points are displaced, at most.
+    pub strength: f64,
+}
+
+impl<N: Noise, W: Noise> Noise for Warp<N, W> {
+    fn sample(&self, p: Vec3) -> f64 {
+        // Sample far enough apart that the three components are unrelated.
+        let displacement = Vec3(
+            self.warp.sample(p),
+            self.warp.sample(p + Vec3(31.4, 15.9, 26.5)),
+            self.warp.sample(p + Vec3(-35.8, 97.9, -32.3)),
+        );
+        self.noise.sample(p + self.strength * displacement)
+    }
+}
@@ -0,0 +1,723 @@
+//! Loading of [Wavefront OBJ][obj] scenes and their MTL material libraries.
+//!
+//! Geometry is read from `v`, `vt`, `vn` and `f` statements. Faces with more than three vertices
+//! are triangulated, and indices may be negative (counting back from the most recent vertex).
+//! Faces are collected into one `TriangleMesh` for each combination of group (`g` or `o`) and
+//! material (`usemtl`). Statements the loader doesn't understand, such as smoothing groups and
+//! lines, are ignored.
+//!
+//! Faces whose material isn't defined, because its name is unknown or because the MTL file that
+//! defines it is missing or can't be read, get a plain gray `Lambertian` material, as do faces
+//! before any `usemtl` statement.
+//!
+//! MTL materials are mapped onto `Material` variants as follows:
+//!
+//! - A non-black emission color `Ke` gives a `DiffuseLight` with that emission.
+//! - A dissolve `d` below 1 (or transparency `Tr` above 0) gives a `Dielectric` with refractive
+//!   index `Ni`, defaulting to 1.5 if absent.
+//! - A specular color `Ks` brighter than the diffuse color `Kd` gives a `Metal` with albedo `Ks`,
+//!   whose fuzz is derived from the specular exponent `Ns`.
+//! - Anything else is `Lambertian` with albedo `Kd`.
+//!
+//! [obj]: https://en.wikipedia.org/wiki/Wavefront_.obj_file
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::material::Material;
+use crate::mesh::{MeshData, TriangleMesh};
+use crate::object::Object;
+use crate::texture;
+use crate::vec3::{Axis::*, Vec3};
+
+/// Materials from an MTL file, by name.
+pub type Materials = HashMap<String, Material>;
+
+/// Error produced when an OBJ or MTL file can't be loaded.
+#[derive(Debug)]
+pub enum ObjError {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// A statement in the input is malformed or refers to something that doesn't exist.
+    Syntax {
+        /// Line number of the statement, starting from 1.
+        line: usize,
+        /// Description of the problem.
+        message: String,
+    },
+    /// An error occurred while loading the file at `path`.
+    InFile { path: PathBuf, error: Box<ObjError> },
+}
+
+impl std::fmt::Display for ObjError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjError::Io(e) => write!(f, "{}", e),
+            ObjError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            ObjError::InFile { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ObjError::Io(e) => Some(e),
+            ObjError::Syntax { .. } => None,
+            ObjError::InFile { error, .. } => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for ObjError {
+    fn from(e: io::Error) -> Self {
+        ObjError::Io(e)
+    }
+}
+
+/// Loads the OBJ file at `path`, along with any MTL files it references, which are looked up
+/// relative to the OBJ file's directory.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Box<dyn Object>>, ObjError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    in_file(path, |reader| {
+        parse(reader, |name| load_mtl(dir.join(name)))
+    })
+}
+
+/// Loads the MTL file at `path`.
+pub fn load_mtl(path: impl AsRef<Path>) -> Result<Materials, ObjError> {
+    in_file(path.as_ref(), parse_mtl)
+}
+
+/// Opens `path` and passes it to `f`, attributing any error to the file.
+fn in_file<T>(
+    path: &Path,
+    f: impl FnOnce(BufReader<File>) -> Result<T, ObjError>,
+) -> Result<T, ObjError> {
+    File::open(path)
+        .map_err(ObjError::from)
+        .and_then(|file| f(BufReader::new(file)))
+        .map_err(|error| ObjError::InFile {
+            path: path.to_owned(),
+            error: Box::new(error),
+        })
+}
+
+/// Parses OBJ statements from `reader`.
+///
+/// Each `mtllib` statement calls `mtllib` with each of the named files, which should return the
+/// materials it defines. If it returns an error instead, faces using those materials get the
+/// default material.
+pub fn parse(
+    reader: impl BufRead,
+    mut mtllib: impl FnMut(&str) -> Result<Materials, ObjError>,
+) -> Result<Vec<Box<dyn Object>>, ObjError> {
+    let mut positions = vec![];
+    let mut uvs = vec![];
+    let mut normals = vec![];
+
+    let mut materials = Materials::new();
+    let mut group = String::new();
+    let mut material: Option<String> = None;
+
+    // Meshes in order of first appearance, plus an index by group and material name.
+    let mut meshes: Vec<MeshBuilder> = vec![];
+    let mut mesh_index: HashMap<(String, Option<String>), usize> = HashMap::new();
+
+    for_each_statement(reader, |line, keyword, args| {
+        let syntax = |message: String| ObjError::Syntax { line, message };
+        match keyword {
+            "v" => positions.push(parse_vec3(line, args)?),
+            "vt" => match args {
+                [u] => uvs.push((parse_f64(line, u)?, 0.)),
+                [u, v] | [u, v, _] => uvs.push((parse_f64(line, u)?, parse_f64(line, v)?)),
+                _ => return Err(syntax("expected 1 to 3 texture coordinates".into())),
+            },
+            "vn" => normals.push(parse_vec3(line, args)?),
+            "f" => {
+                if args.len() < 3 {
+                    return Err(syntax("face has fewer than 3 vertices".into()));
+                }
+                let refs = args
+                    .iter()
+                    .map(|a| parse_vertex_ref(line, a, &positions, &uvs, &normals))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let key = (group.clone(), material.clone());
+                let index = *mesh_index.entry(key).or_insert_with(|| {
+                    let material = match material.as_ref().and_then(|name| materials.get(name)) {
+                        Some(material) => material.clone(),
+                        None => default_material(),
+                    };
+                    meshes.push(MeshBuilder::new(material));
+                    meshes.len() - 1
+                });
+                let mesh = &mut meshes[index];
+
+                let corners: Vec<Vec3> = refs.iter().map(|r| positions[r.0]).collect();
+                let vertices: Vec<u32> = refs
+                    .iter()
+                    .map(|&r| mesh.vertex(r, &positions, &uvs, &normals))
+                    .collect();
+                for [a, b, c] in triangulate(&corners) {
+                    mesh.triangles.push([vertices[a], vertices[b], vertices[c]]);
+                }
+            }
+            "g" | "o" => group = args.join(" "),
+            "usemtl" => material = Some(args.join(" ")),
+            "mtllib" => {
+                for name in args {
+                    if let Ok(library) = mtllib(name) {
+                        materials.extend(library);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    Ok(meshes
+        .into_iter()
+        .filter(|m| !m.triangles.is_empty())
+        .map(|m| Box::new(m.build()) as Box<dyn Object>)
+        .collect())
+}
+
+/// Parses MTL statements from `reader`.
+pub fn parse_mtl(reader: impl BufRead) -> Result<Materials, ObjError> {
+    let mut materials = Materials::new();
+    let mut current: Option<(String, MtlParams)> = None;
+
+    for_each_statement(reader, |line, keyword, args| {
+        if keyword == "newmtl" {
+            if let Some((name, params)) = current.take() {
+                materials.insert(name, params.to_material());
+            }
+            current = Some((args.join(" "), MtlParams::default()));
+            return Ok(());
+        }
+
+        let params = match &mut current {
+            Some((_, params)) => params,
+            // Statements before the first `newmtl` have nothing to apply to.
+            None => return Ok(()),
+        };
+        match keyword {
+            "Kd" => params.kd = parse_vec3(line, args)?,
+            "Ks" => params.ks = parse_vec3(line, args)?,
+            "Ke" => params.ke = parse_vec3(line, args)?,
+            "Ns" => params.ns = parse_single(line, args)?,
+            "Ni" => params.ni = Some(parse_single(line, args)?),
+            "d" => params.d = parse_single(line, args)?,
+            "Tr" => params.d = 1. - parse_single(line, args)?,
+            _ => {}
+        }
+        Ok(())
+    })?;
+
+    if let Some((name, params)) = current {
+        materials.insert(name, params.to_material());
+    }
+    Ok(materials)
+}
+
+/// Splits `reader` into statements and passes each to `f` with its line number, keyword and
+/// arguments. Comments and blank lines are skipped, and lines ending in `\` are joined to the
+/// next.
+fn for_each_statement(
+    reader: impl BufRead,
+    mut f: impl FnMut(usize, &str, &[&str]) -> Result<(), ObjError>,
+) -> Result<(), ObjError> {
+    let mut statement = String::new();
+    let mut start = 0;
+
+    for (i, text) in reader.lines().enumerate() {
+        let text = text?;
+        if statement.is_empty() {
+            start = i + 1;
+        }
+
+        let text = text.split('#').next().unwrap();
+        if let Some(continued) = text.trim_end().strip_suffix('\\') {
+            statement.push_str(continued);
+            statement.push(' ');
+            continue;
+        }
+        statement.push_str(text);
+
+        let words: Vec<&str> = statement.split_whitespace().collect();
+        if let Some((keyword, args)) = words.split_first() {
+            f(start, keyword, args)?;
+        }
+        statement.clear();
+    }
+
+    Ok(())
+}
+
+fn parse_f64(line: usize, s: &str) -> Result<f64, ObjError> {
+    s.parse().map_err(|_| ObjError::Syntax {
+        line,
+        message: format!("invalid number {:?}", s),
+    })
+}
+
+fn parse_single(line: usize, args: &[&str]) -> Result<f64, ObjError> {
+    match args {
+        [x] => parse_f64(line, x),
+        _ => Err(ObjError::Syntax {
+            line,
+            message: format!("expected 1 number, found {}", args.len()),
+        }),
+    }
+}
+
+fn parse_vec3(line: usize, args: &[&str]) -> Result<Vec3, ObjError> {
+    match args {
+        // Vertex positions may carry a fourth (w) component, which we ignore.
+        [x, y, z] | [x, y, z, _] => Ok(Vec3(
+            parse_f64(line, x)?,
+            parse_f64(line, y)?,
+            parse_f64(line, z)?,
+        )),
+        _ => Err(ObjError::Syntax {
+            line,
+            message: format!("expected 3 numbers, found {}", args.len()),
+        }),
+    }
+}
+
+/// Indices of a face vertex's position, and optionally its texture coordinate and normal.
+type VertexRef = (usize, Option<usize>, Option<usize>);
+
+/// Parses a face vertex of the form `v`, `v/vt`, `v//vn` or `v/vt/vn`, resolving its indices
+/// against the attributes read so far.
+fn parse_vertex_ref(
+    line: usize,
+    s: &str,
+    positions: &[Vec3],
+    uvs: &[(f64, f64)],
+    normals: &[Vec3],
+) -> Result<VertexRef, ObjError> {
+    let resolve = |index: &str, len: usize, what: &str| -> Result<usize, ObjError> {
+        let error = |message| ObjError::Syntax { line, message };
+        let i: isize = index
+            .parse()
+            .map_err(|_| error(format!("invalid {} index {:?}", what, index)))?;
+        // Positive indices count from 1; negative ones count back from the end.
+        let resolved = match i {
+            0 => None,
+            i if i > 0 => Some(i as usize - 1),
+            i => len.checked_sub(i.unsigned_abs()),
+        };
+        resolved
+            .filter(|&r| r < len)
+            .ok_or_else(|| error(format!("{} index {} out of range", what, i)))
+    };
+
+    let mut parts = s.split('/');
+    let v = resolve(parts.next().unwrap(), positions.len(), "vertex")?;
+    let vt = match parts.next() {
+        None | Some("") => None,
+        Some(i) => Some(resolve(i, uvs.len(), "texture coordinate")?),
+    };
+    let vn = match parts.next() {
+        None | Some("") => None,
+        Some(i) => Some(resolve(i, normals.len(), "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(ObjError::Syntax {
+            line,
+            message: format!("invalid face vertex {:?}", s),
+        });
+    }
+    Ok((v, vt, vn))
+}
+
+/// Splits a polygon into triangles by ear clipping, returning indices into `points`.
+///
+/// The polygon is assumed to be planar, but may be concave. If it's too degenerate to clip, the
+/// remainder is fanned out from its first vertex.
+pub(crate) fn triangulate(points: &[Vec3]) -> Vec<[usize; 3]> {
+    if points.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Project onto the coordinate plane most nearly parallel to the polygon, using Newell's
+    // method to find its normal.
+    let normal = (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            Vec3(
+                (a[Y] - b[Y]) * (a[Z] + b[Z]),
+                (a[Z] - b[Z]) * (a[X] + b[X]),
+                (a[X] - b[X]) * (a[Y] + b[Y]),
+            )
+        })
+        .sum::<Vec3>()
+        .map(f64::abs);
+    let (u, v) = if normal[X] >= normal[Y] && normal[X] >= normal[Z] {
+        (Y, Z)
+    } else if normal[Y] >= normal[Z] {
+        (Z, X)
+    } else {
+        (X, Y)
+    };
+    let flat: Vec<(f64, f64)> = points.iter().map(|p| (p[u], p[v])).collect();
+
+    let cross = |a: usize, b: usize, c: usize| {
+        let (a, b, c) = (flat[a], flat[b], flat[c]);
+        (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+    };
+    let area: f64 = (0..flat.len())
+        .map(|i| cross(0, i, (i + 1) % flat.len()))
+        .sum();
+
+    let mut remaining: Vec<usize> = (0..points.len()).collect();
+    let mut triangles = Vec::with_capacity(points.len() - 2);
+
+    'clip: while remaining.len() > 3 {
+        let n = remaining.len();
+        for i in 0..n {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            // An ear must turn the same way as the polygon as a whole...
+            if cross(a, b, c) * area <= 0. {
+                continue;
+            }
+            // ...and contain no other vertex.
+            let contains = |p: usize| {
+                cross(a, b, p) * area >= 0.
+                    && cross(b, c, p) * area >= 0.
+                    && cross(c, a, p) * area >= 0.
+            };
+            if remaining
+                .iter()
+                .any(|&p| p != a && p != b && p != c && contains(p))
+            {
+                continue;
+            }
+
+            triangles.push([a, b, c]);
+            remaining.remove(i);
+            continue 'clip;
+        }
+
+        // No ear found, so the polygon is degenerate or self-intersecting.
+        break;
+    }
+
+    for i in 1..remaining.len() - 1 {
+        triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+    }
+    triangles
+}
+
+/// Accumulates the faces of one mesh, giving each distinct combination of position, texture
+/// coordinate and normal its own vertex.
+struct MeshBuilder {
+    material: Material,
+    vertices: HashMap<VertexRef, u32>,
+    positions: Vec<Vec3>,
+    uvs: Vec<Option<(f64, f64)>>,
+    normals: Vec<Option<Vec3>>,
+    triangles: Vec<[u32; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(material: Material) -> Self {
+        MeshBuilder {
+            material,
+            vertices: HashMap::new(),
+            positions: vec![],
+            uvs: vec![],
+            normals: vec![],
+            triangles: vec![],
+        }
+    }
+
+    fn vertex(
+        &mut self,
+        r: VertexRef,
+        positions: &[Vec3],
+        uvs: &[(f64, f64)],
+        normals: &[Vec3],
+    ) -> u32 {
+        let next = self.positions.len() as u32;
+        *self.vertices.entry(r).or_insert_with(|| {
+            self.positions.push(positions[r.0]);
+            self.uvs.push(r.1.map(|i| uvs[i]));
+            self.normals.push(r.2.map(|i| normals[i]));
+            next
+        })
+    }
+
+    fn build(self) -> TriangleMesh {
+        let mut geometry = MeshData::new(self.positions, self.triangles);
+        // Attributes are only usable if every vertex has them.
+        if let Some(normals) = self.normals.into_iter().collect() {
+            geometry = geometry.with_normals(normals);
+        }
+        if let Some(uvs) = self.uvs.into_iter().collect() {
+            geometry = geometry.with_uvs(uvs);
+        }
+        TriangleMesh {
+            geometry: Arc::new(geometry),
+            material: self.material,
+        }
+    }
+}
+
+/// Material for faces that appear before any `usemtl` statement, or whose material isn't defined.
+fn default_material() -> Material {
+    Material::Lambertian {
+        albedo: texture::constant(Vec3::from(0.8)),
+    }
+}
+
+/// Material properties accumulated from an MTL `newmtl` block.
+struct MtlParams {
+    kd: Vec3,
+    ks: Vec3,
+    ke: Vec3,
+    ns: f64,
+    ni: Option<f64>,
+    d: f64,
+}
+
+impl Default for MtlParams {
+    fn default() -> Self {
+        MtlParams {
+            kd: Vec3::from(0.8),
+            ks: Vec3::default(),
+            ke: Vec3::default(),
+            ns: 0.,
+            ni: None,
+            d: 1.,
+        }
+    }
+}
+
+impl MtlParams {
+    /// Chooses the `Material` variant that best matches these properties, as described in the
+    /// module documentation.
+    fn to_material(&self) -> Material {
+        let max = |v: Vec3| v.reduce(f64::max);
+
+        if max(self.ke) > 0. {
+            Material::DiffuseLight {
+                emission: texture::constant(self.ke),
+                brightness: 1.,
+            }
+        } else if self.d < 1. {
+            Material::Dielectric {
+                ref_idx: self.ni.unwrap_or(1.5),
+            }
+        } else if max(self.ks) > max(self.kd) {
+            Material::Metal {
+                albedo: self.ks,
+                // Match the width of a Phong lobe with exponent `Ns`: high exponents are polished,
+                // low ones frosted.
+                fuzz: (2. / (self.ns.max(0.) + 2.)).sqrt(),
+            }
+        } else {
+            Material::Lambertian {
+                albedo: texture::constant(self.kd),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::HitRecord;
+    use crate::ray::Ray;
+
+    /// Parses `obj`, serving `mtllib` requests from `libraries` by name. Names not in `libraries`
+    /// fail to load.
+    fn parse_with(obj: &str, libraries: &[(&str, &str)]) -> Vec<Box<dyn Object>> {
+        parse(obj.as_bytes(), |name| {
+            match libraries.iter().find(|(n, _)| *n == name) {
+                Some((_, mtl)) => parse_mtl(mtl.as_bytes()),
+                None => Err(ObjError::Io(io::ErrorKind::NotFound.into())),
+            }
+        })
+        .unwrap()
+    }
+
+    /// Where a ray straight down the Z axis, through (`x`, `y`), hits `object`.
+    fn hit_at(object: &dyn Object, x: f64, y: f64) -> Option<HitRecord<'_>> {
+        let ray = Ray {
+            origin: Vec3(x, y, 5.),
+            direction: Vec3(0., 0., -1.),
+            time: 0.,
+            differentials: None,
+        };
+        object.hit(&ray, 0.001..f64::MAX, &mut || 0.5)
+    }
+
+    /// The material of `object` where a ray straight down the Z axis, through (`x`, `y`), hits it.
+    fn material_at(object: &dyn Object, x: f64, y: f64) -> Material {
+        hit_at(object, x, y).expect("ray missed").material.clone()
+    }
+
+    /// Parses `obj`, which must fail with a syntax error on `line` mentioning `message`.
+    fn parse_err(obj: &str, line: usize, message: &str) {
+        match parse(obj.as_bytes(), |_| Ok(Materials::new())) {
+            Err(ObjError::Syntax {
+                line: l,
+                message: m,
+            }) if l == line && m.contains(message) => {}
+            Err(e) => panic!(
+                "{:?}: expected an error about {:?}, got {}",
+                obj, message, e
+            ),
+            Ok(_) => panic!("{:?}: expected an error", obj),
+        }
+    }
+
+    const SQUARE: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    const SHINY: &str = "newmtl shiny\nKd 0 0 0\nKs 0.9 0.9 0.9\nNs 1000\n";
+
+    #[test]
+    fn undefined_material() {
+        let obj = format!("{}mtllib shiny.mtl\nusemtl missing\nf 1 2 3 4\n", SQUARE);
+        let objects = parse_with(&obj, &[("shiny.mtl", SHINY)]);
+        assert_eq!(objects.len(), 1);
+        assert!(matches!(
+            material_at(&*objects[0], 0.5, 0.5),
+            Material::Lambertian { .. }
+        ));
+    }
+
+    #[test]
+    fn missing_material_library() {
+        let obj = format!("{}mtllib gone.mtl\nusemtl shiny\nf 1 2 3 4\n", SQUARE);
+        let objects = parse_with(&obj, &[]);
+        assert_eq!(objects.len(), 1);
+        assert!(matches!(
+            material_at(&*objects[0], 0.5, 0.5),
+            Material::Lambertian { .. }
+        ));
+    }
+
+    #[test]
+    fn several_material_libraries() {
+        let obj = format!(
+            "{}mtllib gone.mtl shiny.mtl glass.mtl\nusemtl shiny\nf 1 2 3\nusemtl glass\nf 1 3 4\n",
+            SQUARE
+        );
+        let glass = "newmtl glass\nd 0.1\nNi 1.3\n";
+        let objects = parse_with(&obj, &[("shiny.mtl", SHINY), ("glass.mtl", glass)]);
+        assert_eq!(objects.len(), 2);
+        assert!(matches!(
+            material_at(&*objects[0], 0.8, 0.2),
+            Material::Metal { .. }
+        ));
+        assert!(matches!(
+            material_at(&*objects[1], 0.2, 0.8),
+            Material::Dielectric { ref_idx } if ref_idx == 1.3
+        ));
+    }
+
+    #[test]
+    fn negative_indices() {
+        // Counting back from the most recent vertex at the time of the face.
+        let obj = format!("{}f -4 -3 -2\nv 9 9 9\nf -5 -3 -2\n", SQUARE);
+        let objects = parse_with(&obj, &[]);
+        assert_eq!(objects.len(), 1);
+        // Below the diagonal from (0, 0) to (1, 1), and above it.
+        assert!(hit_at(&*objects[0], 0.8, 0.2).is_some());
+        assert!(hit_at(&*objects[0], 0.2, 0.8).is_some());
+        assert!(hit_at(&*objects[0], 1.1, 0.5).is_none());
+    }
+
+    #[test]
+    fn concave_face() {
+        // An L shape, starting next to its inner corner, where a fan from the first vertex would
+        // cover the notch.
+        let obj = "v 1 2 0\nv 0 2 0\nv 0 0 0\nv 2 0 0\nv 2 1 0\nv 1 1 0\nf 1 2 3 4 5 6\n";
+        let objects = parse_with(obj, &[]);
+        assert!(hit_at(&*objects[0], 0.5, 1.5).is_some());
+        assert!(hit_at(&*objects[0], 1.5, 0.5).is_some());
+        assert!(hit_at(&*objects[0], 1.8, 1.05).is_none());
+    }
+
+    #[test]
+    fn attributes() {
+        let obj = format!(
+            "{}vt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 1\nf 1/1/1 2/2/1 3/3/1\n",
+            SQUARE
+        );
+        let objects = parse_with(&obj, &[]);
+        let hit = hit_at(&*objects[0], 0.75, 0.25).unwrap();
+        assert!((hit.uv.0 - 0.75).abs() < 1e-9 && (hit.uv.1 - 0.25).abs() < 1e-9);
+        assert_eq!(hit.normal.2, 1.);
+    }
+
+    #[test]
+    fn groups() {
+        // Faces are gathered by group and material, in order of first appearance, and a statement
+        // can continue onto the next line.
+        let obj = format!("{}g a\nf 1 2 3\ng b\nf 1 3 \\\n 4\ng a\nf 2 3 4\n", SQUARE);
+        let objects = parse_with(&obj, &[]);
+        assert_eq!(objects.len(), 2);
+        assert!(hit_at(&*objects[0], 0.9, 0.5).is_some());
+        assert!(hit_at(&*objects[1], 0.1, 0.5).is_some());
+        assert!(hit_at(&*objects[1], 0.9, 0.5).is_none());
+    }
+
+    #[test]
+    fn materials() {
+        let mtl = "newmtl lamp\nKe 4 4 4\nnewmtl clay\nKd 0.5 0.4 0.3\nKs 0.1 0.1 0.1\n";
+        let materials = parse_mtl(mtl.as_bytes()).unwrap();
+        assert!(matches!(materials["lamp"], Material::DiffuseLight { .. }));
+        assert!(matches!(materials["clay"], Material::Lambertian { .. }));
+        assert!(matches!(
+            parse_mtl("newmtl x\nKd 1 2\n".as_bytes()),
+            Err(ObjError::Syntax { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        parse_err(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n",
+            4,
+            "vertex index 4 out of range",
+        );
+        parse_err(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -4\n",
+            4,
+            "vertex index -4 out of range",
+        );
+        parse_err(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n",
+            4,
+            "vertex index 0 out of range",
+        );
+        parse_err(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2 3\n",
+            4,
+            "texture coordinate index 1",
+        );
+        parse_err(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/// 2 3\n",
+            4,
+            "invalid face vertex",
+        );
+        parse_err("v 0 0 0\nv 1 0 0\nf 1 2\n", 3, "fewer than 3 vertices");
+        parse_err("# comment\nv 0 zero 0\n", 2, "invalid number");
+        parse_err("vt 0 0 0 0\n", 1, "texture coordinates");
+    }
+}
@@ -1,8 +1,10 @@
 use std::ops::Range;
+use std::sync::Arc;
 
 use crate::aabb::Aabb;
 use crate::material::Material;
-use crate::ray::Ray;
+use crate::matrix::Mat4;
+use crate::ray::{Differentials, Ray};
 use crate::vec3::{
     Axis::{self, *},
     Vec3,
@@ -34,6 +36,35 @@ pub trait Object: std::fmt::Debug + Send + Sync {
     /// Computes the bounding box for the object at the given range of times. This is called during
     /// scene setup, not rendering, and so it may be expensive.
     fn bounding_box(&self, exposure: Range<f64>) -> Aabb;
+
+    /// Whether the object gives off light and supports `random_toward`, so the renderer can aim
+    /// rays at it directly rather than waiting for them to find it by chance.
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    /// Chooses a random direction from `origin` toward the object, as it is at `time`. Only
+    /// objects for which `is_light` returns true need to support this, and the rest return `None`.
+    fn random_toward(
+        &self,
+        _origin: Vec3,
+        _time: f64,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<Vec3> {
+        None
+    }
+
+    /// The probability density, per unit solid angle, of `random_toward` choosing `direction` from
+    /// `origin` at `time`. This is zero for directions that miss the object.
+    fn pdf_toward(
+        &self,
+        _origin: Vec3,
+        _direction: Vec3,
+        _time: f64,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        0.
+    }
 }
 
 impl Object for Box<dyn Object> {
@@ -49,6 +80,57 @@ impl Object for Box<dyn Object> {
     fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
         (**self).bounding_box(exposure)
     }
+
+    fn is_light(&self) -> bool {
+        (**self).is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        (**self).random_toward(origin, time, rng)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        (**self).pdf_toward(origin, direction, time, rng)
+    }
+}
+
+impl Object for Arc<dyn Object> {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        (**self).hit(ray, t_range, rng)
+    }
+
+    fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
+        (**self).bounding_box(exposure)
+    }
+
+    fn is_light(&self) -> bool {
+        (**self).is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        (**self).random_toward(origin, time, rng)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        (**self).pdf_toward(origin, direction, time, rng)
+    }
 }
 
 /// A description of a `Ray` hitting an `Object`. This stores information needed for rendering
@@ -64,10 +146,95 @@ pub struct HitRecord<'m> {
     pub p: Vec3,
     /// Surface normal of the object at the hit position.
     pub normal: Vec3,
+    /// Surface coordinates of the hit position, each nominally in the range `[0, 1]`.
+    pub uv: (f64, f64),
+    /// Rate of change of `p` with `uv.0`, in scene space. Together with `bitangent`, this tells
+    /// which way the surface coordinates run across the surface, for effects such as normal
+    /// mapping and anisotropic reflection. Not normalized.
+    pub tangent: Vec3,
+    /// Rate of change of `p` with `uv.1`, in scene space. Not normalized.
+    pub bitangent: Vec3,
+    /// The hit position in the object's own coordinates, before any `Translate`, `Transform` or
+    /// similar wrappers moved it into the scene. Solid textures are evaluated here, so that they
+    /// move with the object rather than the object moving through them.
+    pub local_p: Vec3,
+    /// The patch of surface seen through one pixel around the hit position, if known. Objects
+    /// leave this empty; it's filled in by the renderer using `with_footprint`.
+    pub footprint: Option<Footprint>,
+    /// For hits on a `TriangleMesh`, the index of the face hit (into `MeshData::triangles`) and
+    /// the barycentric weights of its second and third vertices at the hit position, for
+    /// interpolating per-vertex data. Other objects leave this empty.
+    pub face: Option<(usize, f64, f64)>,
     /// Material of the object at the hit position.
     pub material: &'m Material,
 }
 
+impl<'m> HitRecord<'m> {
+    /// Works out the hit's footprint from the differentials of `ray`, the ray that made the hit,
+    /// if it has any.
+    ///
+    /// The surface is treated as flat around the hit position, which is accurate as long as the
+    /// footprint is small compared to the surface's curvature.
+    pub fn with_footprint(self, ray: &Ray) -> Self {
+        let footprint = ray.differentials.map(|d| {
+            let (dpdx, dpdy) = d.surface_derivatives(ray, self.t, self.normal);
+            Footprint {
+                dpdx,
+                dpdy,
+                duvdx: self.uv_change(dpdx),
+                duvdy: self.uv_change(dpdy),
+            }
+        });
+        HitRecord { footprint, ..self }
+    }
+
+    /// Finds the change in surface coordinates corresponding to a small step `dp` across the
+    /// surface, by expressing it in terms of `tangent` and `bitangent`.
+    fn uv_change(&self, dp: Vec3) -> (f64, f64) {
+        let (t, b) = (self.tangent, self.bitangent);
+        let (tt, tb, bb) = (t.dot(t), t.dot(b), b.dot(b));
+        let det = tt * bb - tb * tb;
+        if det.abs() < 1e-12 {
+            return (0., 0.);
+        }
+        let (pt, pb) = (dp.dot(t), dp.dot(b));
+        ((bb * pt - tb * pb) / det, (tt * pb - tb * pt) / det)
+    }
+}
+
+/// The rates at which a hit's position and surface coordinates change as we move across the image,
+/// horizontally (`*dx`) and vertically (`*dy`), by one pixel (or the fraction of a pixel covered by
+/// one sample).
+#[derive(Debug, Clone, Copy)]
+pub struct Footprint {
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    pub duvdx: (f64, f64),
+    pub duvdy: (f64, f64),
+}
+
+impl Differentials {
+    /// Finds how the point where `ray` hits the plane through `ray.point_at_parameter(t)` facing
+    /// `normal` moves as we move across the image.
+    pub(crate) fn surface_derivatives(&self, ray: &Ray, t: f64, normal: Vec3) -> (Vec3, Vec3) {
+        let d_n = ray.direction.dot(normal);
+        let derivative = |d_origin: Vec3, d_direction: Vec3| {
+            if d_n == 0. {
+                return Vec3::default();
+            }
+            // Moving the ray moves the hit along the ray as well as across it, keeping it in
+            // the plane.
+            let dp = d_origin + t * d_direction;
+            let dt = -dp.dot(normal) / d_n;
+            dp + dt * ray.direction
+        };
+        (
+            derivative(self.dx_origin, self.dx_direction),
+            derivative(self.dy_origin, self.dy_direction),
+        )
+    }
+}
+
 /// A sphere.
 #[derive(Debug, Clone)]
 pub struct Sphere {
@@ -96,10 +263,18 @@ impl Object for Sphere {
             ] {
                 if t < t_range.end && t >= t_range.start {
                     let p = ray.point_at_parameter(t);
+                    let normal = p / self.radius;
+                    let (tangent, bitangent) = sphere_tangents(p);
                     return Some(HitRecord {
                         t,
                         p,
-                        normal: p / self.radius,
+                        normal,
+                        uv: sphere_uv(normal),
+                        tangent,
+                        bitangent,
+                        local_p: p,
+                        footprint: None,
+                        face: None,
                         material: &self.material,
                     });
                 }
@@ -114,6 +289,108 @@ impl Object for Sphere {
             max: Vec3::from(self.radius),
         }
     }
+
+    fn is_light(&self) -> bool {
+        matches!(self.material, Material::DiffuseLight { .. })
+    }
+
+    fn random_toward(
+        &self,
+        origin: Vec3,
+        _time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> Option<Vec3> {
+        use std::f64::consts::PI;
+
+        let r2 = self.radius * self.radius;
+        let d2 = origin.dot(origin);
+        if d2 <= r2 {
+            // From inside, every direction meets the sphere; pick a point evenly over its surface.
+            let z = 1. - 2. * rng();
+            let phi = 2. * PI * rng();
+            let rho = (1. - z * z).sqrt();
+            let point = self.radius * Vec3(rho * phi.cos(), rho * phi.sin(), z);
+            return Some(point - origin);
+        }
+        // From outside, pick evenly among the directions in the cone that the sphere subtends.
+        let cos_max = (1. - r2 / d2).sqrt();
+        let z = 1. + rng() * (cos_max - 1.);
+        let phi = 2. * PI * rng();
+        let rho = (1. - z * z).sqrt();
+        let w = -origin.into_unit();
+        let (u, v) = orthonormal_basis(w);
+        Some(rho * phi.cos() * u + rho * phi.sin() * v + z * w)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        use std::f64::consts::PI;
+
+        let ray = Ray {
+            origin,
+            direction,
+            time,
+            differentials: None,
+        };
+        let hit = match self.hit(&ray, 0.001..f64::MAX, rng) {
+            Some(hit) => hit,
+            None => return 0.,
+        };
+        let r2 = self.radius * self.radius;
+        let d2 = origin.dot(origin);
+        if d2 <= r2 {
+            // Convert the density per unit area to one per unit solid angle.
+            let distance2 = hit.t * hit.t * direction.dot(direction);
+            let cosine = hit.normal.dot(direction.into_unit()).abs();
+            distance2 / (cosine * 4. * PI * r2)
+        } else {
+            let cos_max = (1. - r2 / d2).sqrt();
+            1. / (2. * PI * (1. - cos_max))
+        }
+    }
+}
+
+/// Computes surface coordinates for a point `n` on the unit sphere. `u` runs around the Y axis
+/// starting from -X, and `v` runs from the south pole to the north pole.
+pub(crate) fn sphere_uv(n: Vec3) -> (f64, f64) {
+    use std::f64::consts::PI;
+
+    let phi = f64::atan2(-n[Z], n[X]) + PI;
+    let theta = f64::acos((-n[Y]).clamp(-1., 1.));
+    (phi / (2. * PI), theta / PI)
+}
+
+/// Computes the derivatives of a point `p` on a sphere centered on the origin with respect to the
+/// surface coordinates from `sphere_uv`.
+fn sphere_tangents(p: Vec3) -> (Vec3, Vec3) {
+    use std::f64::consts::PI;
+
+    let rho = (p[X] * p[X] + p[Z] * p[Z]).sqrt();
+    if rho == 0. {
+        // At the poles, `u` is degenerate; pick any directions along the surface.
+        return orthonormal_basis(p.into_unit());
+    }
+    let tangent = 2. * PI * Vec3(p[Z], 0., -p[X]);
+    let bitangent = PI * Vec3(-p[Y] * p[X] / rho, rho, -p[Y] * p[Z] / rho);
+    (tangent, bitangent)
+}
+
+/// Returns two unit vectors perpendicular to the unit vector `n` and to each other, forming a
+/// right-handed basis with it.
+pub(crate) fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    // Any direction not parallel to `n` can be used to start the basis.
+    let helper = if n[X].abs() < 0.9 {
+        Vec3(1., 0., 0.)
+    } else {
+        Vec3(0., 1., 0.)
+    };
+    let tangent = helper.cross(&n).into_unit();
+    (tangent, n.cross(&tangent))
 }
 
 /// A rectangle orthogonal to one axis.
@@ -205,11 +482,24 @@ impl<A: StaticAxis> Object for Rect<A> {
         let p = ray.point_at_parameter(t);
         let mut normal = Vec3::default();
         normal[A::AXIS] = 1.;
+        let (mut tangent, mut bitangent) = (Vec3::default(), Vec3::default());
+        tangent[A::OTHER1] = self.range0.end - self.range0.start;
+        bitangent[A::OTHER2] = self.range1.end - self.range1.start;
+        let uv = (
+            (x - self.range0.start) / (self.range0.end - self.range0.start),
+            (y - self.range1.start) / (self.range1.end - self.range1.start),
+        );
         Some(HitRecord {
             t,
             p,
             material: &self.material,
             normal,
+            uv,
+            tangent,
+            bitangent,
+            local_p: p,
+            footprint: None,
+            face: None,
         })
     }
 
@@ -227,6 +517,50 @@ impl<A: StaticAxis> Object for Rect<A> {
 
         Aabb { min, max }
     }
+
+    fn is_light(&self) -> bool {
+        matches!(self.material, Material::DiffuseLight { .. })
+    }
+
+    fn random_toward(
+        &self,
+        origin: Vec3,
+        _time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> Option<Vec3> {
+        let mut point = Vec3::default();
+        point[A::AXIS] = self.k;
+        point[A::OTHER1] = self.range0.start + rng() * (self.range0.end - self.range0.start);
+        point[A::OTHER2] = self.range1.start + rng() * (self.range1.end - self.range1.start);
+        Some(point - origin)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        let ray = Ray {
+            origin,
+            direction,
+            time,
+            differentials: None,
+        };
+        match self.hit(&ray, 0.001..f64::MAX, rng) {
+            Some(hit) => {
+                // Points are chosen evenly over the area; convert that density to one per unit
+                // solid angle.
+                let area =
+                    (self.range0.end - self.range0.start) * (self.range1.end - self.range1.start);
+                let distance2 = hit.t * hit.t * direction.dot(direction);
+                let cosine = direction[A::AXIS].abs() / direction.length();
+                distance2 / (cosine * area)
+            }
+            None => 0.,
+        }
+    }
 }
 
 /// The same geometry as the contained `O`, but with the normal vectors inverted.
@@ -250,6 +584,24 @@ impl<O: Object> Object for FlipNormals<O> {
     fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
         self.0.bounding_box(exposure)
     }
+
+    fn is_light(&self) -> bool {
+        self.0.is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        self.0.random_toward(origin, time, rng)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        self.0.pdf_toward(origin, direction, time, rng)
+    }
 }
 
 /// The game geometry as `O`, but translated by `offset` from the origin.
@@ -284,6 +636,25 @@ impl<O: Object> Object for Translate<O> {
             max: b.max + self.offset,
         }
     }
+
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        self.object.random_toward(origin - self.offset, time, rng)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        self.object
+            .pdf_toward(origin - self.offset, direction, time, rng)
+    }
 }
 
 /// The same geometry as `O`, but scaled by `factor` on each axis.
@@ -307,18 +678,57 @@ impl<O: Object> Object for Scale<O> {
         };
         self.object.hit(&t_ray, t_range, rng).map(|hit| HitRecord {
             p: hit.p * self.factor,
-            normal: hit.normal / self.factor,
+            normal: (hit.normal / self.factor).into_unit(),
+            tangent: hit.tangent * self.factor,
+            bitangent: hit.bitangent * self.factor,
             ..hit
         })
     }
 
     fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
         let b = self.object.bounding_box(exposure);
+        let (min, max) = (b.min * self.factor, b.max * self.factor);
+        // A negative factor mirrors the box, swapping its ends.
         Aabb {
-            min: b.min * self.factor,
-            max: b.max * self.factor,
+            min: min.zip_with(max, f64::min),
+            max: min.zip_with(max, f64::max),
         }
     }
+
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        self.object
+            .random_toward(origin / self.factor, time, rng)
+            .map(|d| d * self.factor)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        let local = direction / self.factor;
+        let determinant = self.factor.0 * self.factor.1 * self.factor.2;
+        self.object
+            .pdf_toward(origin / self.factor, local, time, rng)
+            * solid_angle_scale(direction, local, determinant)
+    }
+}
+
+/// Converts a density per unit solid angle of directions in an object's space into one in the
+/// scene, where the object is placed by a linear map with the given `determinant`. `direction` is
+/// in the scene, and `local` is the same direction in the object's space.
+///
+/// Unless the map is a rotation or an even scaling, it bunches directions up in some places and
+/// spreads them out in others, so the density changes from direction to direction.
+fn solid_angle_scale(direction: Vec3, local: Vec3, determinant: f64) -> f64 {
+    let stretch = local.length() / direction.length();
+    1. / (determinant.abs() * stretch * stretch * stretch)
 }
 
 /// The same geometry as `O`, but rotated around the Y axis.
@@ -331,6 +741,15 @@ pub struct RotateY<O> {
     cos_theta: f64,
 }
 
+/// Rotates `p` around the Y axis by the angle with the given sine and cosine.
+fn rot(p: Vec3, sin_theta: f64, cos_theta: f64) -> Vec3 {
+    Vec3(
+        p.dot(Vec3(cos_theta, 0., sin_theta)),
+        p.dot(Vec3(0., 1., 0.)),
+        p.dot(Vec3(-sin_theta, 0., cos_theta)),
+    )
+}
+
 impl<T: Object> Object for RotateY<T> {
     #[inline]
     fn hit<'o>(
@@ -339,14 +758,6 @@ impl<T: Object> Object for RotateY<T> {
         t_range: Range<f64>,
         rng: &mut dyn FnMut() -> f64,
     ) -> Option<HitRecord<'o>> {
-        fn rot(p: Vec3, sin_theta: f64, cos_theta: f64) -> Vec3 {
-            Vec3(
-                p.dot(Vec3(cos_theta, 0., sin_theta)),
-                p.dot(Vec3(0., 1., 0.)),
-                p.dot(Vec3(-sin_theta, 0., cos_theta)),
-            )
-        }
-
         let rot_ray = Ray {
             origin: rot(ray.origin, -self.sin_theta, self.cos_theta),
             direction: rot(ray.direction, -self.sin_theta, self.cos_theta),
@@ -358,19 +769,13 @@ impl<T: Object> Object for RotateY<T> {
             .map(|hit| HitRecord {
                 p: rot(hit.p, self.sin_theta, self.cos_theta),
                 normal: rot(hit.normal, self.sin_theta, self.cos_theta),
+                tangent: rot(hit.tangent, self.sin_theta, self.cos_theta),
+                bitangent: rot(hit.bitangent, self.sin_theta, self.cos_theta),
                 ..hit
             })
     }
 
     fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
-        fn rot(p: Vec3, sin_theta: f64, cos_theta: f64) -> Vec3 {
-            Vec3(
-                p.dot(Vec3(cos_theta, 0., sin_theta)),
-                p.dot(Vec3(0., 1., 0.)),
-                p.dot(Vec3(-sin_theta, 0., cos_theta)),
-            )
-        }
-
         let (min, max) = self.object.bounding_box(exposure).corners().fold(
             (Vec3::from(f64::MAX), Vec3::from(f64::MIN)),
             |(min, max), c| {
@@ -380,6 +785,32 @@ impl<T: Object> Object for RotateY<T> {
         );
         Aabb { min, max }
     }
+
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        let origin = rot(origin, -self.sin_theta, self.cos_theta);
+        self.object
+            .random_toward(origin, time, rng)
+            .map(|d| rot(d, self.sin_theta, self.cos_theta))
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        self.object.pdf_toward(
+            rot(origin, -self.sin_theta, self.cos_theta),
+            rot(direction, -self.sin_theta, self.cos_theta),
+            time,
+            rng,
+        )
+    }
 }
 
 /// Combines both `T` and `S` into one `Object`.
@@ -407,6 +838,38 @@ impl<T: Object, S: Object> Object for And<T, S> {
             .bounding_box(exposure.clone())
             .merge(self.1.bounding_box(exposure))
     }
+
+    fn is_light(&self) -> bool {
+        self.0.is_light() || self.1.is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        // Pick evenly between the halves that give off light.
+        match (self.0.is_light(), self.1.is_light()) {
+            (true, true) if rng() < 0.5 => self.0.random_toward(origin, time, rng),
+            (true, true) | (false, true) => self.1.random_toward(origin, time, rng),
+            (true, false) => self.0.random_toward(origin, time, rng),
+            (false, false) => None,
+        }
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        match (self.0.is_light(), self.1.is_light()) {
+            (true, true) => {
+                0.5 * (self.0.pdf_toward(origin, direction, time, rng)
+                    + self.1.pdf_toward(origin, direction, time, rng))
+            }
+            (false, true) => self.1.pdf_toward(origin, direction, time, rng),
+            (true, false) => self.0.pdf_toward(origin, direction, time, rng),
+            (false, false) => 0.,
+        }
+    }
 }
 
 /// Generates a rectangular prism having min and max corners `p0` and `p1`.
@@ -475,8 +938,101 @@ pub fn rotate_y<O: Object>(degrees: f64, object: O) -> RotateY<O> {
     }
 }
 
+/// The same geometry as `O`, but moved by an arbitrary affine transform: any combination of
+/// translation, rotation, scaling and shear.
+///
+/// Use the `transform` function to obtain one of these.
+#[derive(Debug, Clone)]
+pub struct Transform<O> {
+    pub object: O,
+    /// Transform from the object's space into the scene.
+    to_world: Mat4,
+    /// Inverse of `to_world`.
+    to_object: Mat4,
+}
+
+impl<O> Transform<O> {
+    /// The transform from the object's space into the scene.
+    pub fn matrix(&self) -> Mat4 {
+        self.to_world
+    }
+}
+
+impl<O: Object> Object for Transform<O> {
+    #[inline]
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        // The direction isn't normalized, so `t` means the same thing in either space.
+        let local_ray = Ray {
+            origin: self.to_object.transform_point(ray.origin),
+            direction: self.to_object.transform_vector(ray.direction),
+            ..*ray
+        };
+
+        self.object
+            .hit(&local_ray, t_range, rng)
+            .map(|hit| HitRecord {
+                p: self.to_world.transform_point(hit.p),
+                normal: self.to_object.transform_normal(hit.normal).into_unit(),
+                tangent: self.to_world.transform_vector(hit.tangent),
+                bitangent: self.to_world.transform_vector(hit.bitangent),
+                ..hit
+            })
+    }
+
+    fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
+        self.to_world
+            .transform_box(self.object.bounding_box(exposure))
+    }
+
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        self.object
+            .random_toward(self.to_object.transform_point(origin), time, rng)
+            .map(|d| self.to_world.transform_vector(d))
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        let local = self.to_object.transform_vector(direction);
+        let pdf = self
+            .object
+            .pdf_toward(self.to_object.transform_point(origin), local, time, rng);
+        pdf * solid_angle_scale(direction, local, self.to_world.determinant())
+    }
+}
+
+/// Returns a version of `object` that has been moved by `matrix`.
+///
+/// # Panics
+///
+/// If `matrix` can't be inverted.
+pub fn transform<O: Object>(matrix: Mat4, object: O) -> Transform<O> {
+    Transform {
+        object,
+        to_world: matrix,
+        to_object: matrix
+            .inverse()
+            .expect("Transform matrix must be invertible."),
+    }
+}
+
 /// Imposes a motion vector on an object, causing motion blur proportional to the length of the
 /// motion vector times the length of the exposure.
+///
+/// Hits keep the object's own `local_p`, so textures move along with the object.
 #[derive(Debug, Clone)]
 pub struct LinearMove<O> {
     /// The object being moved.
@@ -493,14 +1049,20 @@ impl<O: Object> Object for LinearMove<O> {
         t_range: Range<f64>,
         rng: &mut dyn FnMut() -> f64,
     ) -> Option<HitRecord<'o>> {
-        self.object.hit(
-            &Ray {
-                origin: ray.origin - ray.time * self.motion,
-                ..*ray
-            },
-            t_range,
-            rng,
-        )
+        let offset = ray.time * self.motion;
+        self.object
+            .hit(
+                &Ray {
+                    origin: ray.origin - offset,
+                    ..*ray
+                },
+                t_range,
+                rng,
+            )
+            .map(|hit| HitRecord {
+                p: hit.p + offset,
+                ..hit
+            })
     }
 
     fn bounding_box(&self, exposure: Range<f64>) -> Aabb {
@@ -517,6 +1079,26 @@ impl<O: Object> Object for LinearMove<O> {
 
         bb_start.merge(bb_end)
     }
+
+    fn is_light(&self) -> bool {
+        self.object.is_light()
+    }
+
+    fn random_toward(&self, origin: Vec3, time: f64, rng: &mut dyn FnMut() -> f64) -> Option<Vec3> {
+        self.object
+            .random_toward(origin - time * self.motion, time, rng)
+    }
+
+    fn pdf_toward(
+        &self,
+        origin: Vec3,
+        direction: Vec3,
+        time: f64,
+        rng: &mut dyn FnMut() -> f64,
+    ) -> f64 {
+        self.object
+            .pdf_toward(origin - time * self.motion, direction, time, rng)
+    }
 }
 
 /// A medium of constant density that scatters light internally, such as (greatly simplified) smoke
@@ -539,28 +1121,46 @@ impl<O: Object> Object for ConstantMedium<O> {
         t_range: Range<f64>,
         rng: &mut dyn FnMut() -> f64,
     ) -> Option<HitRecord<'o>> {
-        if let Some(mut hit1) = self.boundary.hit(ray, f64::MIN..f64::MAX, rng) {
-            if let Some(mut hit2) = self.boundary.hit(ray, hit1.t + 0.0001..f64::MAX, rng) {
-                hit1.t = hit1.t.max(t_range.start);
-                hit2.t = hit2.t.min(t_range.end);
-                if hit1.t >= hit2.t {
-                    return None;
-                }
+        // Distance the ray travels through the medium before scattering, chosen on first entry.
+        let mut hit_distance = None;
 
-                debug_assert!(hit1.t >= 0.);
+        // The boundary need not be convex, so the ray may pass in and out of it several times.
+        let mut start = f64::MIN;
+        while let Some(mut hit1) = self.boundary.hit(ray, start..f64::MAX, rng) {
+            let mut hit2 = self.boundary.hit(ray, hit1.t + 0.0001..f64::MAX, rng)?;
+            start = hit2.t + 0.0001;
 
-                let distance_inside = (hit2.t - hit1.t) * ray.direction.length();
-                let hit_distance = -(1. / self.density) * rng().ln();
-                if hit_distance < distance_inside {
-                    let t = hit1.t + hit_distance / ray.direction.length();
-                    return Some(HitRecord {
-                        t,
-                        p: ray.point_at_parameter(t),
-                        normal: Vec3(1., 0., 0.), // arbitrary
-                        material: &self.material,
-                    });
-                }
+            hit1.t = hit1.t.max(t_range.start);
+            hit2.t = hit2.t.min(t_range.end);
+            if hit1.t >= t_range.end {
+                break;
+            }
+            if hit1.t >= hit2.t {
+                continue;
+            }
+
+            let distance_inside = (hit2.t - hit1.t) * ray.direction.length();
+            let hit_distance =
+                hit_distance.get_or_insert_with(|| -(1. / self.density) * rng().ln());
+            if *hit_distance < distance_inside {
+                let t = hit1.t + *hit_distance / ray.direction.length();
+                let p = ray.point_at_parameter(t);
+                return Some(HitRecord {
+                    t,
+                    p,
+                    normal: Vec3(1., 0., 0.), // arbitrary
+                    uv: (0., 0.),             // also arbitrary
+                    tangent: Vec3(0., 1., 0.),
+                    bitangent: Vec3(0., 0., 1.),
+                    local_p: p,
+                    footprint: None,
+                    face: None,
+                    material: &self.material,
+                });
             }
+            // Scattering is memoryless, so the remaining distance carries over to the next
+            // stretch inside the boundary.
+            *hit_distance -= distance_inside;
         }
         None
     }
@@ -1,4 +1,10 @@
+//! [Perlin noise][perlin]: smoothly varying pseudo-random values, for building procedural
+//! textures.
+//!
+//! [perlin]: https://en.wikipedia.org/wiki/Perlin_noise
+
 use rand::prelude::*;
+use rand::rngs::SmallRng;
 
 use crate::vec3::Vec3;
 
@@ -21,13 +27,7 @@ fn generate_vecs(rng: &mut impl Rng) -> Vec<Vec3> {
     f
 }
 
-lazy_static::lazy_static! {
-    pub static ref VECS: Vec<Vec3> = generate_vecs(&mut thread_rng());
-    pub static ref PERM_X: Vec<u8> = generate_perm(&mut thread_rng());
-    pub static ref PERM_Y: Vec<u8> = generate_perm(&mut thread_rng());
-    pub static ref PERM_Z: Vec<u8> = generate_perm(&mut thread_rng());
-}
-
+#[allow(clippy::needless_range_loop)]
 fn trilinear_interp(corners: &[[[Vec3; 2]; 2]; 2], uvw: Vec3) -> f64 {
     let mut accum = 0.;
     let uvw3 = uvw * uvw * (Vec3::from(3.) - 2. * uvw);
@@ -45,30 +45,59 @@ fn trilinear_interp(corners: &[[[Vec3; 2]; 2]; 2], uvw: Vec3) -> f64 {
     accum
 }
 
-pub fn noise(p: Vec3) -> f64 {
-    let ijk = p.map(f64::floor);
-    let uvw = p - ijk;
-    let mut corners = [[[Vec3::default(); 2]; 2]; 2];
-    for di in 0..2 {
-        for dj in 0..2 {
-            for dk in 0..2 {
-                let ix = PERM_X[((ijk.0 as i32 + di as i32) & 255) as usize];
-                let iy = PERM_Y[((ijk.1 as i32 + dj as i32) & 255) as usize];
-                let iz = PERM_Z[((ijk.2 as i32 + dk as i32) & 255) as usize];
-                corners[di][dj][dk] = VECS[(ix ^ iy ^ iz) as usize];
+/// A Perlin noise field. Fields made from the same seed are identical, and those made from
+/// different seeds are unrelated.
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    vecs: Vec<Vec3>,
+    perm_x: Vec<u8>,
+    perm_y: Vec<u8>,
+    perm_z: Vec<u8>,
+}
+
+impl Perlin {
+    /// Generates the noise field for `seed`.
+    pub fn new(seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        Perlin {
+            vecs: generate_vecs(&mut rng),
+            perm_x: generate_perm(&mut rng),
+            perm_y: generate_perm(&mut rng),
+            perm_z: generate_perm(&mut rng),
+        }
+    }
+
+    /// The value of the field at `p`, roughly between -1 and 1. It varies over distances of about
+    /// 1, and repeats every 256 units along each axis.
+    #[allow(clippy::needless_range_loop)]
+    pub fn noise(&self, p: Vec3) -> f64 {
+        let ijk = p.map(f64::floor);
+        let uvw = p - ijk;
+        let mut corners = [[[Vec3::default(); 2]; 2]; 2];
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let ix = self.perm_x[((ijk.0 as i32 + di as i32) & 255) as usize];
+                    let iy = self.perm_y[((ijk.1 as i32 + dj as i32) & 255) as usize];
+                    let iz = self.perm_z[((ijk.2 as i32 + dk as i32) & 255) as usize];
+                    corners[di][dj][dk] = self.vecs[(ix ^ iy ^ iz) as usize];
+                }
             }
         }
+        trilinear_interp(&corners, uvw)
     }
-    trilinear_interp(&corners, uvw)
-}
 
-pub fn turb(mut p: Vec3, depth: usize) -> f64 {
-    let mut accum = 0.;
-    let mut weight = 1.;
-    for _ in 0..depth {
-        accum += weight * noise(p);
-        weight *= 0.5;
-        p = 2. * p;
+    /// Turbulence: the sum of `depth` octaves of noise at doubling frequencies and halving
+    /// amplitudes, which gives the field detail at several scales. The result is between 0 and
+    /// about 1.
+    pub fn turb(&self, mut p: Vec3, depth: usize) -> f64 {
+        let mut accum = 0.;
+        let mut weight = 1.;
+        for _ in 0..depth {
+            accum += weight * self.noise(p);
+            weight *= 0.5;
+            p = 2. * p;
+        }
+        accum.abs()
     }
-    accum.abs()
 }
@@ -0,0 +1,714 @@
+//! Import of [PLY][ply] polygon files, as produced by 3D scanners.
+//!
+//! ASCII and binary (little- or big-endian) files are supported. Vertices must have `x`, `y` and
+//! `z` properties, and may also have normals (`nx`, `ny`, `nz`), surface coordinates (`u`/`v`,
+//! `s`/`t` or `texture_u`/`texture_v`) and colors (`red`, `green`, `blue`). Integer colors are
+//! taken to be sRGB-encoded, and floating point ones linear. Faces are read from the
+//! `vertex_indices` list of the `face` element, and polygons are split into triangles by ear
+//! clipping, as in the OBJ loader. Other elements and properties are skipped.
+//!
+//! Captured vertex colors can be rendered by pointing the mesh's material at them:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use ray_tracing::material::Material;
+//! use ray_tracing::mesh::TriangleMesh;
+//! use ray_tracing::{ply, texture};
+//!
+//! let geometry = Arc::new(ply::load("scan.ply").unwrap());
+//! let mesh = TriangleMesh {
+//!     material: Material::Lambertian {
+//!         albedo: texture::vertex_colors(geometry.clone()),
+//!     },
+//!     geometry,
+//! };
+//! ```
+//!
+//! [ply]: https://en.wikipedia.org/wiki/PLY_(file_format)
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::mesh::MeshData;
+use crate::obj::triangulate;
+use crate::texture::srgb_to_linear;
+use crate::vec3::Vec3;
+
+/// Error produced when a PLY file can't be loaded.
+#[derive(Debug)]
+pub enum PlyError {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The input is not a well-formed PLY file, or doesn't describe a triangle mesh.
+    Format(String),
+}
+
+impl std::fmt::Display for PlyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlyError::Io(e) => write!(f, "{}", e),
+            PlyError::Format(message) => write!(f, "invalid PLY file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlyError::Io(e) => Some(e),
+            PlyError::Format(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for PlyError {
+    fn from(e: io::Error) -> Self {
+        PlyError::Io(e)
+    }
+}
+
+fn format_error<T>(message: impl Into<String>) -> Result<T, PlyError> {
+    Err(PlyError::Format(message.into()))
+}
+
+/// Loads the PLY file at `path`.
+pub fn load(path: impl AsRef<Path>) -> Result<MeshData, PlyError> {
+    read(BufReader::new(File::open(path)?))
+}
+
+/// Reads a PLY file from `reader`.
+pub fn read(mut reader: impl BufRead) -> Result<MeshData, PlyError> {
+    let header = Header::read(&mut reader)?;
+
+    // The number of bytes left in the body, as far as we know, which limits how much memory the
+    // counts in the header can reserve. The length of a binary stream isn't known in advance, so
+    // its reservations are capped, and vectors grow past that as needed.
+    let mut text = String::new();
+    let (mut body, available) = match header.encoding {
+        Encoding::Ascii => {
+            reader.read_to_string(&mut text)?;
+            (Body::Ascii(text.split_whitespace()), text.len())
+        }
+        Encoding::BinaryLittleEndian => (
+            Body::Binary {
+                reader: &mut reader,
+                big_endian: false,
+            },
+            MAX_BINARY_RESERVATION,
+        ),
+        Encoding::BinaryBigEndian => (
+            Body::Binary {
+                reader: &mut reader,
+                big_endian: true,
+            },
+            MAX_BINARY_RESERVATION,
+        ),
+    };
+
+    let mut vertices = None;
+    let mut triangles = vec![];
+
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => vertices = Some(read_vertices(element, &mut body, available)?),
+            "face" => {
+                triangles.reserve(element.reservation(available));
+                let positions = vertices.as_ref().map(|v| &v.positions[..]);
+                read_faces(element, &mut body, positions, &mut triangles)?
+            }
+            _ => {
+                for _ in 0..element.count {
+                    for (_, property) in &element.properties {
+                        body.skip(property)?;
+                    }
+                }
+            }
+        }
+    }
+
+    let vertices = match vertices {
+        Some(v) => v,
+        None => return format_error("no vertex element"),
+    };
+    if triangles.is_empty() {
+        return format_error("no faces");
+    }
+    if let Some(&i) = triangles
+        .iter()
+        .flatten()
+        .find(|&&i| i as usize >= vertices.positions.len())
+    {
+        return format_error(format!("face refers to nonexistent vertex {}", i));
+    }
+
+    let mut mesh = MeshData::new(vertices.positions, triangles);
+    if let Some(normals) = vertices.normals {
+        mesh = mesh.with_normals(normals);
+    }
+    if let Some(uvs) = vertices.uvs {
+        mesh = mesh.with_uvs(uvs);
+    }
+    if let Some(colors) = vertices.colors {
+        mesh = mesh.with_colors(colors);
+    }
+    Ok(mesh)
+}
+
+/// Vertex attributes read from the `vertex` element.
+struct Vertices {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    colors: Option<Vec<Vec3>>,
+}
+
+fn read_vertices(
+    element: &Element,
+    body: &mut Body<'_, impl BufRead>,
+    available: usize,
+) -> Result<Vertices, PlyError> {
+    let find = |names: &[&str]| {
+        element
+            .properties
+            .iter()
+            .position(|(n, _)| names.contains(&n.as_str()))
+    };
+    let find3 = |a, b, c| Some([find(&[a])?, find(&[b])?, find(&[c])?]);
+
+    let position = match find3("x", "y", "z") {
+        Some(p) => p,
+        None => return format_error("vertices lack x, y and z"),
+    };
+    let normal = find3("nx", "ny", "nz");
+    let uv = find(&["u", "s", "texture_u"]).zip(find(&["v", "t", "texture_v"]));
+    let color = find3("red", "green", "blue")
+        .or_else(|| find3("diffuse_red", "diffuse_green", "diffuse_blue"));
+
+    let capacity = element.reservation(available);
+    let mut vertices = Vertices {
+        positions: Vec::with_capacity(capacity),
+        normals: normal.map(|_| Vec::with_capacity(capacity)),
+        uvs: uv.map(|_| Vec::with_capacity(capacity)),
+        colors: color.map(|_| Vec::with_capacity(capacity)),
+    };
+
+    let mut values = vec![0.; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, (_, property)) in values.iter_mut().zip(&element.properties) {
+            *value = match property {
+                Property::Scalar(scalar) => body.read(*scalar)?,
+                Property::List { .. } => {
+                    body.skip(property)?;
+                    0.
+                }
+            };
+        }
+
+        let vec3 = |[x, y, z]: [usize; 3]| Vec3(values[x], values[y], values[z]);
+        vertices.positions.push(vec3(position));
+        if let (Some(normals), Some(n)) = (&mut vertices.normals, normal) {
+            normals.push(vec3(n));
+        }
+        if let (Some(uvs), Some((u, v))) = (&mut vertices.uvs, uv) {
+            uvs.push((values[u], values[v]));
+        }
+        if let (Some(colors), Some(c)) = (&mut vertices.colors, color) {
+            // Integer colors are sRGB-encoded, and scaled to fill their type's range; floating
+            // point colors are already linear, and in [0, 1].
+            colors.push(match &element.properties[c[0]].1 {
+                Property::Scalar(Scalar::U8) => (vec3(c) / 255.).map(srgb_to_linear),
+                Property::Scalar(Scalar::U16) => (vec3(c) / 65535.).map(srgb_to_linear),
+                _ => vec3(c),
+            });
+        }
+    }
+
+    Ok(vertices)
+}
+
+/// Reads the `face` element, splitting its polygons into triangles. Polygons are clipped using the
+/// vertex `positions`, if the vertices came first; otherwise they're fanned out from their first
+/// vertex.
+fn read_faces(
+    element: &Element,
+    body: &mut Body<'_, impl BufRead>,
+    positions: Option<&[Vec3]>,
+    triangles: &mut Vec<[u32; 3]>,
+) -> Result<(), PlyError> {
+    let indices = element.properties.iter().position(|(n, p)| {
+        matches!(p, Property::List { .. }) && (n == "vertex_indices" || n == "vertex_index")
+    });
+    let indices = match indices {
+        Some(i) => i,
+        None => return format_error("faces lack a vertex_indices list"),
+    };
+
+    let mut polygon = vec![];
+    for _ in 0..element.count {
+        for (i, (_, property)) in element.properties.iter().enumerate() {
+            match property {
+                Property::List { count, item } if i == indices => {
+                    let n = body.read(*count)? as usize;
+                    polygon.clear();
+                    for _ in 0..n {
+                        polygon.push(vertex_index(body.read(*item)?)?);
+                    }
+                    if n < 3 {
+                        return format_error("face has fewer than 3 vertices");
+                    }
+                    let corners = positions.and_then(|positions| {
+                        polygon
+                            .iter()
+                            .map(|&i| positions.get(i as usize).copied())
+                            .collect::<Option<Vec<Vec3>>>()
+                    });
+                    match corners {
+                        Some(corners) => triangles.extend(
+                            triangulate(&corners)
+                                .into_iter()
+                                .map(|[a, b, c]| [polygon[a], polygon[b], polygon[c]]),
+                        ),
+                        // Out-of-range indices are reported once all the faces are read.
+                        None => {
+                            for k in 1..n - 1 {
+                                triangles.push([polygon[0], polygon[k], polygon[k + 1]]);
+                            }
+                        }
+                    }
+                }
+                _ => body.skip(property)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts a vertex index read from a face, which must be a whole number that fits in a `u32`.
+fn vertex_index(value: f64) -> Result<u32, PlyError> {
+    if value.fract() != 0. {
+        return format_error(format!("invalid vertex index {}", value));
+    }
+    match u32::try_from(value as i64) {
+        Ok(i) => Ok(i),
+        Err(_) => format_error(format!("invalid vertex index {}", value)),
+    }
+}
+
+/// The most bytes of a binary body assumed to remain when reserving space for its elements.
+const MAX_BINARY_RESERVATION: usize = 1 << 24;
+
+/// Data encoding of a PLY file's body.
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// Numeric type of a property.
+#[derive(Debug, Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, PlyError> {
+        Ok(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return format_error(format!("unknown property type {:?}", name)),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+/// A property of an element: either a single number, or a list of numbers preceded by its length.
+enum Property {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+/// An element declaration from the header, such as `element vertex 1000`.
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+impl Element {
+    /// How many items to reserve space for, when `available` bytes of the body remain. Every
+    /// property takes at least a byte, so the header can't claim more items than fit.
+    fn reservation(&self, available: usize) -> usize {
+        self.count.min(available / self.properties.len().max(1))
+    }
+}
+
+struct Header {
+    encoding: Encoding,
+    elements: Vec<Element>,
+}
+
+impl Header {
+    /// Reads the header, leaving `reader` at the start of the body.
+    fn read(reader: &mut impl BufRead) -> Result<Self, PlyError> {
+        let mut line = String::new();
+        let mut next_line = |line: &mut String| -> Result<(), PlyError> {
+            line.clear();
+            if reader.read_line(line)? == 0 {
+                return format_error("unexpected end of header");
+            }
+            Ok(())
+        };
+
+        next_line(&mut line)?;
+        if line.trim_end() != "ply" {
+            return format_error("missing \"ply\" magic number");
+        }
+
+        let mut encoding = None;
+        let mut elements: Vec<Element> = vec![];
+        loop {
+            next_line(&mut line)?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["format", format, _version] => {
+                    encoding = Some(match *format {
+                        "ascii" => Encoding::Ascii,
+                        "binary_little_endian" => Encoding::BinaryLittleEndian,
+                        "binary_big_endian" => Encoding::BinaryBigEndian,
+                        _ => return format_error(format!("unknown format {:?}", format)),
+                    })
+                }
+                ["element", name, count] => elements.push(Element {
+                    name: name.to_string(),
+                    count: match count.parse() {
+                        Ok(c) => c,
+                        Err(_) => return format_error(format!("invalid count {:?}", count)),
+                    },
+                    properties: vec![],
+                }),
+                ["property", rest @ ..] => {
+                    let (name, property) = match rest {
+                        ["list", count, item, name] => (
+                            name,
+                            Property::List {
+                                count: Scalar::parse(count)?,
+                                item: Scalar::parse(item)?,
+                            },
+                        ),
+                        [scalar, name] => (name, Property::Scalar(Scalar::parse(scalar)?)),
+                        _ => return format_error(format!("invalid property {:?}", line.trim())),
+                    };
+                    match elements.last_mut() {
+                        Some(e) => e.properties.push((name.to_string(), property)),
+                        None => return format_error("property outside of an element"),
+                    }
+                }
+                ["end_header"] => break,
+                ["comment", ..] | ["obj_info", ..] | [] => {}
+                _ => return format_error(format!("unexpected header line {:?}", line.trim())),
+            }
+        }
+
+        match encoding {
+            Some(encoding) => Ok(Header { encoding, elements }),
+            None => format_error("missing format"),
+        }
+    }
+}
+
+/// The body of a PLY file, from which property values are read in order.
+enum Body<'a, R> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary { reader: &'a mut R, big_endian: bool },
+}
+
+impl<R: BufRead> Body<'_, R> {
+    /// Reads a single value of type `scalar`.
+    fn read(&mut self, scalar: Scalar) -> Result<f64, PlyError> {
+        match self {
+            Body::Ascii(words) => match words.next() {
+                Some(word) => match word.parse() {
+                    Ok(v) => Ok(v),
+                    Err(_) => format_error(format!("invalid number {:?}", word)),
+                },
+                None => format_error("unexpected end of data"),
+            },
+            Body::Binary { reader, big_endian } => {
+                let mut buf = [0u8; 8];
+                let size = scalar.size();
+                reader.read_exact(&mut buf[..size])?;
+                if *big_endian {
+                    buf[..size].reverse();
+                }
+                // Bytes are now little-endian.
+                Ok(match scalar {
+                    Scalar::I8 => buf[0] as i8 as f64,
+                    Scalar::U8 => buf[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    Scalar::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    Scalar::I32 => i32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    Scalar::U32 => u32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    Scalar::F32 => f32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    Scalar::F64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+
+    /// Reads and discards a value of `property`.
+    fn skip(&mut self, property: &Property) -> Result<(), PlyError> {
+        match property {
+            Property::Scalar(scalar) => {
+                self.read(*scalar)?;
+            }
+            Property::List { count, item } => {
+                for _ in 0..self.read(*count)? as usize {
+                    self.read(*item)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::material::Material;
+    use crate::mesh::TriangleMesh;
+    use crate::object::Object;
+    use crate::ray::Ray;
+    use crate::texture;
+
+    fn read_ok(ply: &[u8]) -> MeshData {
+        match read(ply) {
+            Ok(mesh) => mesh,
+            Err(e) => panic!("failed to read: {}", e),
+        }
+    }
+
+    /// The components of `v`, for comparison.
+    fn rgb(v: Vec3) -> (f64, f64, f64) {
+        (v.0, v.1, v.2)
+    }
+
+    /// Whether a ray straight down the Z axis, through (`x`, `y`), hits `mesh`.
+    fn hits(mesh: &TriangleMesh, x: f64, y: f64) -> bool {
+        let ray = Ray {
+            origin: Vec3(x, y, 5.),
+            direction: Vec3(0., 0., -1.),
+            time: 0.,
+            differentials: None,
+        };
+        mesh.hit(&ray, 0.001..f64::MAX, &mut || 0.5).is_some()
+    }
+
+    #[test]
+    fn concave_face() {
+        // An L shape, starting next to its inner corner, where a fan from the first vertex would
+        // cover the notch.
+        let geometry = read_ok(
+            b"ply
+format ascii 1.0
+element vertex 6
+property float x
+property float y
+property float z
+element face 1
+property list uchar int vertex_indices
+end_header
+1 2 0
+0 2 0
+0 0 0
+2 0 0
+2 1 0
+1 1 0
+6 0 1 2 3 4 5
+",
+        );
+        assert_eq!(geometry.triangles().len(), 4);
+        let mesh = TriangleMesh {
+            geometry: Arc::new(geometry),
+            material: Material::Lambertian {
+                albedo: texture::constant(Vec3::from(0.5)),
+            },
+        };
+        assert!(hits(&mesh, 0.5, 1.5));
+        assert!(hits(&mesh, 1.5, 0.5));
+        assert!(!hits(&mesh, 1.8, 1.05));
+    }
+
+    #[test]
+    fn colors() {
+        let header = |kind: &str| {
+            format!(
+                "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+property {0} red
+property {0} green
+property {0} blue
+element face 1
+property list uchar int vertex_indices
+end_header
+",
+                kind
+            )
+        };
+
+        let bytes = header("uchar") + "0 0 0 255 0 0\n1 0 0 0 128 0\n0 1 0 0 0 10\n3 0 1 2\n";
+        let mesh = read_ok(bytes.as_bytes());
+        let colors = mesh.colors().unwrap();
+        assert_eq!(rgb(colors[0]), (1., 0., 0.));
+        assert_eq!(rgb(colors[1]), (0., srgb_to_linear(128. / 255.), 0.));
+        assert_eq!(rgb(colors[2]), (0., 0., srgb_to_linear(10. / 255.)));
+
+        // Floating point colors are already linear.
+        let bytes = header("float") + "0 0 0 0.5 0 0\n1 0 0 0 0.25 0\n0 1 0 0 0 1\n3 0 1 2\n";
+        let mesh = read_ok(bytes.as_bytes());
+        let colors = mesh.colors().unwrap();
+        assert_eq!(rgb(colors[0]), (0.5, 0., 0.));
+        assert_eq!(rgb(colors[1]), (0., 0.25, 0.));
+        assert_eq!(rgb(colors[2]), (0., 0., 1.));
+    }
+
+    /// A square with an extra vertex property, an extra face property and an extra element, all
+    /// of which are skipped, written in `format`. `data` writes each number of the body as
+    /// `(value, type)`.
+    fn square(format: &str, mut data: impl FnMut(&mut Vec<u8>, f64, Scalar)) -> Vec<u8> {
+        let mut bytes = format!(
+            "ply
+format {} 1.0
+comment made by hand
+element vertex 4
+property float x
+property float y
+property float z
+property uchar confidence
+element face 1
+property uchar flags
+property list uchar int vertex_indices
+element camera 1
+property double view
+end_header
+",
+            format
+        )
+        .into_bytes();
+        for [x, y] in [[0., 0.], [1., 0.], [1., 1.], [0., 1.]] {
+            for value in [x, y, 0.] {
+                data(&mut bytes, value, Scalar::F32);
+            }
+            data(&mut bytes, 200., Scalar::U8);
+        }
+        data(&mut bytes, 7., Scalar::U8);
+        data(&mut bytes, 4., Scalar::U8);
+        for i in 0..4 {
+            data(&mut bytes, i as f64, Scalar::I32);
+        }
+        data(&mut bytes, 1.5, Scalar::F64);
+        bytes
+    }
+
+    fn binary(big_endian: bool) -> impl FnMut(&mut Vec<u8>, f64, Scalar) {
+        move |bytes, value, scalar| {
+            let mut b = match scalar {
+                Scalar::U8 => vec![value as u8],
+                Scalar::I32 => (value as i32).to_le_bytes().to_vec(),
+                Scalar::F32 => (value as f32).to_le_bytes().to_vec(),
+                Scalar::F64 => value.to_le_bytes().to_vec(),
+                _ => unreachable!(),
+            };
+            if big_endian {
+                b.reverse();
+            }
+            bytes.extend(b);
+        }
+    }
+
+    fn read_err(ply: &[u8], message: &str) {
+        match read(ply) {
+            Err(PlyError::Format(m)) if m.contains(message) => {}
+            Err(e) => panic!("expected an error about {:?}, got {}", message, e),
+            Ok(_) => panic!("expected an error about {:?}", message),
+        }
+    }
+
+    #[test]
+    fn ascii_and_binary_agree() {
+        let ascii = square("ascii", |bytes, value, _| {
+            bytes.extend(format!("{} ", value).into_bytes())
+        });
+        let meshes = [
+            read_ok(&ascii),
+            read_ok(&square("binary_little_endian", binary(false))),
+            read_ok(&square("binary_big_endian", binary(true))),
+        ];
+        let triangles = |mesh: &MeshData| {
+            let mut triangles = mesh.triangles().to_vec();
+            triangles.sort();
+            triangles
+        };
+        for mesh in &meshes {
+            let positions: Vec<_> = mesh.positions().iter().map(|&p| rgb(p)).collect();
+            assert_eq!(
+                positions,
+                [(0., 0., 0.), (1., 0., 0.), (1., 1., 0.), (0., 1., 0.)]
+            );
+            assert_eq!(mesh.triangles().len(), 2);
+            assert_eq!(triangles(mesh), triangles(&meshes[0]));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        read_err(b"obj\n", "missing \"ply\" magic number");
+        read_err(
+            b"ply\nformat ascii 1.0\nelement vertex 1\n",
+            "unexpected end of header",
+        );
+        read_err(
+            b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nend_header\n",
+            "vertices lack x, y and z",
+        );
+        let truncated = square("binary_little_endian", binary(false));
+        assert!(read(&truncated[..truncated.len() - 20]).is_err());
+        let bad_index = square("ascii", |bytes, value, scalar| {
+            let value = if matches!(scalar, Scalar::I32) && value == 3. {
+                9.
+            } else {
+                value
+            };
+            bytes.extend(format!("{} ", value).into_bytes())
+        });
+        read_err(&bad_index, "face refers to nonexistent vertex 9");
+    }
+}
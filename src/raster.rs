@@ -0,0 +1,748 @@
+//! Raster images, for use as height maps, textures and the like.
+//!
+//! Images can be loaded from:
+//!
+//! - [Netpbm][pnm] graymaps (PGM) and pixmaps (PPM), in either their ASCII or binary forms, with
+//!   8 or 16 bits per sample.
+//! - PNG files of any color type and bit depth. Transparency is ignored.
+//! - [Radiance HDR][hdr] files and [Portable FloatMaps][pfm] (PFM), which store unbounded, linear
+//!   light intensities.
+//!
+//! Sample values from Netpbm and PNG files are scaled to the range 0 to 1, but otherwise kept as
+//! stored. In particular, color images are usually sRGB-encoded; use `Raster::decode_srgb` to
+//! decode them before using them as colors.
+//!
+//! [pnm]: https://en.wikipedia.org/wiki/Netpbm
+//! [hdr]: https://en.wikipedia.org/wiki/RGBE_image_format
+//! [pfm]: https://www.pauldebevec.com/Research/HDR/PFM/
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::texture::srgb_to_linear;
+use crate::vec3::Vec3;
+
+/// Error produced when an image can't be loaded.
+#[derive(Debug)]
+pub enum RasterError {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The input is not a well-formed image of a supported format.
+    Format(String),
+}
+
+impl std::fmt::Display for RasterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RasterError::Io(e) => write!(f, "{}", e),
+            RasterError::Format(message) => write!(f, "invalid image: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RasterError::Io(e) => Some(e),
+            RasterError::Format(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for RasterError {
+    fn from(e: io::Error) -> Self {
+        RasterError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for RasterError {
+    fn from(e: png::DecodingError) -> Self {
+        match e {
+            png::DecodingError::IoError(e) => RasterError::Io(e),
+            e => RasterError::Format(e.to_string()),
+        }
+    }
+}
+
+fn format_error<T>(message: impl Into<String>) -> Result<T, RasterError> {
+    Err(RasterError::Format(message.into()))
+}
+
+/// The number of samples in an image of `width` by `height` pixels, each with `channels`
+/// samples, if it can be counted at all.
+fn sample_count(width: usize, height: usize, channels: usize) -> Result<usize, RasterError> {
+    match width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels))
+    {
+        Some(count) => Ok(count),
+        None => format_error("image too large"),
+    }
+}
+
+/// A rectangular grid of RGB pixels, stored row by row from the top.
+#[derive(Debug, Clone)]
+pub struct Raster {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+}
+
+impl Raster {
+    /// Creates an image `width` pixels wide and `height` pixels high from `pixels`, given row by
+    /// row from the top.
+    ///
+    /// # Panics
+    ///
+    /// If there are not exactly `width * height` pixels.
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "Raster must have width * height pixels."
+        );
+        Raster {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the pixel in column `x` and row `y`, counting from the top left.
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Returns the brightness of the pixel in column `x` and row `y`, using the Rec. 709
+    /// luminance weights. For grayscale images, this is just the pixel's value.
+    pub fn luminance(&self, x: usize, y: usize) -> f64 {
+        self.get(x, y).dot(Vec3(0.2126, 0.7152, 0.0722))
+    }
+
+    /// Converts an sRGB-encoded image, as most 8-bit images are, to linear intensities.
+    pub fn decode_srgb(mut self) -> Self {
+        for p in &mut self.pixels {
+            *p = p.map(srgb_to_linear);
+        }
+        self
+    }
+}
+
+/// Loads the image file at `path`, in any of the supported formats. The format is recognized from
+/// the start of the file, not its name.
+pub fn load(path: impl AsRef<Path>) -> Result<Raster, RasterError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let start = reader.fill_buf()?;
+    if start.starts_with(b"\x89PNG") {
+        read_png(reader)
+    } else if start.starts_with(b"#?") {
+        read_hdr(reader)
+    } else if start.starts_with(b"PF") || start.starts_with(b"Pf") {
+        read_pfm(reader)
+    } else {
+        read_pnm(reader)
+    }
+}
+
+/// Splits the four fields of a Netpbm-style header from the start of `bytes`. Fields are separated
+/// by whitespace, with comments running from `#` to the end of the line. Returns the fields and
+/// the position just after the last one.
+fn read_header(bytes: &[u8]) -> Result<(Vec<String>, usize), RasterError> {
+    let mut pos = 0;
+    let mut header = vec![];
+    while header.len() < 4 {
+        while pos < bytes.len() && (bytes[pos].is_ascii_whitespace() || bytes[pos] == b'#') {
+            if bytes[pos] == b'#' {
+                while pos < bytes.len() && bytes[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return format_error("truncated header");
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+    Ok((header, pos))
+}
+
+/// Reads a Netpbm graymap or pixmap from `reader`.
+pub fn read_pnm(mut reader: impl BufRead) -> Result<Raster, RasterError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    // The header is the magic number, width, height and maximum sample value.
+    let (header, pos) = read_header(&bytes)?;
+
+    let (channels, binary) = match header[0].as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        other => return format_error(format!("unsupported format {:?}", other)),
+    };
+    let number = |s: &str| match s.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => format_error(format!("invalid header value {:?}", s)),
+    };
+    let (width, height, max) = (
+        number(&header[1])?,
+        number(&header[2])?,
+        number(&header[3])?,
+    );
+    if max > 65535 {
+        return format_error("maximum value too large");
+    }
+
+    let count = sample_count(width, height, channels)?;
+    let samples: Vec<usize> = if binary {
+        // A single whitespace character separates the header from the data.
+        let data = &bytes[(pos + 1).min(bytes.len())..];
+        let size = if max > 255 { 2 } else { 1 };
+        if count
+            .checked_mul(size)
+            .is_none_or(|needed| data.len() < needed)
+        {
+            return format_error("not enough pixel data");
+        }
+        data.chunks_exact(size)
+            .take(count)
+            .map(|c| c.iter().fold(0, |n, &b| n << 8 | b as usize))
+            .collect()
+    } else {
+        let samples = String::from_utf8_lossy(&bytes[pos..])
+            .split_whitespace()
+            .take(count)
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .or_else(|_| format_error("invalid pixel value"))?;
+        if samples.len() < count {
+            return format_error("not enough pixel data");
+        }
+        samples
+    };
+
+    let scale = 1. / max as f64;
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|c| match *c {
+            [v] => Vec3::from(v as f64 * scale),
+            [r, g, b] => scale * Vec3(r as f64, g as f64, b as f64),
+            _ => unreachable!(),
+        })
+        .collect();
+    Ok(Raster::new(width, height, pixels))
+}
+
+/// Reads a PNG image from `reader`.
+pub fn read_png(reader: impl BufRead) -> Result<Raster, RasterError> {
+    let mut decoder = png::Decoder::new(reader);
+    // Expand palettes and sub-byte samples, so every sample is 8 or 16 bits.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+
+    let channels = info.color_type.samples();
+    let (size, max) = match info.bit_depth {
+        png::BitDepth::Sixteen => (2, 65535.),
+        _ => (1, 255.),
+    };
+    let pixels = data
+        .chunks_exact(info.line_size)
+        .take(info.height as usize)
+        .flat_map(|line| line.chunks_exact(channels * size).take(info.width as usize))
+        .map(|pixel| {
+            let sample = |i: usize| {
+                let bytes = &pixel[i * size..(i + 1) * size];
+                bytes.iter().fold(0, |n, &b| n << 8 | b as usize) as f64 / max
+            };
+            // Gray or RGB, possibly followed by alpha, which is dropped.
+            if channels < 3 {
+                Vec3::from(sample(0))
+            } else {
+                Vec3(sample(0), sample(1), sample(2))
+            }
+        })
+        .collect();
+    Ok(Raster::new(
+        info.width as usize,
+        info.height as usize,
+        pixels,
+    ))
+}
+
+/// Reads a Radiance HDR image from `reader`.
+///
+/// Only the usual orientation, with rows running from top to bottom and columns from left to
+/// right, is supported.
+pub fn read_hdr(mut reader: impl BufRead) -> Result<Raster, RasterError> {
+    // The header is a series of lines ending with a blank one, followed by a line giving the
+    // size.
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return format_error("missing HDR signature");
+    }
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return format_error("truncated header");
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return format_error(format!("unsupported pixel format {:?}", format));
+            }
+        }
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    let (height, width) = match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) if h > 0 && w > 0 => (h, w),
+            _ => return format_error(format!("invalid size {:?}", line.trim())),
+        },
+        _ => return format_error(format!("unsupported orientation {:?}", line.trim())),
+    };
+
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let mut pos = 0;
+    let mut next = || {
+        let byte = data.get(pos).copied();
+        pos += 1;
+        byte.map_or_else(|| format_error("not enough pixel data"), Ok)
+    };
+
+    // Runs can pack many pixels into a few bytes, so the size of the data doesn't limit the size
+    // of the image. But it does limit what's worth reserving up front, before the data has
+    // shown that the image really is that large.
+    let count = sample_count(width, height, 1)?;
+    let mut pixels = Vec::with_capacity(count.min(data.len()));
+    let mut scanline = vec![];
+    if scanline.try_reserve_exact(width).is_err() {
+        return format_error("image too large");
+    }
+    scanline.resize(width, [0u8; 4]);
+    for _ in 0..height {
+        let first = [next()?, next()?, next()?, next()?];
+        if (8..0x8000).contains(&width) && first[..2] == [2, 2] && first[2] & 0x80 == 0 {
+            // Run-length encoded, with each of the four components stored separately.
+            if (first[2] as usize) << 8 | first[3] as usize != width {
+                return format_error("scanline width mismatch");
+            }
+            for c in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = next()? as usize;
+                    let (count, run) = if count > 128 {
+                        (count - 128, Some(next()?))
+                    } else {
+                        (count, None)
+                    };
+                    if count == 0 || x + count > width {
+                        return format_error("bad run length");
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[c] = match run {
+                            Some(value) => value,
+                            None => next()?,
+                        };
+                    }
+                    x += count;
+                }
+            }
+        } else {
+            // Flat pixels, possibly with old-style runs: a pixel of (1, 1, 1, n) repeats the
+            // previous one n times, shifted left by 8 bits for each consecutive run.
+            if first[..3] == [1, 1, 1] {
+                return format_error("run with no previous pixel");
+            }
+            scanline[0] = first;
+            let (mut x, mut shift) = (1, 0);
+            while x < width {
+                let pixel = [next()?, next()?, next()?, next()?];
+                if pixel[..3] == [1, 1, 1] {
+                    let count = (pixel[3] as usize) << shift;
+                    if x + count > width {
+                        return format_error("bad run length");
+                    }
+                    let previous = scanline[x - 1];
+                    scanline[x..x + count].fill(previous);
+                    x += count;
+                    shift += 8;
+                } else {
+                    scanline[x] = pixel;
+                    x += 1;
+                    shift = 0;
+                }
+            }
+        }
+
+        pixels.extend(scanline.iter().map(|&[r, g, b, e]| {
+            // Each component is a mantissa sharing the exponent `e`.
+            if e == 0 {
+                Vec3::default()
+            } else {
+                let scale = 2f64.powi(e as i32 - 136);
+                scale * Vec3(r as f64, g as f64, b as f64)
+            }
+        }));
+    }
+    Ok(Raster::new(width, height, pixels))
+}
+
+/// Reads a Portable FloatMap, in color (`PF`) or grayscale (`Pf`), from `reader`.
+pub fn read_pfm(mut reader: impl BufRead) -> Result<Raster, RasterError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    // The header is the magic number, width, height and a scale whose sign gives the byte order.
+    let (header, pos) = read_header(&bytes)?;
+
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        other => return format_error(format!("unsupported format {:?}", other)),
+    };
+    let (width, height) = match (header[1].parse::<usize>(), header[2].parse::<usize>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => (w, h),
+        _ => return format_error("invalid size"),
+    };
+    let little_endian = match header[3].parse::<f64>() {
+        Ok(scale) if scale != 0. => scale < 0.,
+        _ => return format_error(format!("invalid scale {:?}", header[3])),
+    };
+
+    // A single whitespace character separates the header from the data.
+    let data = &bytes[(pos + 1).min(bytes.len())..];
+    let count = sample_count(width, height, channels)?;
+    if count
+        .checked_mul(4)
+        .is_none_or(|needed| data.len() < needed)
+    {
+        return format_error("not enough pixel data");
+    }
+    let samples: Vec<f64> = data
+        .chunks_exact(4)
+        .take(count)
+        .map(|c| {
+            let b = [c[0], c[1], c[2], c[3]];
+            let x = if little_endian {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            };
+            x as f64
+        })
+        .collect();
+
+    // Rows are stored from the bottom up.
+    let pixels = samples
+        .chunks_exact(width * channels)
+        .rev()
+        .flat_map(|row| row.chunks_exact(channels))
+        .map(|c| match *c {
+            [v] => Vec3::from(v),
+            [r, g, b] => Vec3(r, g, b),
+            _ => unreachable!(),
+        })
+        .collect();
+    Ok(Raster::new(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pixels of `image`, row by row, as tuples for comparison.
+    fn pixels(image: &Raster) -> Vec<(f64, f64, f64)> {
+        image.pixels.iter().map(|p| (p.0, p.1, p.2)).collect()
+    }
+
+    fn read_err(result: Result<Raster, RasterError>, message: &str) {
+        match result {
+            Err(RasterError::Format(m)) if m.contains(message) => {}
+            Err(e) => panic!("expected an error about {:?}, got {}", message, e),
+            Ok(_) => panic!("expected an error about {:?}", message),
+        }
+    }
+
+    #[test]
+    fn pnm_ascii_and_binary_agree() {
+        let ascii = read_pnm(&b"P3\n# two by one\n2 1\n255\n255 0 0  0 51 255\n"[..]).unwrap();
+        let binary = read_pnm(&b"P6 2 1 255\n\xff\x00\x00\x00\x33\xff"[..]).unwrap();
+        let wide = read_pnm(&b"P6 2 1 65535\n\xff\xff\x00\x00\x00\x00\x00\x00\x33\x33\xff\xff"[..])
+            .unwrap();
+        for image in [&ascii, &binary, &wide] {
+            assert_eq!((image.width(), image.height()), (2, 1));
+            assert_eq!(pixels(image), [(1., 0., 0.), (0., 0.2, 1.)]);
+        }
+
+        let gray = read_pnm(&b"P2 1 2 4 1 3"[..]).unwrap();
+        let binary_gray = read_pnm(&b"P5 1 2 4\n\x01\x03"[..]).unwrap();
+        for image in [&gray, &binary_gray] {
+            assert_eq!((image.width(), image.height()), (1, 2));
+            assert_eq!(pixels(image), [(0.25, 0.25, 0.25), (0.75, 0.75, 0.75)]);
+        }
+    }
+
+    #[test]
+    fn pnm_errors() {
+        read_err(read_pnm(&b"P4 1 1 1\n\x00"[..]), "unsupported format");
+        read_err(
+            read_pnm(&b"P3 1 1 70000\n1 1 1"[..]),
+            "maximum value too large",
+        );
+        read_err(read_pnm(&b"P3 0 1 255\n"[..]), "invalid header value");
+        read_err(
+            read_pnm(&b"P3 2 1 255\n1 2 3 4 5"[..]),
+            "not enough pixel data",
+        );
+        read_err(
+            read_pnm(&b"P6 2 1 255\n\x01\x02\x03"[..]),
+            "not enough pixel data",
+        );
+        read_err(read_pnm(&b"P3 1 1 255\n1 x 3"[..]), "invalid pixel value");
+        let huge = format!("P6 {} {} 255\n", usize::MAX / 2, 3);
+        read_err(read_pnm(huge.as_bytes()), "image too large");
+    }
+
+    /// Encodes a PNG image, `width` pixels wide, with the given color type, bit depth and data.
+    fn png(
+        width: u32,
+        height: u32,
+        color: png::ColorType,
+        depth: png::BitDepth,
+        palette: Option<&[u8]>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if let Some(palette) = palette {
+            encoder.set_palette(palette.to_vec());
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn png_formats_agree() {
+        use png::{BitDepth, ColorType};
+
+        let expected = [(1., 0., 0.), (0., 0.2, 1.)];
+        let images = [
+            png(
+                2,
+                1,
+                ColorType::Rgb,
+                BitDepth::Eight,
+                None,
+                &[255, 0, 0, 0, 51, 255],
+            ),
+            // The alpha channel is dropped.
+            png(
+                2,
+                1,
+                ColorType::Rgba,
+                BitDepth::Eight,
+                None,
+                &[255, 0, 0, 7, 0, 51, 255, 9],
+            ),
+            png(
+                2,
+                1,
+                ColorType::Rgb,
+                BitDepth::Sixteen,
+                None,
+                &[255, 255, 0, 0, 0, 0, 0, 0, 51, 51, 255, 255],
+            ),
+            // Two 4-bit palette indices, packed into one byte.
+            png(
+                2,
+                1,
+                ColorType::Indexed,
+                BitDepth::Four,
+                Some(&[0, 51, 255, 255, 0, 0]),
+                &[0x10],
+            ),
+        ];
+        for bytes in &images {
+            let image = read_png(&bytes[..]).unwrap();
+            assert_eq!((image.width(), image.height()), (2, 1));
+            assert_eq!(pixels(&image), expected);
+        }
+
+        let gray = png(
+            1,
+            2,
+            ColorType::Grayscale,
+            BitDepth::Eight,
+            None,
+            &[51, 255],
+        );
+        let image = read_png(&gray[..]).unwrap();
+        assert_eq!(pixels(&image), [(0.2, 0.2, 0.2), (1., 1., 1.)]);
+
+        assert!(read_png(&gray[..gray.len() / 2]).is_err());
+    }
+
+    fn hdr(width: usize, height: usize, data: &[u8]) -> Vec<u8> {
+        let mut bytes = format!(
+            "#?RADIANCE\n# made by hand\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=1\n\n-Y {} +X {}\n",
+            height, width
+        )
+        .into_bytes();
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn hdr_encodings_agree() {
+        // An exponent of 136 makes each mantissa its own value; 129 divides them by 128.
+        let (a, b) = ([1, 2, 3, 136], [128, 64, 0, 129]);
+        let expected: Vec<_> = [(1., 2., 3.), (1., 0.5, 0.)]
+            .into_iter()
+            .cycle()
+            .take(8)
+            .chain([(1., 2., 3.); 8])
+            .collect();
+
+        // Flat pixels.
+        let mut flat = vec![];
+        for pixel in [a, b].iter().cycle().take(8).chain([a; 8].iter()) {
+            flat.extend(pixel);
+        }
+        // An old-style run repeats the previous pixel.
+        let mut old_runs = flat[..32].to_vec();
+        old_runs.extend(a);
+        old_runs.extend([1, 1, 1, 7]);
+        // New-style runs store each component separately, as literals (count up to 128) or runs
+        // (count above 128).
+        let mut new_runs = vec![];
+        for scanline in [[a, b], [a, a]] {
+            new_runs.extend([2, 2, 0, 8]);
+            for (even, odd) in scanline[0].into_iter().zip(scanline[1]) {
+                if even == odd {
+                    new_runs.extend([128 + 8, even]);
+                } else {
+                    new_runs.push(8);
+                    new_runs.extend([even, odd].iter().cycle().take(8));
+                }
+            }
+        }
+
+        for data in [flat, old_runs, new_runs] {
+            let image = read_hdr(&hdr(8, 2, &data)[..]).unwrap();
+            assert_eq!((image.width(), image.height()), (8, 2));
+            assert_eq!(pixels(&image), expected);
+        }
+    }
+
+    #[test]
+    fn hdr_errors() {
+        read_err(read_hdr(&b"P6 1 1 255\n"[..]), "missing HDR signature");
+        read_err(
+            read_hdr(&b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n"[..]),
+            "truncated header",
+        );
+        read_err(
+            read_hdr(&b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n"[..]),
+            "unsupported pixel format",
+        );
+        read_err(
+            read_hdr(&b"#?RADIANCE\n\n+Y 1 +X 1\n\x01\x01\x01\x80"[..]),
+            "unsupported orientation",
+        );
+        read_err(
+            read_hdr(&hdr(2, 1, &[1, 2, 3, 136])[..]),
+            "not enough pixel data",
+        );
+        read_err(
+            read_hdr(&hdr(2, 1, &[1, 1, 1, 5])[..]),
+            "run with no previous pixel",
+        );
+        read_err(
+            read_hdr(&hdr(2, 1, &[1, 2, 3, 136, 1, 1, 1, 5])[..]),
+            "bad run length",
+        );
+        read_err(
+            read_hdr(&hdr(8, 1, &[2, 2, 0, 9, 0, 0, 0, 0])[..]),
+            "scanline width mismatch",
+        );
+        read_err(
+            read_hdr(&hdr(8, 1, &[2, 2, 0, 8, 128 + 9, 0])[..]),
+            "bad run length",
+        );
+    }
+
+    fn pfm(magic: &str, width: usize, height: usize, scale: f64, samples: &[f32]) -> Vec<u8> {
+        let mut bytes = format!("{}\n{} {}\n{}\n", magic, width, height, scale).into_bytes();
+        for x in samples {
+            if scale < 0. {
+                bytes.extend(x.to_le_bytes());
+            } else {
+                bytes.extend(x.to_be_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn pfm_byte_orders_agree() {
+        // Rows are stored from the bottom up, and values aren't limited to [0, 1].
+        let samples = [0.5, 0., 0., 0., 0., 0., 1., 2., 3., 0., 0., 40.];
+        for scale in [-1., 1.] {
+            let image = read_pfm(&pfm("PF", 2, 2, scale, &samples)[..]).unwrap();
+            assert_eq!((image.width(), image.height()), (2, 2));
+            assert_eq!(
+                pixels(&image),
+                [(1., 2., 3.), (0., 0., 40.), (0.5, 0., 0.), (0., 0., 0.)]
+            );
+        }
+
+        let gray = read_pfm(&pfm("Pf", 1, 2, -1., &[0.25, 8.])[..]).unwrap();
+        assert_eq!(pixels(&gray), [(8., 8., 8.), (0.25, 0.25, 0.25)]);
+    }
+
+    #[test]
+    fn pfm_errors() {
+        read_err(
+            read_pfm(&pfm("PX", 1, 1, -1., &[0.])[..]),
+            "unsupported format",
+        );
+        read_err(read_pfm(&pfm("Pf", 0, 1, -1., &[])[..]), "invalid size");
+        read_err(read_pfm(&pfm("Pf", 1, 1, 0., &[0.])[..]), "invalid scale");
+        read_err(
+            read_pfm(&pfm("PF", 2, 1, -1., &[0., 0., 0.])[..]),
+            "not enough pixel data",
+        );
+        read_err(
+            read_pfm(&pfm("PF", usize::MAX / 2, 3, -1., &[])[..]),
+            "image too large",
+        );
+    }
+}
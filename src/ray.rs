@@ -6,6 +6,10 @@ pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
     pub time: f64,
+    /// How the ray changes from one pixel to the next, if known. Textures use this to work out
+    /// how much of their surface a pixel covers, and blur themselves accordingly to avoid
+    /// aliasing.
+    pub differentials: Option<Differentials>,
 }
 
 impl Ray {
@@ -14,4 +18,30 @@ impl Ray {
     pub fn point_at_parameter(&self, t: f64) -> Vec3 {
         self.origin + t * self.direction
     }
+
+    /// Scales the ray's differentials by `x` horizontally and `y` vertically.
+    pub fn scale_differentials(self, x: f64, y: f64) -> Self {
+        Ray {
+            differentials: self.differentials.map(|d| Differentials {
+                dx_origin: x * d.dx_origin,
+                dx_direction: x * d.dx_direction,
+                dy_origin: y * d.dy_origin,
+                dy_direction: y * d.dy_direction,
+            }),
+            ..self
+        }
+    }
+}
+
+/// The rates of change of a ray's origin and direction as we move across the image, horizontally
+/// (`dx_*`) and vertically (`dy_*`). These are [ray differentials][igehy], which can be followed
+/// through reflection and refraction to find the area of a surface seen through one pixel.
+///
+/// [igehy]: https://graphics.stanford.edu/papers/trd/
+#[derive(Debug, Copy, Clone)]
+pub struct Differentials {
+    pub dx_origin: Vec3,
+    pub dx_direction: Vec3,
+    pub dy_origin: Vec3,
+    pub dy_direction: Vec3,
 }
@@ -0,0 +1,321 @@
+//! Procedural shapes defined by signed distance functions.
+//!
+//! A signed distance function (SDF) gives, for any point, the distance to the nearest point on a
+//! shape's surface: positive outside the shape, negative inside. Shapes defined this way are easy
+//! to blend, bend and repeat, which is awkward or impossible with meshes. This module provides a
+//! set of primitive SDFs and combinators for building up others, and `SdfObject`, which renders
+//! one by [sphere tracing][st].
+//!
+//! Sphere tracing relies on the function never *overestimating* the distance to the surface. All
+//! the functions here satisfy that, except as noted.
+//!
+//! [st]: https://en.wikipedia.org/wiki/Ray_marching#Sphere_tracing
+//!
+//! ```
+//! use ray_tracing::material::Material;
+//! use ray_tracing::sdf::{self, SdfObject};
+//! use ray_tracing::texture;
+//! use ray_tracing::vec3::Vec3;
+//!
+//! // A rounded box with a sphere melted into its top.
+//! let shape = sdf::smooth_union(
+//!     sdf::rounded_box(Vec3(1., 0.5, 1.), 0.1),
+//!     sdf::translate(sdf::sphere(0.6), Vec3(0., 0.6, 0.)),
+//!     0.2,
+//! );
+//! let object = SdfObject::new(
+//!     shape,
+//!     Vec3(-1.1, -0.6, -1.1),
+//!     Vec3(1.1, 1.3, 1.1),
+//!     Material::Lambertian {
+//!         albedo: texture::constant(Vec3::from(0.73)),
+//!     },
+//! );
+//! ```
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::object::{self, HitRecord, Object};
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+/// A signed distance function: for any point, the distance to the nearest surface, negative inside
+/// the shape.
+pub type Sdf = Arc<dyn Fn(Vec3) -> f64 + Send + Sync>;
+
+/// A sphere of radius `radius` centered on the origin.
+pub fn sphere(radius: f64) -> Sdf {
+    Arc::new(move |p| p.length() - radius)
+}
+
+/// A box centered on the origin, extending `half_size` from the center along each axis.
+pub fn cuboid(half_size: Vec3) -> Sdf {
+    Arc::new(move |p| {
+        let q = p.map(f64::abs) - half_size;
+        q.map(|x| x.max(0.)).length() + q.reduce(f64::max).min(0.)
+    })
+}
+
+/// A box like `cuboid`, but with its edges and corners rounded off to radius `radius`. The
+/// rounding is taken out of the box, so it still extends `half_size` from the center.
+pub fn rounded_box(half_size: Vec3, radius: f64) -> Sdf {
+    round(cuboid(half_size - Vec3::from(radius)), radius)
+}
+
+/// A torus lying in the XZ plane, centered on the origin, with the same dimensions as
+/// `shape::Torus`.
+pub fn torus(major_radius: f64, minor_radius: f64) -> Sdf {
+    Arc::new(move |p| {
+        let ring = (p.0 * p.0 + p.2 * p.2).sqrt() - major_radius;
+        (ring * ring + p.1 * p.1).sqrt() - minor_radius
+    })
+}
+
+/// A capsule: the points within `radius` of the line segment from `a` to `b`.
+pub fn capsule(a: Vec3, b: Vec3, radius: f64) -> Sdf {
+    Arc::new(move |p| {
+        let (pa, ba) = (p - a, b - a);
+        let h = (pa.dot(ba) / ba.dot(ba)).clamp(0., 1.);
+        (pa - h * ba).length() - radius
+    })
+}
+
+/// The [Mandelbulb][mb] fractal of the given `power` (8 is the classic choice), computed to
+/// `iterations` iterations. It fits within a sphere of radius 1.2 around the origin.
+///
+/// This is a distance *estimate*, which can overshoot slightly near the surface; use a step scale
+/// around 0.9 with `SdfObject`.
+///
+/// [mb]: https://en.wikipedia.org/wiki/Mandelbulb
+pub fn mandelbulb(power: f64, iterations: usize) -> Sdf {
+    Arc::new(move |p| {
+        let mut z = p;
+        let mut dr = 1.;
+        let mut r = z.length();
+        for _ in 0..iterations {
+            if r > 2. || r == 0. {
+                break;
+            }
+            // Raise z to the power in spherical coordinates, around Y, and track the derivative.
+            let theta = (z.1 / r).acos() * power;
+            let phi = f64::atan2(z.2, z.0) * power;
+            dr = r.powf(power - 1.) * power * dr + 1.;
+            z = r.powf(power)
+                * Vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                )
+                + p;
+            r = z.length();
+        }
+        if r == 0. {
+            0.
+        } else {
+            0.5 * r.ln() * r / dr
+        }
+    })
+}
+
+/// The space inside either `a` or `b`.
+pub fn union(a: Sdf, b: Sdf) -> Sdf {
+    Arc::new(move |p| a(p).min(b(p)))
+}
+
+/// The space inside both `a` and `b`.
+///
+/// Inside the shape this is exact, but outside it may underestimate, so tracing is slower.
+pub fn intersection(a: Sdf, b: Sdf) -> Sdf {
+    Arc::new(move |p| a(p).max(b(p)))
+}
+
+/// The space inside `a` but not `b`.
+pub fn difference(a: Sdf, b: Sdf) -> Sdf {
+    Arc::new(move |p| a(p).max(-b(p)))
+}
+
+/// Polynomial smooth minimum of `a` and `b`, blending over a distance of `k`.
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+    b + (a - b) * h - k * h * (1. - h)
+}
+
+/// Like `union`, but with a fillet of size roughly `k` where the surfaces meet, so the two shapes
+/// appear to melt together.
+pub fn smooth_union(a: Sdf, b: Sdf, k: f64) -> Sdf {
+    Arc::new(move |p| smooth_min(a(p), b(p), k))
+}
+
+/// Like `intersection`, but with the seam where the surfaces meet rounded over a distance of `k`.
+pub fn smooth_intersection(a: Sdf, b: Sdf, k: f64) -> Sdf {
+    Arc::new(move |p| -smooth_min(-a(p), -b(p), k))
+}
+
+/// Like `difference`, but with the edges of the cut rounded over a distance of `k`.
+pub fn smooth_difference(a: Sdf, b: Sdf, k: f64) -> Sdf {
+    Arc::new(move |p| -smooth_min(-a(p), b(p), k))
+}
+
+/// The shape `a`, moved by `offset`.
+pub fn translate(a: Sdf, offset: Vec3) -> Sdf {
+    Arc::new(move |p| a(p - offset))
+}
+
+/// The shape `a`, scaled by `factor` around the origin.
+pub fn scale(a: Sdf, factor: f64) -> Sdf {
+    Arc::new(move |p| a(p / factor) * factor)
+}
+
+/// The shape `a`, grown outward by `radius`, which rounds off its edges and corners.
+pub fn round(a: Sdf, radius: f64) -> Sdf {
+    Arc::new(move |p| a(p) - radius)
+}
+
+/// A shell of thickness `thickness` around the surface of `a`.
+pub fn onion(a: Sdf, thickness: f64) -> Sdf {
+    Arc::new(move |p| a(p).abs() - thickness / 2.)
+}
+
+/// Endless copies of `a`, repeated every `period` along each axis. A period of zero leaves that
+/// axis alone.
+///
+/// The copy at the origin must fit within a cell of the given size, centered on the origin, or
+/// its cut-off parts will be missing.
+pub fn repeat(a: Sdf, period: Vec3) -> Sdf {
+    Arc::new(move |p| {
+        a(p.zip_with(period, |x, period| {
+            if period == 0. {
+                x
+            } else {
+                x - period * (x / period).round()
+            }
+        }))
+    })
+}
+
+/// The shape `a`, twisted around the Y axis by `rate` radians per unit of height.
+///
+/// Twisting stretches space, so the result overestimates distances by a factor of up to
+/// `sqrt(1 + (rate * r)^2)` at distance `r` from the axis. Compensate with `SdfObject`'s step
+/// scale.
+pub fn twist(a: Sdf, rate: f64) -> Sdf {
+    Arc::new(move |p| {
+        let (s, c) = (rate * p.1).sin_cos();
+        a(Vec3(c * p.0 - s * p.2, p.1, s * p.0 + c * p.2))
+    })
+}
+
+/// Maximum number of steps taken along a ray before giving up on it.
+const MAX_STEPS: usize = 512;
+
+/// Distance from the surface at which a ray is considered to have hit it.
+const SURFACE_DISTANCE: f64 = 0.00001;
+
+/// An object whose surface is the zero set of a signed distance function.
+#[derive(Clone)]
+pub struct SdfObject {
+    distance: Sdf,
+    bounds: Aabb,
+    material: Material,
+    step_scale: f64,
+}
+
+impl SdfObject {
+    /// Renders the shape described by `distance`, which must lie entirely between the corners
+    /// `min` and `max`. Rays are only traced inside that box, so a tighter box is faster.
+    pub fn new(distance: Sdf, min: Vec3, max: Vec3, material: Material) -> Self {
+        SdfObject {
+            distance,
+            bounds: Aabb { min, max },
+            material,
+            step_scale: 1.,
+        }
+    }
+
+    /// Multiplies each step along a ray by `step_scale`, which should be less than 1 for distance
+    /// functions that can overestimate, such as those from `twist`. The smaller it is, the more
+    /// steps tracing takes.
+    pub fn with_step_scale(self, step_scale: f64) -> Self {
+        SdfObject { step_scale, ..self }
+    }
+
+    /// Estimates the outward surface normal at `p` from the gradient of the distance function,
+    /// sampled at the corners of a small tetrahedron.
+    fn normal(&self, p: Vec3) -> Vec3 {
+        const H: f64 = SURFACE_DISTANCE;
+        [
+            Vec3(1., -1., -1.),
+            Vec3(-1., -1., 1.),
+            Vec3(-1., 1., -1.),
+            Vec3(1., 1., 1.),
+        ]
+        .iter()
+        .map(|&k| (self.distance)(p + H * k) * k)
+        .fold(Vec3::default(), |a, b| a + b)
+        .into_unit()
+    }
+}
+
+impl std::fmt::Debug for SdfObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SdfObject")
+            .field("bounds", &self.bounds)
+            .field("step_scale", &self.step_scale)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Object for SdfObject {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let range = self.bounds.clip(ray, t_range);
+        if range.is_empty() {
+            return None;
+        }
+
+        // Distances are measured in space, but `t` is in units of the ray's direction.
+        let speed = ray.direction.length();
+        let mut t = range.start;
+        // Rays that start inside the shape (such as those refracted into it) trace the distance to
+        // the surface from the inside.
+        let side = (self.distance)(ray.point_at_parameter(t)).signum();
+
+        for _ in 0..MAX_STEPS {
+            let p = ray.point_at_parameter(t);
+            let d = side * (self.distance)(p);
+            if d < SURFACE_DISTANCE {
+                // There's no natural parameterization, so surface coordinates are left at zero.
+                let normal = self.normal(p);
+                let (tangent, bitangent) = object::orthonormal_basis(normal);
+                return Some(HitRecord {
+                    t,
+                    p,
+                    normal,
+                    uv: (0., 0.),
+                    tangent,
+                    bitangent,
+                    local_p: p,
+                    footprint: None,
+                    face: None,
+                    material: &self.material,
+                });
+            }
+            t += self.step_scale * d / speed;
+            if t >= range.end {
+                break;
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        self.bounds
+    }
+}
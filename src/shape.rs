@@ -0,0 +1,564 @@
+//! Analytic primitives beyond `Sphere` and `Rect`.
+//!
+//! Like `Sphere`, most of these are defined in a canonical position, around the Y axis with their
+//! base or center at the origin, and are placed in a scene with `Translate`, `rotate_y` or
+//! `transform`. `Plane` and `Quad` are instead specified directly in scene coordinates, since
+//! they're most often used for walls and floors.
+//!
+//! The solids (`Cylinder`, `Cone` and `Torus`) are closed, with outward-facing normals, so they
+//! can be used with `Material::Dielectric`, `ConstantMedium` and CSG.
+
+use std::f64::consts::PI;
+use std::ops::Range;
+
+use crate::aabb::Aabb;
+use crate::material::Material;
+use crate::object::{self, HitRecord, Object};
+use crate::ray::Ray;
+use crate::vec3::{Axis::*, Vec3};
+
+/// Computes the `u` surface coordinate of a point at `(x, z)` around the Y axis, with the same
+/// convention as `Sphere`: starting from -X and increasing counter-clockwise seen from above.
+fn angle_u(x: f64, z: f64) -> f64 {
+    (f64::atan2(-z, x) + PI) / (2. * PI)
+}
+
+/// The derivative of a point `(x, z)` with respect to `angle_u`.
+fn angle_tangent(x: f64, z: f64) -> Vec3 {
+    2. * PI * Vec3(z, 0., -x)
+}
+
+/// Computes the surface coordinates of a point `(x, z)` on a cap of radius `radius`, centered on
+/// the Y axis, by mapping the square around the cap onto the unit square.
+fn cap_uv(x: f64, z: f64, radius: f64) -> (f64, f64) {
+    ((x / radius + 1.) / 2., (z / radius + 1.) / 2.)
+}
+
+/// Finds where `ray` crosses the horizontal plane at height `y`, if it does so within `t_range`
+/// and within `radius` of the Y axis.
+fn hit_cap(ray: &Ray, y: f64, radius: f64, t_range: &Range<f64>) -> Option<(f64, Vec3)> {
+    let t = (y - ray.origin[Y]) / ray.direction[Y];
+    if !(t >= t_range.start && t < t_range.end) {
+        return None;
+    }
+    let p = ray.point_at_parameter(t);
+    if p[X] * p[X] + p[Z] * p[Z] > radius * radius {
+        return None;
+    }
+    Some((t, p))
+}
+
+/// Keeps whichever of two hits is nearer.
+fn nearer<'o>(a: Option<HitRecord<'o>>, b: Option<HitRecord<'o>>) -> Option<HitRecord<'o>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a.t <= b.t { a } else { b }),
+        (a, b) => a.or(b),
+    }
+}
+
+/// A solid circular cylinder of radius `radius`, standing on the XZ plane around the Y axis, and
+/// extending up to `height`. It's closed by flat caps at each end.
+///
+/// On the side, `u` runs around the axis as for `Sphere`, and `v` runs from bottom to top. On the
+/// caps, `u` and `v` run along X and Z.
+#[derive(Debug, Clone)]
+pub struct Cylinder {
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Object for Cylinder {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let (o, d) = (ray.origin, ray.direction);
+
+        // The side is where x^2 + z^2 = radius^2.
+        let a = d[X] * d[X] + d[Z] * d[Z];
+        let b = o[X] * d[X] + o[Z] * d[Z];
+        let c = o[X] * o[X] + o[Z] * o[Z] - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        let mut side = None;
+        if a > 0. && discriminant > 0. {
+            for &t in &[
+                (-b - discriminant.sqrt()) / a,
+                (-b + discriminant.sqrt()) / a,
+            ] {
+                let p = ray.point_at_parameter(t);
+                if t >= t_range.start && t < t_range.end && p[Y] >= 0. && p[Y] <= self.height {
+                    side = Some(HitRecord {
+                        t,
+                        p,
+                        normal: Vec3(p[X], 0., p[Z]) / self.radius,
+                        uv: (angle_u(p[X], p[Z]), p[Y] / self.height),
+                        tangent: angle_tangent(p[X], p[Z]),
+                        bitangent: Vec3(0., self.height, 0.),
+                        local_p: p,
+                        footprint: None,
+                        face: None,
+                        material: &self.material,
+                    });
+                    break;
+                }
+            }
+        }
+
+        let cap = |y: f64, normal: Vec3| {
+            hit_cap(ray, y, self.radius, &t_range).map(|(t, p)| HitRecord {
+                t,
+                p,
+                normal,
+                uv: cap_uv(p[X], p[Z], self.radius),
+                tangent: Vec3(2. * self.radius, 0., 0.),
+                bitangent: Vec3(0., 0., 2. * self.radius),
+                local_p: p,
+                footprint: None,
+                face: None,
+                material: &self.material,
+            })
+        };
+        nearer(
+            side,
+            nearer(
+                cap(0., Vec3(0., -1., 0.)),
+                cap(self.height, Vec3(0., 1., 0.)),
+            ),
+        )
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        Aabb {
+            min: Vec3(-self.radius, 0., -self.radius),
+            max: Vec3(self.radius, self.height, self.radius),
+        }
+    }
+}
+
+/// A solid circular cone with a base of radius `radius` on the XZ plane, centered on the Y axis,
+/// and its apex on the Y axis at `height`. The base is closed by a flat cap.
+///
+/// Surface coordinates are as for `Cylinder`.
+#[derive(Debug, Clone)]
+pub struct Cone {
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Object for Cone {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let (o, d) = (ray.origin, ray.direction);
+
+        // The side is where x^2 + z^2 = (k * (height - y))^2, with k the slope of the side.
+        let k2 = (self.radius / self.height).powi(2);
+        let h = self.height - o[Y];
+        let a = d[X] * d[X] + d[Z] * d[Z] - k2 * d[Y] * d[Y];
+        let b = o[X] * d[X] + o[Z] * d[Z] + k2 * h * d[Y];
+        let c = o[X] * o[X] + o[Z] * o[Z] - k2 * h * h;
+        let roots = if a.abs() < 1e-12 {
+            // The ray is parallel to the side, so crosses the (double) cone only once.
+            [-c / (2. * b), f64::NAN]
+        } else {
+            let discriminant = b * b - a * c;
+            if discriminant < 0. {
+                [f64::NAN; 2]
+            } else {
+                let (t0, t1) = (
+                    (-b - discriminant.sqrt()) / a,
+                    (-b + discriminant.sqrt()) / a,
+                );
+                [t0.min(t1), t0.max(t1)]
+            }
+        };
+
+        let mut side = None;
+        for &t in &roots {
+            let p = ray.point_at_parameter(t);
+            // This also excludes the upper nappe of the double cone, and NaN.
+            if t >= t_range.start && t < t_range.end && p[Y] >= 0. && p[Y] <= self.height {
+                // Moving up the side draws in towards the axis, reaching it at the apex.
+                let rho = (p[X] * p[X] + p[Z] * p[Z]).sqrt().max(f64::MIN_POSITIVE);
+                side = Some(HitRecord {
+                    t,
+                    p,
+                    normal: Vec3(p[X], k2 * (self.height - p[Y]), p[Z]).into_unit(),
+                    uv: (angle_u(p[X], p[Z]), p[Y] / self.height),
+                    tangent: angle_tangent(p[X], p[Z]),
+                    bitangent: Vec3(
+                        -self.radius * p[X] / rho,
+                        self.height,
+                        -self.radius * p[Z] / rho,
+                    ),
+                    local_p: p,
+                    footprint: None,
+                    face: None,
+                    material: &self.material,
+                });
+                break;
+            }
+        }
+
+        let base = hit_cap(ray, 0., self.radius, &t_range).map(|(t, p)| HitRecord {
+            t,
+            p,
+            normal: Vec3(0., -1., 0.),
+            uv: cap_uv(p[X], p[Z], self.radius),
+            tangent: Vec3(2. * self.radius, 0., 0.),
+            bitangent: Vec3(0., 0., 2. * self.radius),
+            local_p: p,
+            footprint: None,
+            face: None,
+            material: &self.material,
+        });
+        nearer(side, base)
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        Aabb {
+            min: Vec3(-self.radius, 0., -self.radius),
+            max: Vec3(self.radius, self.height, self.radius),
+        }
+    }
+}
+
+/// A flat disk of radius `radius` in the XZ plane, centered on the origin, facing up.
+///
+/// Surface coordinates run along X and Z, as for the caps of `Cylinder`.
+#[derive(Debug, Clone)]
+pub struct Disk {
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl Object for Disk {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        hit_cap(ray, 0., self.radius, &t_range).map(|(t, p)| HitRecord {
+            t,
+            p,
+            normal: Vec3(0., 1., 0.),
+            uv: cap_uv(p[X], p[Z], self.radius),
+            tangent: Vec3(2. * self.radius, 0., 0.),
+            bitangent: Vec3(0., 0., 2. * self.radius),
+            local_p: p,
+            footprint: None,
+            face: None,
+            material: &self.material,
+        })
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        Aabb {
+            min: Vec3(-self.radius, 0., -self.radius),
+            max: Vec3(self.radius, 0., self.radius),
+        }
+        .pad_flat()
+    }
+}
+
+/// A solid torus (doughnut) lying in the XZ plane, centered on the origin. `major_radius` is the
+/// distance from the center to the middle of the tube, and `minor_radius` is the radius of the
+/// tube.
+///
+/// `u` runs around the Y axis as for `Sphere`, and `v` runs around the tube, starting from its
+/// inner edge and passing over the top first.
+#[derive(Debug, Clone)]
+pub struct Torus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+impl Object for Torus {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let (big_r, small_r) = (self.major_radius, self.minor_radius);
+
+        // The quartic is badly conditioned for rays that start far away, so first work with a
+        // unit direction, from where the ray enters the torus's bounding sphere.
+        let scale = ray.direction.length();
+        let d = ray.direction / scale;
+        let bound = big_r + small_r;
+        let f = ray.origin.dot(d);
+        let discriminant = f * f - (ray.origin.dot(ray.origin) - bound * bound);
+        if discriminant <= 0. {
+            return None;
+        }
+        let start = (-f - discriminant.sqrt()).max(0.);
+        let o = ray.origin + start * d;
+
+        // Points on the surface satisfy (|p|^2 + R^2 - r^2)^2 = 4R^2 (x^2 + z^2). Substituting
+        // o + td for p gives a quartic in t.
+        let f = o.dot(d);
+        let g = o.dot(o) + big_r * big_r - small_r * small_r;
+        let four_r2 = 4. * big_r * big_r;
+        let coefficients = [
+            4. * f,
+            4. * f * f + 2. * g - four_r2 * (1. - d[Y] * d[Y]),
+            4. * f * g - four_r2 * 2. * (f - o[Y] * d[Y]),
+            g * g - four_r2 * (o.dot(o) - o[Y] * o[Y]),
+        ];
+
+        solve_quartic(coefficients)
+            .into_iter()
+            .map(|t| (t + start) / scale)
+            .find(|t| *t >= t_range.start && *t < t_range.end)
+            .map(|t| {
+                let p = ray.point_at_parameter(t);
+                // The nearest point on the circle through the middle of the tube.
+                let ring = big_r * Vec3(p[X], 0., p[Z]).into_unit();
+                let normal = (p - ring) / small_r;
+                let rho = Vec3(p[X], 0., p[Z]).length();
+                let outward = rho - big_r;
+                HitRecord {
+                    t,
+                    p,
+                    normal,
+                    uv: (
+                        angle_u(p[X], p[Z]),
+                        f64::atan2(p[Y], -outward).rem_euclid(2. * PI) / (2. * PI),
+                    ),
+                    tangent: angle_tangent(p[X], p[Z]),
+                    bitangent: 2. * PI * Vec3(p[Y] * p[X] / rho, -outward, p[Y] * p[Z] / rho),
+                    local_p: p,
+                    footprint: None,
+                    face: None,
+                    material: &self.material,
+                }
+            })
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        let r = self.major_radius + self.minor_radius;
+        Aabb {
+            min: Vec3(-r, -self.minor_radius, -r),
+            max: Vec3(r, self.minor_radius, r),
+        }
+    }
+}
+
+/// Finds the real roots of `t^4 + a t^3 + b t^2 + c t + d`, given `[a, b, c, d]`, in increasing
+/// order.
+///
+/// This uses Ferrari's method, followed by a couple of Newton iterations to recover the precision
+/// lost along the way.
+fn solve_quartic([a, b, c, d]: [f64; 4]) -> Vec<f64> {
+    // Substitute t = y - a/4 to get the depressed quartic y^4 + p y^2 + q y + r.
+    let a2 = a * a;
+    let p = b - 3. / 8. * a2;
+    let q = c - a * b / 2. + a2 * a / 8.;
+    let r = d - a * c / 4. + a2 * b / 16. - 3. / 256. * a2 * a2;
+
+    let mut ys = vec![];
+    let mut push_quadratic_roots = |b: f64, c: f64| {
+        // Roots of y^2 + by + c.
+        let discriminant = b * b - 4. * c;
+        if discriminant >= 0. {
+            let s = discriminant.sqrt();
+            ys.push((-b - s) / 2.);
+            ys.push((-b + s) / 2.);
+        }
+    };
+
+    if q.abs() < 1e-12 {
+        // Biquadratic: a quadratic in y^2.
+        let discriminant = p * p - 4. * r;
+        if discriminant >= 0. {
+            for z in [
+                (-p - discriminant.sqrt()) / 2.,
+                (-p + discriminant.sqrt()) / 2.,
+            ] {
+                if z >= 0. {
+                    ys.push(-z.sqrt());
+                    ys.push(z.sqrt());
+                }
+            }
+        }
+    } else {
+        // Add 2m(y^2) + m^2 + mp to both sides of y^4 + py^2 = -qy - r, choosing m so that both
+        // sides are perfect squares. That happens when m is a root of the resolvent cubic, which
+        // always has a positive root when q is nonzero.
+        let m = largest_cubic_root(p, p * p / 4. - r, -q * q / 8.);
+        let s = (2. * m).sqrt();
+        push_quadratic_roots(-s, p / 2. + m + q / (2. * s));
+        push_quadratic_roots(s, p / 2. + m - q / (2. * s));
+    }
+
+    let mut ts: Vec<f64> = ys
+        .into_iter()
+        .map(|y| {
+            let mut t = y - a / 4.;
+            for _ in 0..2 {
+                let value = (((t + a) * t + b) * t + c) * t + d;
+                let slope = ((4. * t + 3. * a) * t + 2. * b) * t + c;
+                if slope != 0. {
+                    t -= value / slope;
+                }
+            }
+            t
+        })
+        .collect();
+    ts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ts
+}
+
+/// Finds the largest real root of `x^3 + a x^2 + b x + c`.
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // Substitute x = y - a/3 to get the depressed cubic y^3 + py + q.
+    let p = b - a * a / 3.;
+    let q = 2. * a * a * a / 27. - a * b / 3. + c;
+    let discriminant = q * q / 4. + p * p * p / 27.;
+
+    let y = if discriminant >= 0. {
+        // One real root, by Cardano's formula.
+        let s = discriminant.sqrt();
+        (-q / 2. + s).cbrt() + (-q / 2. - s).cbrt()
+    } else {
+        // Three real roots, by the trigonometric method; the first is the largest.
+        let m = 2. * (-p / 3.).sqrt();
+        let theta = (3. * q / (p * m)).clamp(-1., 1.).acos() / 3.;
+        m * theta.cos()
+    };
+    y - a / 3.
+}
+
+/// An infinite plane through `point`, facing in direction `normal`.
+///
+/// The surface coordinates are distances from `point` along two directions in the plane, and so
+/// are unbounded.
+///
+/// A plane has no finite bounding box, so `Bvh` keeps it out of the hierarchy and tests it
+/// against every ray.
+#[derive(Debug, Clone)]
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+    pub material: Material,
+}
+
+impl Object for Plane {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let normal = self.normal.into_unit();
+        let t = (self.point - ray.origin).dot(normal) / ray.direction.dot(normal);
+        if !(t >= t_range.start && t < t_range.end) {
+            return None;
+        }
+
+        let p = ray.point_at_parameter(t);
+        let (tangent, bitangent) = object::orthonormal_basis(normal);
+        let offset = p - self.point;
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            uv: (offset.dot(tangent), offset.dot(bitangent)),
+            tangent,
+            bitangent,
+            local_p: p,
+            footprint: None,
+            face: None,
+            material: &self.material,
+        })
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        Aabb {
+            min: Vec3::from(f64::NEG_INFINITY),
+            max: Vec3::from(f64::INFINITY),
+        }
+    }
+}
+
+/// A parallelogram with one corner at `corner` and sides `u` and `v`, so that its corners are
+/// `corner`, `corner + u`, `corner + u + v` and `corner + v`. It faces in the direction of
+/// `u.cross(v)`.
+///
+/// The surface coordinates are the fractions of `u` and `v` from `corner` to the hit point.
+#[derive(Debug, Clone)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Material,
+}
+
+impl Object for Quad {
+    fn hit<'o>(
+        &'o self,
+        ray: &Ray,
+        t_range: Range<f64>,
+        _rng: &mut dyn FnMut() -> f64,
+    ) -> Option<HitRecord<'o>> {
+        let n = self.u.cross(&self.v);
+        let t = (self.corner - ray.origin).dot(n) / ray.direction.dot(n);
+        if !(t >= t_range.start && t < t_range.end) {
+            return None;
+        }
+
+        // Express the hit point in terms of u and v.
+        let p = ray.point_at_parameter(t);
+        let offset = p - self.corner;
+        let w = n / n.dot(n);
+        let alpha = w.dot(offset.cross(&self.v));
+        let beta = w.dot(self.u.cross(&offset));
+        if !((0. ..=1.).contains(&alpha) && (0. ..=1.).contains(&beta)) {
+            return None;
+        }
+
+        Some(HitRecord {
+            t,
+            p,
+            normal: n.into_unit(),
+            uv: (alpha, beta),
+            tangent: self.u,
+            bitangent: self.v,
+            local_p: p,
+            footprint: None,
+            face: None,
+            material: &self.material,
+        })
+    }
+
+    fn bounding_box(&self, _exposure: Range<f64>) -> Aabb {
+        let corners = [
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        corners
+            .into_iter()
+            .fold(
+                Aabb {
+                    min: self.corner,
+                    max: self.corner,
+                },
+                |b, c| Aabb {
+                    min: b.min.zip_with(c, f64::min),
+                    max: b.max.zip_with(c, f64::max),
+                },
+            )
+            .pad_flat()
+    }
+}
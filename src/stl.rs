@@ -0,0 +1,229 @@
+//! Import of [STL][stl] triangle meshes, as exported by CAD software.
+//!
+//! Both ASCII and binary files are supported. STL stores each facet with its own copy of its
+//! vertices, so vertices at identical positions are merged to produce an indexed mesh. Facet
+//! normals in the file are ignored in favor of normals computed from the vertices, since many
+//! exporters leave them zeroed.
+//!
+//! [stl]: https://en.wikipedia.org/wiki/STL_(file_format)
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::mesh::MeshData;
+use crate::vec3::Vec3;
+
+/// Error produced when an STL file can't be loaded.
+#[derive(Debug)]
+pub enum StlError {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The input is not a well-formed STL file.
+    Format(String),
+}
+
+impl std::fmt::Display for StlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StlError::Io(e) => write!(f, "{}", e),
+            StlError::Format(message) => write!(f, "invalid STL file: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StlError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StlError::Io(e) => Some(e),
+            StlError::Format(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for StlError {
+    fn from(e: io::Error) -> Self {
+        StlError::Io(e)
+    }
+}
+
+/// Loads the STL file at `path`.
+pub fn load(path: impl AsRef<Path>) -> Result<MeshData, StlError> {
+    read(BufReader::new(File::open(path)?))
+}
+
+/// Reads an STL file from `reader`.
+pub fn read(mut reader: impl Read) -> Result<MeshData, StlError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    let corners = if is_binary(&bytes) {
+        read_binary(&bytes)
+    } else {
+        read_ascii(&bytes)?
+    };
+    if corners.is_empty() {
+        return Err(StlError::Format("no facets".into()));
+    }
+
+    // Merge vertices with bit-identical positions.
+    let mut index: HashMap<[u64; 3], u32> = HashMap::new();
+    let mut positions = vec![];
+    let vertices: Vec<u32> = corners
+        .into_iter()
+        .map(|p| {
+            *index
+                .entry([p.0, p.1, p.2].map(f64::to_bits))
+                .or_insert_with(|| {
+                    positions.push(p);
+                    positions.len() as u32 - 1
+                })
+        })
+        .collect();
+    let triangles = vertices
+        .chunks_exact(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect();
+
+    Ok(MeshData::new(positions, triangles))
+}
+
+/// Determines whether `bytes` holds a binary STL file.
+///
+/// Binary files are supposed to begin with something other than `solid`, but many exporters put
+/// it there anyway. Instead, check whether the facet count in the binary header matches the
+/// length of the data.
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    bytes.len() == 84 + 50 * count || !bytes.starts_with(b"solid")
+}
+
+/// Reads facet corners from a binary STL file: an 80-byte header, a facet count, and 50 bytes per
+/// facet.
+fn read_binary(bytes: &[u8]) -> Vec<Vec3> {
+    let float = |b: &[u8]| f32::from_le_bytes(b.try_into().unwrap()) as f64;
+
+    bytes[84..]
+        .chunks_exact(50)
+        .flat_map(|facet| {
+            // Each facet has a normal, three vertices, and two bytes of attributes.
+            facet[12..48]
+                .chunks_exact(12)
+                .map(|v| Vec3(float(&v[0..4]), float(&v[4..8]), float(&v[8..12])))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Reads facet corners from the `vertex` statements of an ASCII STL file.
+fn read_ascii(bytes: &[u8]) -> Result<Vec<Vec3>, StlError> {
+    let text = std::str::from_utf8(bytes)
+        .map_err(|_| StlError::Format("ASCII file is not valid UTF-8".into()))?;
+
+    let mut corners = vec![];
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        if word != "vertex" {
+            continue;
+        }
+        let mut coord = || match words.next().map(str::parse::<f64>) {
+            Some(Ok(x)) => Ok(x),
+            _ => Err(StlError::Format("invalid vertex coordinates".into())),
+        };
+        corners.push(Vec3(coord()?, coord()?, coord()?));
+    }
+
+    if corners.len() % 3 != 0 {
+        return Err(StlError::Format("facet with other than 3 vertices".into()));
+    }
+    Ok(corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two facets making a unit square, sharing the edge from (1, 0, 0) to (0, 1, 0).
+    const FACETS: [[[f32; 3]; 3]; 2] = [
+        [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]],
+        [[1., 0., 0.], [1., 1., 0.], [0., 1., 0.]],
+    ];
+
+    fn ascii() -> String {
+        let mut text = String::from("solid square\n");
+        for facet in FACETS {
+            text += "  facet normal 0 0 1\n    outer loop\n";
+            for [x, y, z] in facet {
+                text += &format!("      vertex {} {} {}\n", x, y, z);
+            }
+            text += "    endloop\n  endfacet\n";
+        }
+        text + "endsolid square\n"
+    }
+
+    /// A binary file, with a header that starts with `solid` as some exporters write.
+    fn binary() -> Vec<u8> {
+        let mut bytes = b"solid square, but binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend((FACETS.len() as u32).to_le_bytes());
+        for facet in FACETS {
+            // Normals are ignored, so leave them zeroed, as many exporters do.
+            bytes.extend([0; 12]);
+            for x in facet.iter().flatten() {
+                bytes.extend(x.to_le_bytes());
+            }
+            bytes.extend([0; 2]);
+        }
+        bytes
+    }
+
+    fn read_err(bytes: &[u8], message: &str) {
+        match read(bytes) {
+            Err(StlError::Format(m)) if m.contains(message) => {}
+            Err(e) => panic!("expected an error about {:?}, got {}", message, e),
+            Ok(_) => panic!("expected an error about {:?}", message),
+        }
+    }
+
+    #[test]
+    fn ascii_and_binary_agree() {
+        let from_ascii = read(ascii().as_bytes()).unwrap();
+        let from_binary = read(&binary()[..]).unwrap();
+        for mesh in [&from_ascii, &from_binary] {
+            // The shared corners are merged.
+            assert_eq!(mesh.positions().len(), 4);
+            assert_eq!(mesh.triangles().len(), 2);
+        }
+        let corners = |mesh: &MeshData| {
+            let mut corners: Vec<_> = mesh
+                .triangles()
+                .iter()
+                .map(|t| {
+                    t.map(|i| mesh.positions()[i as usize])
+                        .map(|p| [p.0, p.1, p.2])
+                })
+                .collect();
+            corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            corners
+        };
+        assert_eq!(corners(&from_ascii), corners(&from_binary));
+    }
+
+    #[test]
+    fn errors() {
+        read_err(b"", "no facets");
+        read_err(b"solid empty\nendsolid empty\n", "no facets");
+        read_err(
+            b"solid x\nfacet normal 0 0 1\nouter loop\nvertex 0 0 zero\n",
+            "invalid vertex coordinates",
+        );
+        read_err(
+            b"solid x\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nendloop\n",
+            "facet with other than 3 vertices",
+        );
+    }
+}
@@ -1,26 +1,403 @@
 use std::sync::Arc;
 
-use crate::perlin;
+use crate::mesh::MeshData;
+use crate::noise::{Fbm, Feature, Noise, Simplex, Warp, Worley};
+use crate::object::HitRecord;
+use crate::perlin::Perlin;
+use crate::raster::Raster;
 use crate::vec3::Vec3;
 
-pub type Texture = Arc<dyn Fn(Vec3) -> Vec3 + Send + Sync>;
+/// A color that varies over a surface, computed from the details of where a ray hit it.
+///
+/// Solid textures, which fill space like the grain of a block of wood, should use the hit's
+/// `local_p`, so they stay put on the object however it's placed. Textures that wrap around the
+/// surface, like an image, should use its `uv`.
+pub type Texture = Arc<dyn Fn(&HitRecord) -> Vec3 + Send + Sync>;
 
 pub fn constant(color: Vec3) -> Texture {
     Arc::new(move |_| color)
 }
 
+/// Alternates between `t0` and `t1` in a three-dimensional grid of cubes, about 0.3 units on a
+/// side, filling the space around the object.
+///
+/// Where a pixel covers several cubes, the two textures are blended in proportion. The size of
+/// the pixel is measured in the scene, so this only blurs correctly on objects that aren't scaled.
 pub fn checker(t0: Texture, t1: Texture) -> Texture {
-    Arc::new(move |p| {
-        let s = (10. * p).map(f64::sin).reduce(std::ops::Mul::mul);
-        if s < 0. {
-            t1(p)
+    // Each cube is half a period of sin(10 x) wide.
+    const FREQUENCY: f64 = 10. / std::f64::consts::PI;
+    Arc::new(move |hit| {
+        let p = FREQUENCY * hit.local_p;
+        let width = hit.footprint.map_or(Vec3::default(), |f| {
+            FREQUENCY * (f.dpdx.map(f64::abs) + f.dpdy.map(f64::abs))
+        });
+        let s = p
+            .zip_with(width, filtered_square_wave)
+            .reduce(std::ops::Mul::mul);
+        blend(s, &t0, &t1, hit)
+    })
+}
+
+/// Alternates between `t0` and `t1` in a grid of `cells.0` by `cells.1` squares over the surface
+/// coordinates, like a chessboard printed on the surface.
+pub fn uv_checker(t0: Texture, t1: Texture, cells: (f64, f64)) -> Texture {
+    Arc::new(move |hit| {
+        let (u, v) = (hit.uv.0 * cells.0, hit.uv.1 * cells.1);
+        let (width_u, width_v) = hit.footprint.map_or((0., 0.), |f| {
+            (
+                cells.0 * (f.duvdx.0.abs() + f.duvdy.0.abs()),
+                cells.1 * (f.duvdx.1.abs() + f.duvdy.1.abs()),
+            )
+        });
+        let s = filtered_square_wave(u, width_u) * filtered_square_wave(v, width_v);
+        blend(s, &t0, &t1, hit)
+    })
+}
+
+/// Averages a square wave, which is 1 for `x` between 0 and 1 and -1 between 1 and 2, repeating,
+/// over an interval of length `width` centered on `x`.
+fn filtered_square_wave(x: f64, width: f64) -> f64 {
+    // The integral of the square wave: a triangle wave.
+    fn integral(x: f64) -> f64 {
+        1. - (x.rem_euclid(2.) - 1.).abs()
+    }
+
+    if width < 1e-6 {
+        if x.rem_euclid(2.) < 1. {
+            1.
         } else {
-            t0(p)
+            -1.
+        }
+    } else {
+        (integral(x + width / 2.) - integral(x - width / 2.)) / width
+    }
+}
+
+/// Mixes `t0` and `t1` at `hit` according to `s`, using only `t0` when `s` is 1 and only `t1` when
+/// `s` is -1.
+fn blend(s: f64, t0: &Texture, t1: &Texture, hit: &HitRecord) -> Vec3 {
+    let weight = (1. + s) / 2.;
+    if weight >= 1. {
+        t0(hit)
+    } else if weight <= 0. {
+        t1(hit)
+    } else {
+        weight * t0(hit) + (1. - weight) * t1(hit)
+    }
+}
+
+/// Grayscale turbulence from the noise field `noise`, stretched by a factor of `1 / scale`.
+pub fn perlin(scale: f64, noise: Perlin) -> Texture {
+    Arc::new(move |hit| Vec3::from(noise.turb(scale * hit.local_p, 7)))
+}
+
+/// Linearly interpolates between `a` and `b`, by `t` from 0 to 1.
+fn lerp(a: Vec3, b: Vec3, t: f64) -> Vec3 {
+    (1. - t) * a + t * b
+}
+
+/// Smoothly steps from 0, for `x` at `edge0` or below, to 1, for `x` at `edge1` or above.
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// White marble with dark gray veins, running roughly across Z, with features about `1 / scale`
+/// across.
+pub fn marble(scale: f64, seed: u64) -> Texture {
+    let noise = Perlin::new(seed);
+    Arc::new(move |hit| {
+        let p = scale * hit.local_p;
+        // Bands along Z, pushed back and forth by turbulence.
+        let bands = 0.5 * (1. + f64::sin(p.2 + 10. * noise.turb(p, 7)));
+        lerp(Vec3(0.25, 0.27, 0.3), Vec3(0.9, 0.9, 0.88), bands.powf(0.5))
+    })
+}
+
+/// Wood, with growth rings around the Y axis about `1 / scale` apart, like a log standing on end.
+pub fn wood(scale: f64, seed: u64) -> Texture {
+    let grain = Fbm::new(Simplex::new(seed), 4);
+    let wobble = Simplex::new(seed.wrapping_add(1));
+    Arc::new(move |hit| {
+        let p = scale * hit.local_p;
+        // The rings aren't quite circular, and vary in thickness.
+        let radius =
+            (p.0 * p.0 + p.2 * p.2).sqrt() + 0.3 * wobble.sample(Vec3(p.0, 0.1 * p.1, p.2));
+        let ring = radius.rem_euclid(1.);
+        // Each ring is mostly light earlywood, ending in a narrow band of dark latewood.
+        let dark = smoothstep(0.6, 0.9, ring) * (1. - smoothstep(0.95, 1., ring));
+        let fibers = 0.1 * grain.sample(Vec3(8. * p.0, 0.5 * p.1, 8. * p.2));
+        lerp(
+            Vec3(0.76, 0.55, 0.33),
+            Vec3(0.42, 0.25, 0.12),
+            (dark + fibers).clamp(0., 1.),
+        )
+    })
+}
+
+/// Speckled gray and pink granite, with crystals about `1 / scale` across.
+pub fn granite(scale: f64, seed: u64) -> Texture {
+    let crystals = Worley::new(seed, Feature::F1);
+    let feldspar = Worley::new(seed.wrapping_add(1), Feature::F1);
+    let mottling = Fbm::new(Simplex::new(seed.wrapping_add(2)), 4);
+    Arc::new(move |hit| {
+        let p = scale * hit.local_p;
+        let base = lerp(
+            Vec3(0.55, 0.55, 0.53),
+            Vec3(0.7, 0.68, 0.66),
+            0.5 + 0.5 * mottling.sample(p),
+        );
+        // Scattered dark crystals, and larger pink ones in a coarser arrangement.
+        let pink = 1. - smoothstep(0.2, 0.35, feldspar.sample(0.5 * p));
+        let color = lerp(base, Vec3(0.72, 0.5, 0.45), 0.8 * pink);
+        let black = 1. - smoothstep(0.15, 0.3, crystals.sample(2. * p));
+        lerp(color, Vec3(0.08, 0.08, 0.09), black)
+    })
+}
+
+/// White clouds on a blue sky, billowing over distances of about `1 / scale`. `cover`, from 0 to
+/// 1, is the fraction of the sky they fill, roughly.
+pub fn clouds(scale: f64, cover: f64, seed: u64) -> Texture {
+    let noise = Warp {
+        noise: Fbm::new(Simplex::new(seed), 6),
+        warp: Fbm::new(Simplex::new(seed.wrapping_add(1)), 3),
+        strength: 0.5,
+    };
+    Arc::new(move |hit| {
+        let density = 0.5 + 0.5 * noise.sample(scale * hit.local_p);
+        // Fbm values cluster around the middle of the range; spread them over the cover.
+        let threshold = 0.5 + 0.3 * (1. - 2. * cover);
+        let cloud = smoothstep(threshold - 0.05, threshold + 0.2, density);
+        lerp(Vec3(0.35, 0.55, 0.9), Vec3(0.95, 0.95, 0.97), cloud)
+    })
+}
+
+/// Colors a surface using the vertex colors of `mesh`, interpolated across each face, for use on
+/// a `TriangleMesh` made from `mesh`. Other objects, and meshes without colors, are black.
+///
+/// The texture is looked up by the face and barycentric weights the mesh records in the hit (see
+/// `HitRecord::face`), so it lines up with the mesh however the mesh is placed in the scene.
+pub fn vertex_colors(mesh: Arc<MeshData>) -> Texture {
+    Arc::new(move |hit| match (mesh.colors(), hit.face) {
+        (Some(colors), Some((face, b1, b2))) if face < mesh.triangles().len() => {
+            mesh.interpolate(colors, face, b1, b2)
         }
+        _ => Vec3::default(),
+    })
+}
+
+/// How an image texture blends between the pixels around the point being looked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Uses the single nearest pixel, so pixels show as sharp-edged squares up close. When
+    /// mipmapping, the nearest level is used as well.
+    Nearest,
+    /// Blends the four nearest pixels linearly. When mipmapping, the two nearest levels are
+    /// blended too.
+    Bilinear,
+    /// Blends the sixteen nearest pixels with Catmull-Rom splines, which is smoother than
+    /// `Bilinear` when magnified, while staying about as sharp.
+    Bicubic,
+}
+
+/// What an image texture shows outside the range 0 to 1 of surface coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    /// Tiles the image endlessly.
+    Repeat,
+    /// Extends the pixels at the edges of the image outward.
+    Clamp,
+    /// Tiles the image, flipping every other copy so that neighboring copies meet seamlessly.
+    Mirror,
+}
+
+impl Wrap {
+    /// Maps the pixel index `i`, which may lie outside the image, to one inside an image `size`
+    /// pixels across.
+    fn index(self, i: isize, size: usize) -> usize {
+        let size = size as isize;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// Wraps `image` around a surface, by its surface coordinates: `u` runs across the image from left
+/// to right and `v` runs up it from the bottom.
+///
+/// The image's pixels are used as they are, so sRGB images should be decoded first with
+/// `Raster::decode_srgb`. High dynamic range images work as well, and are particularly useful for
+/// `Material::DiffuseLight`.
+///
+/// When the hit has a footprint, the texture is [mipmapped][mip]: looked up in a copy of the image
+/// shrunk until its pixels are about the size of the footprint, so that distant surfaces don't
+/// sparkle. Where the footprint is long and thin, as on a surface seen at a grazing angle, several
+/// lookups are made along its length so the image isn't blurred more than it needs to be.
+///
+/// ```no_run
+/// use ray_tracing::material::Material;
+/// use ray_tracing::raster;
+/// use ray_tracing::texture::{self, Filter, Wrap};
+///
+/// let earth = raster::load("earth.png").unwrap().decode_srgb();
+/// let material = Material::Lambertian {
+///     albedo: texture::image(earth, Filter::Bilinear, Wrap::Repeat),
+/// };
+/// ```
+///
+/// [mip]: https://en.wikipedia.org/wiki/Mipmap
+pub fn image(image: Raster, filter: Filter, wrap: Wrap) -> Texture {
+    // Most lookups made along a footprint.
+    const MAX_ANISOTROPY: f64 = 8.;
+
+    let levels = mip_levels(image, wrap);
+    let (width, height) = (levels[0].width() as f64, levels[0].height() as f64);
+    let last_level = (levels.len() - 1) as f64;
+
+    Arc::new(move |hit| {
+        let (u, v) = hit.uv;
+        let footprint = match hit.footprint {
+            Some(f) => f,
+            None => return sample(&levels[0], u, v, filter, wrap),
+        };
+
+        // The footprint's axes, in pixels of the full-size image.
+        let axes = [footprint.duvdx, footprint.duvdy];
+        let length = |(du, dv): (f64, f64)| (du * width).hypot(dv * height);
+        let (major, minor) = if length(axes[0]) >= length(axes[1]) {
+            (axes[0], axes[1])
+        } else {
+            (axes[1], axes[0])
+        };
+        let (major_length, minor_length) = (length(major), length(minor));
+        let probes = (major_length / minor_length.max(f64::MIN_POSITIVE))
+            .ceil()
+            .clamp(1., MAX_ANISOTROPY);
+
+        // Each lookup covers its share of the major axis, and all of the minor one.
+        let lod = (major_length / probes)
+            .max(minor_length)
+            .max(f64::MIN_POSITIVE)
+            .log2()
+            .clamp(0., last_level);
+        let lookup = |u: f64, v: f64| {
+            if filter == Filter::Nearest {
+                sample(&levels[lod.round() as usize], u, v, filter, wrap)
+            } else {
+                let (fine, blend) = (lod.floor(), lod.fract());
+                let a = sample(&levels[fine as usize], u, v, filter, wrap);
+                if blend == 0. {
+                    a
+                } else {
+                    let b = sample(&levels[fine as usize + 1], u, v, filter, wrap);
+                    (1. - blend) * a + blend * b
+                }
+            }
+        };
+
+        let n = probes as usize;
+        (0..n)
+            .map(|i| {
+                let offset = (i as f64 + 0.5) / probes - 0.5;
+                lookup(u + offset * major.0, v + offset * major.1)
+            })
+            .fold(Vec3::default(), |a, b| a + b)
+            / probes
     })
 }
 
-pub fn perlin(scale: f64) -> Texture {
-    Arc::new(move |p| Vec3::from(perlin::turb(scale * p, 7)))
-    //Arc::new(move |p| Vec3::from(0.5 * (1. + f64::sin(scale * p.2 + 10. * perlin::turb(p, 7)))))
+/// Builds the chain of successively halved copies of `image` used for mipmapping, starting with
+/// `image` itself and ending with a single pixel.
+fn mip_levels(image: Raster, wrap: Wrap) -> Vec<Raster> {
+    let mut levels = vec![image];
+    loop {
+        let last = levels.last().unwrap();
+        let (width, height) = (last.width(), last.height());
+        if width == 1 && height == 1 {
+            return levels;
+        }
+        // Each pixel averages a 2x2 block of the previous level. Blocks hanging off an edge of an
+        // odd-sized image take their missing pixels from wherever the wrap mode says.
+        let (new_width, new_height) = (width.div_ceil(2), height.div_ceil(2));
+        let pixels = (0..new_height)
+            .flat_map(|y| (0..new_width).map(move |x| (x as isize, y as isize)))
+            .map(|(x, y)| {
+                let texel = |x, y| texel(last, wrap, x, y);
+                0.25 * (texel(2 * x, 2 * y)
+                    + texel(2 * x + 1, 2 * y)
+                    + texel(2 * x, 2 * y + 1)
+                    + texel(2 * x + 1, 2 * y + 1))
+            })
+            .collect();
+        levels.push(Raster::new(new_width, new_height, pixels));
+    }
+}
+
+/// Returns the pixel of `image` in column `x` and row `y`, which may lie outside the image.
+fn texel(image: &Raster, wrap: Wrap, x: isize, y: isize) -> Vec3 {
+    image.get(wrap.index(x, image.width()), wrap.index(y, image.height()))
+}
+
+/// Looks up `image` at the surface coordinates `(u, v)`.
+fn sample(image: &Raster, u: f64, v: f64, filter: Filter, wrap: Wrap) -> Vec3 {
+    let texel = |x, y| texel(image, wrap, x, y);
+    // Pixel coordinates, in which pixel centers lie at half-integers.
+    let x = u * image.width() as f64;
+    let y = (1. - v) * image.height() as f64;
+    match filter {
+        Filter::Nearest => texel(x.floor() as isize, y.floor() as isize),
+        Filter::Bilinear => {
+            let (x, y) = (x - 0.5, y - 0.5);
+            let (x0, y0) = (x.floor(), y.floor());
+            let (fx, fy) = (x - x0, y - y0);
+            let (x0, y0) = (x0 as isize, y0 as isize);
+            (1. - fy) * ((1. - fx) * texel(x0, y0) + fx * texel(x0 + 1, y0))
+                + fy * ((1. - fx) * texel(x0, y0 + 1) + fx * texel(x0 + 1, y0 + 1))
+        }
+        Filter::Bicubic => {
+            let (x, y) = (x - 0.5, y - 0.5);
+            let (x0, y0) = (x.floor(), y.floor());
+            let (wx, wy) = (catmull_rom(x - x0), catmull_rom(y - y0));
+            let (x0, y0) = (x0 as isize, y0 as isize);
+            let mut sum = Vec3::default();
+            for (j, wy) in wy.iter().enumerate() {
+                for (i, wx) in wx.iter().enumerate() {
+                    sum += wx * wy * texel(x0 + i as isize - 1, y0 + j as isize - 1);
+                }
+            }
+            // The spline overshoots near sharp edges; don't let that go below black.
+            sum.map(|c| c.max(0.))
+        }
+    }
+}
+
+/// Catmull-Rom weights for the four pixels around a point `t` of the way from the second to the
+/// third.
+fn catmull_rom(t: f64) -> [f64; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2. * t2 - t),
+        0.5 * (3. * t3 - 5. * t2 + 2.),
+        0.5 * (-3. * t3 + 4. * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+/// Converts an sRGB-encoded color component to linear intensity.
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
 }
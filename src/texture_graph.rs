@@ -0,0 +1,1188 @@
+//! Textures described as expressions, which can be inspected, combined, saved and loaded.
+//!
+//! A texture closure from the `texture` module is opaque: once made, all you can do with it is
+//! evaluate it. A `Node` instead describes a texture as a tree of simple operations (noise, color
+//! ramps, arithmetic and so on), which can be built in code, written out as text, or parsed from
+//! text, and then compiled into a `Texture` for rendering.
+//!
+//! The text form is a nested expression of function calls. For example, this is blue-veined
+//! marble, tiled over the object:
+//!
+//! ```text
+//! # Veins where the warped noise crosses the middle of its range.
+//! ramp(
+//!     warp(scale(4, 4, 4, fbm(simplex(7), 5)), fbm(perlin(3), 3), 0.3),
+//!     0.45, rgb(0.95, 0.95, 0.93),
+//!     0.5, rgb(0.2, 0.25, 0.5),
+//!     0.55, rgb(0.95, 0.95, 0.93)
+//! )
+//! ```
+//!
+//! The functions are:
+//!
+//! | Expression | Meaning |
+//! |---|---|
+//! | `0.5` | A constant gray. |
+//! | `rgb(r, g, b)` | A constant color. |
+//! | `position` | The hit position in the object's coordinates, as a color. |
+//! | `uv` | The surface coordinates, as the red and green components of a color. |
+//! | `image("file.png", filter, wrap, encoding)` | An image, looked up by surface coordinates. `filter` is `nearest`, `bilinear` or `bicubic`; `wrap` is `repeat`, `clamp` or `mirror`; `encoding` is `srgb` or `linear`. |
+//! | `perlin(seed)`, `simplex(seed)` | Noise, from 0 to 1. `seed` is a whole number, at most 2^53. |
+//! | `worley(seed, feature)` | Cellular noise, where `feature` is `f1`, `f2` or `f2_minus_f1`. |
+//! | `fbm(noise, octaves)`, `ridged(noise, octaves)` | Fractal sums of one of the above noises, with up to 32 octaves. |
+//! | `add(a, b)`, `sub(a, b)`, `mul(a, b)`, `div(a, b)`, `min(a, b)`, `max(a, b)`, `pow(a, b)` | Arithmetic, on each color component. |
+//! | `mix(a, b, mask)` | `a` where `mask` is 0, `b` where it's 1, and a blend in between. |
+//! | `ramp(input, position, color, ...)` | Maps the brightness of `input` onto a gradient of colors at the given positions. |
+//! | `scale(x, y, z, input)`, `translate(x, y, z, input)`, `rotate(x, y, z, degrees, input)` | `input`, with the object's coordinates transformed. |
+//! | `matrix(m00, m01, ..., m33, input)` | `input`, with the object's coordinates transformed by a 4x4 matrix, given row by row. |
+//! | `uv_transform(scale_u, scale_v, offset_u, offset_v, input)` | `input`, with the surface coordinates scaled and then offset. |
+//! | `warp(input, by, strength)` | `input`, with the object's coordinates displaced by the color of `by`, from -1 to 1, times `strength`. |
+//! | `triplanar(input, sharpness)` | `input` projected onto the object along each axis, so that it can use surface coordinates on objects without them. |
+//!
+//! Numbers may also be `inf`, `-inf` or `NaN`. In strings, a backslash makes the next character
+//! literal, so `"a \"quoted\" name"` and `"C:\\textures"` can be written. Everything from `#` to
+//! the end of a line is a comment.
+//!
+//! ```
+//! use ray_tracing::material::Material;
+//! use ray_tracing::texture_graph::Node;
+//!
+//! let node: Node = "mix(rgb(0.8, 0.1, 0.1), 0.9, fbm(simplex(1), 4))".parse().unwrap();
+//! let material = Material::Lambertian {
+//!     albedo: node.compile(),
+//! };
+//! assert_eq!(
+//!     node.to_string(),
+//!     "mix(rgb(0.8, 0.1, 0.1), 0.9, fbm(simplex(1), 4))"
+//! );
+//! ```
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::matrix::Mat4;
+use crate::noise::{self, Feature, Noise as _, Simplex, Worley};
+use crate::object::{Footprint, HitRecord};
+use crate::perlin::Perlin;
+use crate::raster::{self, Raster, RasterError};
+use crate::texture::{self, Filter, Texture, Wrap};
+use crate::vec3::{Axis::*, Vec3};
+
+/// Error produced when a texture expression can't be parsed or loaded.
+#[derive(Debug)]
+pub enum GraphError {
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The expression is malformed.
+    Syntax {
+        /// Line number of the problem, starting from 1.
+        line: usize,
+        /// Description of the problem.
+        message: String,
+    },
+    /// An image used by the expression couldn't be loaded.
+    Image { path: PathBuf, error: RasterError },
+}
+
+impl std::fmt::Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GraphError::Io(e) => write!(f, "{}", e),
+            GraphError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            GraphError::Image { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GraphError::Io(e) => Some(e),
+            GraphError::Syntax { .. } => None,
+            GraphError::Image { error, .. } => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for GraphError {
+    fn from(e: io::Error) -> Self {
+        GraphError::Io(e)
+    }
+}
+
+/// The arithmetic operations of `Node::Math`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Minimum,
+    Maximum,
+    Power,
+}
+
+impl Op {
+    const ALL: [Op; 7] = [
+        Op::Add,
+        Op::Subtract,
+        Op::Multiply,
+        Op::Divide,
+        Op::Minimum,
+        Op::Maximum,
+        Op::Power,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Op::Add => "add",
+            Op::Subtract => "sub",
+            Op::Multiply => "mul",
+            Op::Divide => "div",
+            Op::Minimum => "min",
+            Op::Maximum => "max",
+            Op::Power => "pow",
+        }
+    }
+
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Op::Add => a + b,
+            Op::Subtract => a - b,
+            Op::Multiply => a * b,
+            Op::Divide => a / b,
+            Op::Minimum => a.min(b),
+            Op::Maximum => a.max(b),
+            Op::Power => a.powf(b),
+        }
+    }
+}
+
+/// The basic noise functions of `Node::Noise`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Basis {
+    Perlin,
+    Simplex,
+    Worley(Feature),
+}
+
+/// How copies of a `Basis` are summed for `Node::Noise`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fractal {
+    /// Just one copy.
+    Single,
+    /// `noise::Fbm` with this many octaves.
+    Fbm(usize),
+    /// `noise::Ridged` with this many octaves.
+    Ridged(usize),
+}
+
+/// A texture expression. See the module documentation for what each node does.
+#[derive(Clone)]
+pub enum Node {
+    Constant(Vec3),
+    Position,
+    Uv,
+    Image {
+        /// Where the image was loaded from.
+        path: PathBuf,
+        /// The path as written in the expression, relative to the expression's file, or as given
+        /// to `Node::image`. This is what's written out again, so the image is found on reloading.
+        source: PathBuf,
+        image: Arc<Raster>,
+        filter: Filter,
+        wrap: Wrap,
+        /// Whether the image was sRGB-encoded, and decoded on loading.
+        srgb: bool,
+    },
+    /// Noise, scaled to run from 0 to 1 if it doesn't already.
+    Noise {
+        basis: Basis,
+        fractal: Fractal,
+        seed: u64,
+    },
+    Math(Op, Box<Node>, Box<Node>),
+    Mix {
+        a: Box<Node>,
+        b: Box<Node>,
+        mask: Box<Node>,
+    },
+    Ramp {
+        input: Box<Node>,
+        /// Positions and colors of the gradient, in increasing order of position.
+        stops: Vec<(f64, Vec3)>,
+    },
+    /// `input`, evaluated at the object's coordinates transformed by `matrix`.
+    Transform {
+        matrix: Mat4,
+        input: Box<Node>,
+    },
+    UvTransform {
+        scale: (f64, f64),
+        offset: (f64, f64),
+        input: Box<Node>,
+    },
+    Warp {
+        input: Box<Node>,
+        by: Box<Node>,
+        strength: f64,
+    },
+    Triplanar {
+        input: Box<Node>,
+        sharpness: f64,
+    },
+}
+
+impl Node {
+    /// Loads `path` as an image node. Images in the `srgb` encoding are decoded to linear colors.
+    pub fn image(
+        path: impl AsRef<Path>,
+        filter: Filter,
+        wrap: Wrap,
+        srgb: bool,
+    ) -> Result<Node, GraphError> {
+        let path = path.as_ref();
+        let image = raster::load(path).map_err(|error| GraphError::Image {
+            path: path.to_owned(),
+            error,
+        })?;
+        Ok(Node::Image {
+            path: path.to_owned(),
+            source: path.to_owned(),
+            image: Arc::new(if srgb { image.decode_srgb() } else { image }),
+            filter,
+            wrap,
+            srgb,
+        })
+    }
+
+    /// Compiles the expression into a texture.
+    ///
+    /// Subexpressions that don't depend on the hit, such as arithmetic on constants, are worked
+    /// out once here rather than for every hit.
+    pub fn compile(&self) -> Texture {
+        if let Some(c) = self.constant_value() {
+            return texture::constant(c);
+        }
+        match self {
+            Node::Constant(c) => texture::constant(*c),
+            Node::Position => Arc::new(|hit| hit.local_p),
+            Node::Uv => Arc::new(|hit| Vec3(hit.uv.0, hit.uv.1, 0.)),
+            Node::Image {
+                image,
+                filter,
+                wrap,
+                ..
+            } => texture::image((**image).clone(), *filter, *wrap),
+            Node::Noise {
+                basis,
+                fractal,
+                seed,
+            } => {
+                let base: Arc<dyn noise::Noise> = match basis {
+                    Basis::Perlin => Arc::new(Perlin::new(*seed)),
+                    Basis::Simplex => Arc::new(Simplex::new(*seed)),
+                    Basis::Worley(feature) => Arc::new(Worley::new(*seed, *feature)),
+                };
+                let noise: Arc<dyn noise::Noise> = match *fractal {
+                    Fractal::Single => base,
+                    Fractal::Fbm(octaves) => Arc::new(noise::Fbm::new(base, octaves)),
+                    Fractal::Ridged(octaves) => Arc::new(noise::Ridged::new(base, octaves)),
+                };
+                // Worley and ridged noise already run from 0 to 1.
+                let signed =
+                    !matches!(basis, Basis::Worley(_)) && !matches!(fractal, Fractal::Ridged(_));
+                Arc::new(move |hit| {
+                    let value = noise.sample(hit.local_p);
+                    Vec3::from(if signed { 0.5 + 0.5 * value } else { value })
+                })
+            }
+            Node::Math(op, a, b) => {
+                let (op, a, b) = (*op, a.compile(), b.compile());
+                Arc::new(move |hit| a(hit).zip_with(b(hit), |a, b| op.apply(a, b)))
+            }
+            Node::Mix { a, b, mask } => {
+                let (a, b, mask) = (a.compile(), b.compile(), mask.compile());
+                Arc::new(move |hit| {
+                    let m = mask(hit);
+                    (Vec3::from(1.) - m) * a(hit) + m * b(hit)
+                })
+            }
+            Node::Ramp { input, stops } => {
+                let (input, stops) = (input.compile(), stops.clone());
+                Arc::new(move |hit| ramp(&stops, input(hit).reduce(std::ops::Add::add) / 3.))
+            }
+            Node::Transform { matrix, input } => {
+                let (matrix, input) = (*matrix, input.compile());
+                Arc::new(move |hit| {
+                    input(&HitRecord {
+                        local_p: matrix.transform_point(hit.local_p),
+                        ..hit.clone()
+                    })
+                })
+            }
+            Node::UvTransform {
+                scale,
+                offset,
+                input,
+            } => {
+                let (scale, offset, input) = (*scale, *offset, input.compile());
+                let stretch = move |(u, v): (f64, f64)| (u * scale.0, v * scale.1);
+                Arc::new(move |hit| {
+                    let (u, v) = stretch(hit.uv);
+                    input(&HitRecord {
+                        uv: (u + offset.0, v + offset.1),
+                        footprint: hit.footprint.map(|f| texture_footprint(f, stretch)),
+                        ..hit.clone()
+                    })
+                })
+            }
+            Node::Warp {
+                input,
+                by,
+                strength,
+            } => {
+                let (input, by, strength) = (input.compile(), by.compile(), *strength);
+                Arc::new(move |hit| {
+                    let displacement = 2. * by(hit) - Vec3::from(1.);
+                    input(&HitRecord {
+                        local_p: hit.local_p + strength * displacement,
+                        ..hit.clone()
+                    })
+                })
+            }
+            Node::Triplanar { input, sharpness } => {
+                let (input, sharpness) = (input.compile(), *sharpness);
+                Arc::new(move |hit| {
+                    // Project along each axis in turn, weighting each projection by how squarely
+                    // the surface faces along that axis.
+                    let weights = hit.normal.map(|n| n.abs().powf(sharpness));
+                    let total = weights.reduce(std::ops::Add::add);
+                    let p = hit.local_p;
+                    [(X, Y, Z), (Y, X, Z), (Z, X, Y)]
+                        .iter()
+                        .filter(|(axis, _, _)| weights[*axis] > 0.)
+                        .map(|&(axis, u, v)| {
+                            let project = |d: Vec3| (d[u], d[v]);
+                            let color = input(&HitRecord {
+                                uv: project(p),
+                                footprint: hit.footprint.map(|f| Footprint {
+                                    duvdx: project(f.dpdx),
+                                    duvdy: project(f.dpdy),
+                                    ..f
+                                }),
+                                ..hit.clone()
+                            });
+                            weights[axis] / total * color
+                        })
+                        .fold(Vec3::default(), |a, b| a + b)
+                })
+            }
+        }
+    }
+
+    /// The value of the expression if it's the same everywhere.
+    fn constant_value(&self) -> Option<Vec3> {
+        match self {
+            Node::Constant(c) => Some(*c),
+            Node::Math(op, a, b) => {
+                let (a, b) = (a.constant_value()?, b.constant_value()?);
+                Some(a.zip_with(b, |a, b| op.apply(a, b)))
+            }
+            Node::Mix { a, b, mask } => {
+                let (a, b, m) = (
+                    a.constant_value()?,
+                    b.constant_value()?,
+                    mask.constant_value()?,
+                );
+                Some((Vec3::from(1.) - m) * a + m * b)
+            }
+            Node::Ramp { input, stops } => Some(ramp(
+                stops,
+                input.constant_value()?.reduce(std::ops::Add::add) / 3.,
+            )),
+            Node::Transform { input, .. }
+            | Node::UvTransform { input, .. }
+            | Node::Warp { input, .. }
+            | Node::Triplanar { input, .. } => input.constant_value(),
+            _ => None,
+        }
+    }
+}
+
+/// Scales the changes in surface coordinates of `footprint` by `stretch`.
+fn texture_footprint(
+    footprint: Footprint,
+    stretch: impl Fn((f64, f64)) -> (f64, f64),
+) -> Footprint {
+    Footprint {
+        duvdx: stretch(footprint.duvdx),
+        duvdy: stretch(footprint.duvdy),
+        ..footprint
+    }
+}
+
+/// Looks up `t` in the gradient described by `stops`.
+fn ramp(stops: &[(f64, Vec3)], t: f64) -> Vec3 {
+    match stops.iter().position(|&(position, _)| position > t) {
+        None => stops.last().map_or(Vec3::default(), |s| s.1),
+        Some(0) => stops[0].1,
+        Some(i) => {
+            let ((p0, c0), (p1, c1)) = (stops[i - 1], stops[i]);
+            let f = (t - p0) / (p1 - p0);
+            (1. - f) * c0 + f * c1
+        }
+    }
+}
+
+impl std::fmt::Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn color(c: Vec3) -> String {
+            if c.0 == c.1 && c.1 == c.2 {
+                format!("{}", c.0)
+            } else {
+                format!("rgb({}, {}, {})", c.0, c.1, c.2)
+            }
+        }
+
+        match self {
+            Node::Constant(c) => write!(f, "{}", color(*c)),
+            Node::Position => write!(f, "position"),
+            Node::Uv => write!(f, "uv"),
+            Node::Image {
+                source,
+                filter,
+                wrap,
+                srgb,
+                ..
+            } => write!(
+                f,
+                "image({}, {}, {}, {})",
+                quote(&source.to_string_lossy()),
+                FILTERS.iter().find(|x| x.1 == *filter).unwrap().0,
+                WRAPS.iter().find(|x| x.1 == *wrap).unwrap().0,
+                if *srgb { "srgb" } else { "linear" },
+            ),
+            Node::Noise {
+                basis,
+                fractal,
+                seed,
+            } => {
+                let basis = match basis {
+                    Basis::Perlin => format!("perlin({})", seed),
+                    Basis::Simplex => format!("simplex({})", seed),
+                    Basis::Worley(feature) => format!(
+                        "worley({}, {})",
+                        seed,
+                        FEATURES.iter().find(|x| x.1 == *feature).unwrap().0
+                    ),
+                };
+                match fractal {
+                    Fractal::Single => write!(f, "{}", basis),
+                    Fractal::Fbm(octaves) => write!(f, "fbm({}, {})", basis, octaves),
+                    Fractal::Ridged(octaves) => write!(f, "ridged({}, {})", basis, octaves),
+                }
+            }
+            Node::Math(op, a, b) => write!(f, "{}({}, {})", op.name(), a, b),
+            Node::Mix { a, b, mask } => write!(f, "mix({}, {}, {})", a, b, mask),
+            Node::Ramp { input, stops } => {
+                write!(f, "ramp({}", input)?;
+                for (position, c) in stops {
+                    write!(f, ", {}, {}", position, color(*c))?;
+                }
+                write!(f, ")")
+            }
+            Node::Transform { matrix, input } => {
+                let m = matrix.0;
+                let diagonal = Vec3(m[0][0], m[1][1], m[2][2]);
+                let offset = Vec3(m[0][3], m[1][3], m[2][3]);
+                if *matrix == Mat4::scaling(diagonal) {
+                    let Vec3(x, y, z) = diagonal;
+                    return write!(f, "scale({}, {}, {}, {})", x, y, z, input);
+                }
+                if *matrix == Mat4::translation(offset) {
+                    let Vec3(x, y, z) = offset;
+                    return write!(f, "translate({}, {}, {}, {})", x, y, z, input);
+                }
+                write!(f, "matrix(")?;
+                for row in &matrix.0 {
+                    for x in row {
+                        write!(f, "{}, ", x)?;
+                    }
+                }
+                write!(f, "{})", input)
+            }
+            Node::UvTransform {
+                scale,
+                offset,
+                input,
+            } => write!(
+                f,
+                "uv_transform({}, {}, {}, {}, {})",
+                scale.0, scale.1, offset.0, offset.1, input
+            ),
+            Node::Warp {
+                input,
+                by,
+                strength,
+            } => write!(f, "warp({}, {}, {})", input, by, strength),
+            Node::Triplanar { input, sharpness } => {
+                write!(f, "triplanar({}, {})", input, sharpness)
+            }
+        }
+    }
+}
+
+/// Writes `s` as a string literal that the parser reads back as `s`.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl std::fmt::Debug for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Node({})", self)
+    }
+}
+
+const FILTERS: [(&str, Filter); 3] = [
+    ("nearest", Filter::Nearest),
+    ("bilinear", Filter::Bilinear),
+    ("bicubic", Filter::Bicubic),
+];
+
+const WRAPS: [(&str, Wrap); 3] = [
+    ("repeat", Wrap::Repeat),
+    ("clamp", Wrap::Clamp),
+    ("mirror", Wrap::Mirror),
+];
+
+const FEATURES: [(&str, Feature); 3] = [
+    ("f1", Feature::F1),
+    ("f2", Feature::F2),
+    ("f2_minus_f1", Feature::F2MinusF1),
+];
+
+impl FromStr for Node {
+    type Err = GraphError;
+
+    /// Parses a texture expression. Image paths are relative to the current directory.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s, Path::new(""))
+    }
+}
+
+/// Loads the texture expression in the file at `path`. Image paths in it are relative to the
+/// file's directory.
+pub fn load(path: impl AsRef<Path>) -> Result<Node, GraphError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)?;
+    parse(&text, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Parses the texture expression `text`, with image paths relative to `base`.
+pub fn parse(text: &str, base: &Path) -> Result<Node, GraphError> {
+    let mut parser = Parser {
+        text,
+        pos: 0,
+        line: 1,
+    };
+    let expr = parser.expr()?;
+    parser.skip_space();
+    if parser.pos < text.len() {
+        return parser.error("unexpected text after expression");
+    }
+    expr.into_node(base)
+}
+
+/// An expression as written, before its functions have been checked.
+enum Expr {
+    /// A number, and the line it's on.
+    Number(f64, usize),
+    /// A string, and the line it starts on.
+    String(String, usize),
+    /// A name, with arguments if it was followed by parentheses.
+    Call {
+        name: String,
+        args: Option<Vec<Expr>>,
+        line: usize,
+    },
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, GraphError> {
+        Err(GraphError::Syntax {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while !matches!(self.peek(), None | Some('\n')) {
+                    self.bump();
+                }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), GraphError> {
+        self.skip_space();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("expected {:?}, found {:?}", expected, c)),
+            None => self.error(format!("expected {:?}, found end of input", expected)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, GraphError> {
+        self.skip_space();
+        let start = self.pos;
+        let line = self.line;
+        match self.peek() {
+            Some('"') => {
+                self.bump();
+                let mut s = String::new();
+                loop {
+                    match self.bump() {
+                        Some('"') => return Ok(Expr::String(s, line)),
+                        Some('\\') => match self.bump() {
+                            Some(c) => s.push(c),
+                            None => return self.error("unterminated string"),
+                        },
+                        Some(c) => s.push(c),
+                        None => return self.error("unterminated string"),
+                    }
+                }
+            }
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "+-.".contains(c))
+                {
+                    self.bump();
+                }
+                let word = &self.text[start..self.pos];
+                match word.parse() {
+                    Ok(x) => Ok(Expr::Number(x, line)),
+                    Err(_) => self.error(format!("invalid number {:?}", word)),
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_') {
+                    self.bump();
+                }
+                let name = self.text[start..self.pos].to_owned();
+                // Infinity and NaN, as `Display` writes them. (`-inf` is read as a number.)
+                if name == "inf" || name == "NaN" {
+                    return Ok(Expr::Number(name.parse().unwrap(), line));
+                }
+                self.skip_space();
+                let args = if self.peek() == Some('(') {
+                    self.bump();
+                    let mut args = vec![self.expr()?];
+                    self.skip_space();
+                    while self.peek() == Some(',') {
+                        self.bump();
+                        args.push(self.expr()?);
+                        self.skip_space();
+                    }
+                    self.expect(')')?;
+                    Some(args)
+                } else {
+                    None
+                };
+                Ok(Expr::Call { name, args, line })
+            }
+            Some(c) => self.error(format!("unexpected {:?}", c)),
+            None => self.error("unexpected end of input"),
+        }
+    }
+}
+
+impl Expr {
+    fn line(&self) -> usize {
+        match self {
+            Expr::Number(_, line) | Expr::String(_, line) | Expr::Call { line, .. } => *line,
+        }
+    }
+
+    fn into_node(self, base: &Path) -> Result<Node, GraphError> {
+        let (name, args, line) = match self {
+            Expr::Number(x, _) => return Ok(Node::Constant(Vec3::from(x))),
+            Expr::String(_, line) => {
+                return syntax_error(line, "expected a texture, found a string")
+            }
+            Expr::Call { name, args, line } => (name, args, line),
+        };
+        let args = match args {
+            None => {
+                return match name.as_str() {
+                    "position" => Ok(Node::Position),
+                    "uv" => Ok(Node::Uv),
+                    _ => syntax_error(line, format!("unknown texture {:?}", name)),
+                }
+            }
+            Some(args) => args,
+        };
+
+        let arity = args.len();
+        let count = |n: usize| {
+            if arity == n {
+                Ok(())
+            } else {
+                syntax_error(
+                    line,
+                    format!("{} takes {} arguments, not {}", name, n, arity),
+                )
+            }
+        };
+        let mut args = args.into_iter();
+        let node = |args: &mut std::vec::IntoIter<Expr>| -> Result<Box<Node>, GraphError> {
+            Ok(Box::new(args.next().unwrap().into_node(base)?))
+        };
+
+        if let Some(&op) = Op::ALL.iter().find(|op| op.name() == name) {
+            count(2)?;
+            return Ok(Node::Math(op, node(&mut args)?, node(&mut args)?));
+        }
+        match name.as_str() {
+            "rgb" => {
+                count(3)?;
+                let [r, g, b] = numbers::<3>(args)?;
+                Ok(Node::Constant(Vec3(r, g, b)))
+            }
+            "image" => {
+                count(4)?;
+                let source = match args.next().unwrap() {
+                    Expr::String(s, _) => PathBuf::from(s),
+                    _ => return syntax_error(line, "image path must be a string"),
+                };
+                let filter = keyword(args.next().unwrap(), &FILTERS)?;
+                let wrap = keyword(args.next().unwrap(), &WRAPS)?;
+                let srgb = keyword(args.next().unwrap(), &[("srgb", true), ("linear", false)])?;
+                let mut image = Node::image(base.join(&source), filter, wrap, srgb)?;
+                if let Node::Image { source: s, .. } = &mut image {
+                    *s = source;
+                }
+                Ok(image)
+            }
+            "perlin" | "simplex" => {
+                count(1)?;
+                let [seed] = numbers::<1>(args)?;
+                let seed = whole_number(seed, MAX_SEED, "seed", line)?;
+                let basis = if name == "perlin" {
+                    Basis::Perlin
+                } else {
+                    Basis::Simplex
+                };
+                Ok(Node::Noise {
+                    basis,
+                    fractal: Fractal::Single,
+                    seed,
+                })
+            }
+            "worley" => {
+                count(2)?;
+                let [seed] = numbers::<1>(args.next())?;
+                let seed = whole_number(seed, MAX_SEED, "seed", line)?;
+                let feature = keyword(args.next().unwrap(), &FEATURES)?;
+                Ok(Node::Noise {
+                    basis: Basis::Worley(feature),
+                    fractal: Fractal::Single,
+                    seed,
+                })
+            }
+            "fbm" | "ridged" => {
+                count(2)?;
+                let (basis, seed) = match *node(&mut args)? {
+                    Node::Noise {
+                        basis,
+                        fractal: Fractal::Single,
+                        seed,
+                    } => (basis, seed),
+                    _ => return syntax_error(line, format!("{} needs a basic noise", name)),
+                };
+                let [octaves] = numbers::<1>(args)?;
+                let octaves = whole_number(octaves, MAX_OCTAVES, "octaves", line)? as usize;
+                let fractal = if name == "fbm" {
+                    Fractal::Fbm(octaves)
+                } else {
+                    Fractal::Ridged(octaves)
+                };
+                Ok(Node::Noise {
+                    basis,
+                    fractal,
+                    seed,
+                })
+            }
+            "mix" => {
+                count(3)?;
+                Ok(Node::Mix {
+                    a: node(&mut args)?,
+                    b: node(&mut args)?,
+                    mask: node(&mut args)?,
+                })
+            }
+            "ramp" => {
+                if arity < 3 || arity % 2 == 0 {
+                    return syntax_error(line, "ramp takes an input and pairs of stops");
+                }
+                let input = node(&mut args)?;
+                let mut stops = vec![];
+                while let Some(position) = args.next() {
+                    let [position] = numbers::<1>(Some(position))?;
+                    let color = match args.next().unwrap().into_node(base)? {
+                        Node::Constant(c) => c,
+                        _ => return syntax_error(line, "ramp colors must be constant"),
+                    };
+                    stops.push((position, color));
+                }
+                if stops.windows(2).any(|w| w[0].0 > w[1].0) {
+                    return syntax_error(line, "ramp positions must be in increasing order");
+                }
+                Ok(Node::Ramp { input, stops })
+            }
+            "scale" | "translate" => {
+                count(4)?;
+                let [x, y, z] = numbers::<3>(args.by_ref().take(3))?;
+                let matrix = if name == "scale" {
+                    Mat4::scaling(Vec3(x, y, z))
+                } else {
+                    Mat4::translation(Vec3(x, y, z))
+                };
+                Ok(Node::Transform {
+                    matrix,
+                    input: node(&mut args)?,
+                })
+            }
+            "rotate" => {
+                count(5)?;
+                let [x, y, z, degrees] = numbers::<4>(args.by_ref().take(4))?;
+                Ok(Node::Transform {
+                    matrix: Mat4::rotation(Vec3(x, y, z), degrees),
+                    input: node(&mut args)?,
+                })
+            }
+            "matrix" => {
+                count(17)?;
+                let m = numbers::<16>(args.by_ref().take(16))?;
+                let mut rows = [[0.; 4]; 4];
+                for (i, x) in m.iter().enumerate() {
+                    rows[i / 4][i % 4] = *x;
+                }
+                Ok(Node::Transform {
+                    matrix: Mat4(rows),
+                    input: node(&mut args)?,
+                })
+            }
+            "uv_transform" => {
+                count(5)?;
+                let [su, sv, ou, ov] = numbers::<4>(args.by_ref().take(4))?;
+                Ok(Node::UvTransform {
+                    scale: (su, sv),
+                    offset: (ou, ov),
+                    input: node(&mut args)?,
+                })
+            }
+            "warp" => {
+                count(3)?;
+                let (input, by) = (node(&mut args)?, node(&mut args)?);
+                let [strength] = numbers::<1>(args)?;
+                Ok(Node::Warp {
+                    input,
+                    by,
+                    strength,
+                })
+            }
+            "triplanar" => {
+                count(2)?;
+                let input = node(&mut args)?;
+                let [sharpness] = numbers::<1>(args)?;
+                Ok(Node::Triplanar { input, sharpness })
+            }
+            _ => syntax_error(line, format!("unknown texture {:?}", name)),
+        }
+    }
+}
+
+fn syntax_error<T>(line: usize, message: impl Into<String>) -> Result<T, GraphError> {
+    Err(GraphError::Syntax {
+        line,
+        message: message.into(),
+    })
+}
+
+/// The largest seed the text form accepts: past this, not every whole number can be written.
+const MAX_SEED: u64 = 1 << 53;
+
+/// The most octaves the text form accepts. Later octaves would be far too faint and too fine to
+/// show, and a huge count would hang the render.
+const MAX_OCTAVES: u64 = 32;
+
+/// Checks that `x`, the argument `what`, is a whole number from 0 to `max`.
+fn whole_number(x: f64, max: u64, what: &str, line: usize) -> Result<u64, GraphError> {
+    if x.fract() == 0. && (0. ..=max as f64).contains(&x) {
+        Ok(x as u64)
+    } else {
+        syntax_error(
+            line,
+            format!(
+                "{} must be a whole number from 0 to {}, not {}",
+                what, max, x
+            ),
+        )
+    }
+}
+
+/// Takes `N` numbers from `args`.
+fn numbers<const N: usize>(args: impl IntoIterator<Item = Expr>) -> Result<[f64; N], GraphError> {
+    let mut numbers = [0.; N];
+    for (n, arg) in numbers.iter_mut().zip(args) {
+        *n = match arg {
+            Expr::Number(x, _) => x,
+            _ => return syntax_error(arg.line(), "expected a number"),
+        };
+    }
+    Ok(numbers)
+}
+
+/// Looks up the keyword `arg` in `options`.
+fn keyword<T: Copy>(arg: Expr, options: &[(&str, T)]) -> Result<T, GraphError> {
+    if let Expr::Call {
+        name, args: None, ..
+    } = &arg
+    {
+        if let Some((_, value)) = options.iter().find(|(n, _)| n == name) {
+            return Ok(*value);
+        }
+    }
+    let names: Vec<_> = options.iter().map(|(n, _)| *n).collect();
+    syntax_error(arg.line(), format!("expected one of {}", names.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `text`, which must succeed.
+    fn parse_ok(text: &str) -> Node {
+        match text.parse() {
+            Ok(node) => node,
+            Err(e) => panic!("{:?} failed to parse: {}", text, e),
+        }
+    }
+
+    /// Parses `text`, which must fail with a syntax error mentioning `message`.
+    fn parse_err(text: &str, message: &str) {
+        match text.parse::<Node>() {
+            Err(GraphError::Syntax { message: m, .. }) if m.contains(message) => {}
+            Err(e) => panic!(
+                "{:?}: expected an error about {:?}, got {}",
+                text, message, e
+            ),
+            Ok(node) => panic!("{:?}: expected an error, got {}", text, node),
+        }
+    }
+
+    /// A fresh, empty directory for a test's files.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("texture_graph_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn round_trip() {
+        for text in [
+            "0.5",
+            "rgb(0.1, 0.2, 0.3)",
+            "position",
+            "uv",
+            "fbm(perlin(1), 5)",
+            "ridged(worley(9007199254740992, f2_minus_f1), 3)",
+            "mix(simplex(2), rgb(1, 0, 0), worley(3, f1))",
+            "ramp(perlin(4), 0.25, 0, 0.75, rgb(1, 0.5, 0))",
+            "scale(1, 2, 3, translate(-1, 0, 2.5, rotate(0, 1, 0, 30, position)))",
+            "uv_transform(2, 2, 0.5, 0, warp(uv, simplex(5), 0.3))",
+            "triplanar(pow(add(perlin(6), 0.1), 2), 4)",
+        ] {
+            let node = parse_ok(text);
+            let written = node.to_string();
+            assert_eq!(parse_ok(&written).to_string(), written, "from {:?}", text);
+        }
+    }
+
+    #[test]
+    fn quoted_strings() {
+        for s in [
+            "plain.png",
+            "a \"quoted\" name",
+            "C:\\textures\\",
+            "two\nlines",
+        ] {
+            let mut parser = Parser {
+                text: &quote(s),
+                pos: 0,
+                line: 1,
+            };
+            match parser.expr() {
+                Ok(Expr::String(parsed, _)) => assert_eq!(parsed, s),
+                _ => panic!("{:?} didn't parse back", quote(s)),
+            }
+        }
+    }
+
+    #[test]
+    fn file_relative_image() {
+        let dir = scratch_dir("file_relative_image");
+        fs::create_dir(dir.join("images")).unwrap();
+        fs::write(dir.join("images/red.ppm"), "P3 1 1 255 255 0 0").unwrap();
+        let expression = "image(\"images/red.ppm\", nearest, repeat, linear)";
+        fs::write(dir.join("red.tex"), expression).unwrap();
+
+        let node = load(dir.join("red.tex")).unwrap();
+        match &node {
+            Node::Image { path, source, .. } => {
+                assert_eq!(path, &dir.join("images/red.ppm"));
+                assert_eq!(source, Path::new("images/red.ppm"));
+            }
+            _ => panic!("expected an image, got {}", node),
+        }
+
+        // Written out and loaded again from the same place, the path still finds the image.
+        assert_eq!(node.to_string(), expression);
+        fs::write(dir.join("again.tex"), node.to_string()).unwrap();
+        assert_eq!(load(dir.join("again.tex")).unwrap().to_string(), expression);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_image() {
+        let dir = scratch_dir("missing_image");
+        fs::write(
+            dir.join("missing.tex"),
+            "image(\"nope.png\", nearest, clamp, srgb)",
+        )
+        .unwrap();
+        match load(dir.join("missing.tex")) {
+            Err(GraphError::Image { path, .. }) => assert_eq!(path, dir.join("nope.png")),
+            other => panic!(
+                "expected an image error, got {:?}",
+                other.map(|n| n.to_string())
+            ),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        parse_err("perlin(1.5)", "seed must be a whole number");
+        parse_err("simplex(-1)", "seed must be a whole number");
+        parse_err("worley(1e300, f1)", "seed must be a whole number");
+        parse_err("perlin(nan)", "expected a number");
+        parse_err("perlin(+inf)", "seed must be a whole number");
+        parse_err("fbm(perlin(1), 1e18)", "octaves must be a whole number");
+        parse_err("ridged(simplex(1), 2.5)", "octaves must be a whole number");
+        parse_err("fbm(perlin(1), -3)", "octaves must be a whole number");
+        parse_err("fbm(fbm(perlin(1), 2), 2)", "fbm needs a basic noise");
+        parse_err("rgb(1, 2)", "rgb takes 3 arguments, not 2");
+        parse_err(
+            "image(uv, nearest, repeat, srgb)",
+            "image path must be a string",
+        );
+        parse_err(
+            "image(\"a.png\", blurry, repeat, srgb)",
+            "expected one of nearest",
+        );
+        parse_err("ramp(uv, 0.5, rgb(1, 0, 0), 0.25, 0)", "increasing order");
+        parse_err("ramp(uv, 0.5, perlin(1))", "ramp colors must be constant");
+        parse_err("add(1, 2) 3", "unexpected text after expression");
+        parse_err("image(\"a.png", "unterminated string");
+        parse_err("mix(1, 2,", "unexpected end of input");
+        parse_err("checker(1)", "unknown texture");
+        parse_err("1.2.3", "invalid number");
+    }
+
+    #[test]
+    fn error_lines() {
+        for (text, expected) in [
+            ("\n\nperlin(0.5)", 3),
+            ("mix(1,\n\"oops\", 2)", 2),
+            ("\"oops\"", 1),
+            ("rgb(1,\n2,\nuv)", 3),
+            ("image(\"a.png\",\n\n blurry, repeat, srgb)", 3),
+        ] {
+            match text.parse::<Node>() {
+                Err(GraphError::Syntax { line, .. }) => assert_eq!(line, expected, "{:?}", text),
+                other => panic!("{:?}: expected a syntax error, got {:?}", text, other),
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_values() {
+        let nodes = [
+            Node::Constant(Vec3(f64::INFINITY, f64::NAN, f64::NEG_INFINITY)),
+            Node::Constant(Vec3::from(f64::NAN)),
+            Node::Constant(Vec3(f64::MAX, f64::MIN_POSITIVE, 5e-324)),
+            Node::Warp {
+                input: Box::new(Node::Position),
+                by: Box::new(Node::Uv),
+                strength: f64::INFINITY,
+            },
+            Node::Transform {
+                matrix: Mat4::scaling(Vec3(f64::NAN, 1e300, -1e-300)),
+                input: Box::new(Node::Uv),
+            },
+        ];
+        for node in nodes {
+            let written = node.to_string();
+            assert_eq!(parse_ok(&written).to_string(), written);
+        }
+        assert_eq!(parse_ok("inf").to_string(), "inf");
+        assert_eq!(
+            parse_ok("rgb(-inf, NaN, 1e308)").to_string(),
+            format!("rgb(-inf, NaN, {})", 1e308)
+        );
+    }
+}
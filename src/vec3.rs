@@ -1,4 +1,4 @@
-//!
+//! Three-component vectors, used for points, directions and colors.
 
 use rand::prelude::*;
 
@@ -27,6 +27,19 @@ impl Vec3 {
         }
     }
 
+    /// Generates a random unit vector, evenly distributed over all directions.
+    #[inline]
+    pub fn on_unit_sphere(rng: &mut impl Rng) -> Self {
+        loop {
+            let v = Vec3::in_unit_sphere(rng);
+            let length = v.length();
+            // Very short vectors lose precision when scaled up.
+            if length > 1e-6 {
+                return v / length;
+            }
+        }
+    }
+
     /// Generates a random `Vec3` inside a disc with unit radius in the XY plane. The length of the
     /// result is between 0 and 1, and the Z component is 0.
     #[inline]
@@ -0,0 +1,118 @@
+//! Lights placed by a wrapper object must still be found by sampling them directly. Rendering the
+//! same scene with and without the lights in the list of lights to sample must give the same
+//! average brightness: aiming shadow rays at the lights only reduces the noise, so any difference
+//! means a wrapper reports the wrong density for the directions it chooses.
+
+use std::sync::Arc;
+
+use rand::prelude::*;
+
+use ray_tracing::instance::Instance;
+use ray_tracing::material::Material;
+use ray_tracing::matrix::Mat4;
+use ray_tracing::object::{self, Object};
+use ray_tracing::ray::Ray;
+use ray_tracing::texture;
+use ray_tracing::vec3::Vec3;
+use ray_tracing::{color, RenderSettings, World};
+
+fn light() -> Material {
+    Material::DiffuseLight {
+        emission: texture::constant(Vec3::from(1.)),
+        brightness: 4.,
+    }
+}
+
+/// The average brightness of a gray sphere lit by `light`, over its whole disk as seen from the
+/// front, along with the standard error of that average. The lights of the world are sampled
+/// directly only if `sample_lights` is set.
+fn lit_sphere(light: Box<dyn Object>, sample_lights: bool) -> (f64, f64) {
+    const SAMPLES: usize = 200_000;
+
+    let objects: Vec<Box<dyn Object>> = vec![
+        Box::new(object::Sphere {
+            radius: 1.,
+            material: Material::Lambertian {
+                albedo: texture::constant(Vec3::from(0.5)),
+            },
+        }),
+        light,
+    ];
+    let world = &objects[..];
+    let lights = if sample_lights {
+        world.lights()
+    } else {
+        vec![]
+    };
+    assert_eq!(world.lights().len(), 1, "the light wasn't found");
+    let settings = RenderSettings::default();
+
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(0xDEADBEEF);
+    let (mut sum, mut sum_squares) = (0., 0.);
+    for _ in 0..SAMPLES {
+        let target = Vec3::in_unit_disc(&mut rng);
+        let ray = Ray {
+            origin: Vec3(target.0, target.1, -5.),
+            direction: Vec3(0., 0., 1.),
+            time: 0.,
+            differentials: None,
+        };
+        let brightness = color(&world, &lights, &settings, ray, &mut rng).1;
+        sum += brightness;
+        sum_squares += brightness * brightness;
+    }
+    let mean = sum / SAMPLES as f64;
+    let variance = sum_squares / SAMPLES as f64 - mean * mean;
+    (mean, (variance / SAMPLES as f64).sqrt())
+}
+
+fn assert_sampling_agrees(light: impl Fn() -> Box<dyn Object>) {
+    let (direct, direct_error) = lit_sphere(light(), true);
+    let (bounced, bounced_error) = lit_sphere(light(), false);
+    let error = (direct_error * direct_error + bounced_error * bounced_error).sqrt();
+    assert!(
+        (direct - bounced).abs() < 5. * error,
+        "{} ± {} with the light sampled, {} ± {} without",
+        direct,
+        direct_error,
+        bounced,
+        bounced_error
+    );
+    assert!(
+        direct_error < bounced_error,
+        "sampling the light made the estimate noisier"
+    );
+}
+
+#[test]
+fn transformed_light() {
+    // Stretched, sheared and moved off to the upper left, so that the density of directions
+    // toward the light changes across it.
+    let matrix = Mat4::translation(Vec3(-1., 2.5, -1.5))
+        * Mat4::shearing(0.5, 0., 0., 0.3, 0., 0.)
+        * Mat4::scaling(Vec3(2., 0.5, 1.));
+    assert_sampling_agrees(|| {
+        Box::new(object::transform(
+            matrix,
+            object::Sphere {
+                radius: 0.5,
+                material: light(),
+            },
+        ))
+    });
+}
+
+#[test]
+fn instanced_light() {
+    // The prototype isn't a light; the instance's replacement material makes it one.
+    let prototype: Arc<dyn Object> = Arc::new(object::Sphere {
+        radius: 0.5,
+        material: Material::Lambertian {
+            albedo: texture::constant(Vec3::from(0.5)),
+        },
+    });
+    let matrix = Mat4::translation(Vec3(1., 2., -1.)) * Mat4::scaling(Vec3(1.5, 0.5, 0.8));
+    assert_sampling_agrees(|| {
+        Box::new(Instance::new(prototype.clone(), matrix).with_material(light()))
+    });
+}
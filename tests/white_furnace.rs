@@ -0,0 +1,81 @@
+//! The white furnace test: a perfectly white object under perfectly even light of brightness 1
+//! should disappear, since it reflects all the light it receives, and so looks as bright as its
+//! surroundings from every angle. Any other brightness means the material loses or makes light.
+
+use rand::prelude::*;
+
+use ray_tracing::environment::Environment;
+use ray_tracing::material::Material;
+use ray_tracing::object::{self, Object};
+use ray_tracing::ray::Ray;
+use ray_tracing::vec3::Vec3;
+use ray_tracing::{color, RenderSettings, WithEnvironment, World};
+
+/// The average brightness of a white sphere of `material`, seen over its whole disk, including
+/// at grazing angles near its edge, along with the standard error of that average.
+fn furnace(material: Material) -> (f64, f64) {
+    const SAMPLES: usize = 100_000;
+
+    let objects: Vec<Box<dyn Object>> = vec![Box::new(object::Sphere {
+        radius: 1.,
+        material,
+    })];
+    let world = WithEnvironment {
+        world: &objects[..],
+        environment: Some(Environment::Constant(Vec3::from(1.))),
+    };
+    let lights = world.lights();
+    let settings = RenderSettings::default();
+
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(0xDEADBEEF);
+    let (mut sum, mut sum_squares) = (0., 0.);
+    for _ in 0..SAMPLES {
+        let target = Vec3::in_unit_disc(&mut rng);
+        let ray = Ray {
+            origin: Vec3(target.0, target.1, -5.),
+            direction: Vec3(0., 0., 1.),
+            time: 0.,
+            differentials: None,
+        };
+        let brightness = color(&world, &lights, &settings, ray, &mut rng).1;
+        sum += brightness;
+        sum_squares += brightness * brightness;
+    }
+    let mean = sum / SAMPLES as f64;
+    let variance = sum_squares / SAMPLES as f64 - mean * mean;
+    (mean, (variance / SAMPLES as f64).sqrt())
+}
+
+fn assert_conserves_energy(material: Material) {
+    let (mean, error) = furnace(material);
+    assert!(
+        (mean - 1.).abs() < 5. * error + 1e-9,
+        "average brightness {} ± {}, expected 1",
+        mean,
+        error
+    );
+}
+
+#[test]
+fn polished_metal() {
+    assert_conserves_energy(Material::Metal {
+        albedo: Vec3::from(1.),
+        fuzz: 0.,
+    });
+}
+
+#[test]
+fn brushed_metal() {
+    assert_conserves_energy(Material::Metal {
+        albedo: Vec3::from(1.),
+        fuzz: 0.3,
+    });
+}
+
+#[test]
+fn frosted_metal() {
+    assert_conserves_energy(Material::Metal {
+        albedo: Vec3::from(1.),
+        fuzz: 1.,
+    });
+}